@@ -0,0 +1,204 @@
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+use crate::models::ArchiveFormat;
+use crate::ImapAttachmentDaemonError;
+
+// Limits on the content of archive attachments, guarding against archive bombs. The total size and the entry count
+// include the entries of nested archives.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ArchiveLimits {
+    pub entries: usize,
+    pub total_size: u64,
+    pub compression_ratio: u64,
+    // How deep archives can be nested, 1 to ignore archives within archives
+    pub depth: usize,
+}
+
+// A file extracted from an archive, with its path in the archive, prefixed by the paths of the nested archives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExtractedFile {
+    pub path: String,
+    pub content: Vec<u8>,
+}
+
+// The format of an archive from its filename, when it is one of the formats to unpack. Comic book archives such as
+// `.cbz` files are not archive attachments.
+pub(crate) fn archive_format(filename: &str, formats: &BTreeSet<ArchiveFormat>) -> Option<ArchiveFormat> {
+    let filename = filename.to_lowercase();
+    let path = Path::new(&filename);
+    let format = match path.extension().and_then(OsStr::to_str)? {
+        "zip" => ArchiveFormat::Zip,
+        "tar" => ArchiveFormat::Tar,
+        "tgz" => ArchiveFormat::TarGz,
+        "gz" if path
+            .file_stem()
+            .is_some_and(|stem| Path::new(stem).extension() == Some(OsStr::new("tar"))) =>
+        {
+            ArchiveFormat::TarGz
+        }
+        _ => return None,
+    };
+    formats.contains(&format).then_some(format)
+}
+
+// Extracts the accepted files of an archive and of the archives nested in it, up to the limits. Other entries are
+// skipped without being read.
+pub(crate) fn extract_archive(
+    content: &[u8],
+    format: ArchiveFormat,
+    formats: &BTreeSet<ArchiveFormat>,
+    is_accepted: &dyn Fn(&str) -> bool,
+    limits: ArchiveLimits,
+) -> Result<Vec<ExtractedFile>, ImapAttachmentDaemonError> {
+    let mut extraction = Extraction {
+        formats,
+        is_accepted,
+        limits,
+        entries: 0,
+        total_size: 0,
+        files: Vec::new(),
+    };
+    extraction.extract(content, format, "", 1)?;
+    Ok(extraction.files)
+}
+
+struct Extraction<'a> {
+    formats: &'a BTreeSet<ArchiveFormat>,
+    is_accepted: &'a dyn Fn(&str) -> bool,
+    limits: ArchiveLimits,
+    entries: usize,
+    total_size: u64,
+    files: Vec<ExtractedFile>,
+}
+
+impl Extraction<'_> {
+    fn extract(
+        &mut self,
+        content: &[u8],
+        format: ArchiveFormat,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ImapAttachmentDaemonError> {
+        match format {
+            ArchiveFormat::Zip => self.extract_zip(content, prefix, depth),
+            ArchiveFormat::Tar => self.extract_tar(Cursor::new(content), prefix, depth),
+            ArchiveFormat::TarGz => {
+                // The whole stream is decompressed, even the skipped entries, so its size is bounded by the ratio
+                let exceeded = Cell::new(false);
+                let reader = LimitedReader {
+                    inner: GzDecoder::new(content),
+                    remaining: u64::try_from(content.len())
+                        .unwrap_or(u64::MAX)
+                        .saturating_mul(self.limits.compression_ratio),
+                    exceeded: &exceeded,
+                };
+                match self.extract_tar(reader, prefix, depth) {
+                    Err(_) if exceeded.get() => {
+                        Err(ImapAttachmentDaemonError::ArchiveLimitExceeded("compression ratio"))
+                    }
+                    result => result,
+                }
+            }
+        }
+    }
+
+    fn extract_zip(&mut self, content: &[u8], prefix: &str, depth: usize) -> Result<(), ImapAttachmentDaemonError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(content))?;
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            if file.is_dir() {
+                continue;
+            }
+            let name = file.name().to_string();
+            // Declared sizes are checked before reading, and actual sizes while reading, since both can be forged
+            if file.size() > file.compressed_size().saturating_mul(self.limits.compression_ratio) {
+                return Err(ImapAttachmentDaemonError::ArchiveLimitExceeded("compression ratio"));
+            }
+            let compressed_size = file.compressed_size();
+            self.add_entry(&mut file, &name, Some(compressed_size), prefix, depth)?;
+        }
+        Ok(())
+    }
+
+    fn extract_tar(&mut self, reader: impl Read, prefix: &str, depth: usize) -> Result<(), ImapAttachmentDaemonError> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().to_string();
+            self.add_entry(&mut entry, &name, None, prefix, depth)?;
+        }
+        Ok(())
+    }
+
+    fn add_entry(
+        &mut self,
+        reader: &mut impl Read,
+        name: &str,
+        compressed_size: Option<u64>,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<(), ImapAttachmentDaemonError> {
+        self.entries += 1;
+        if self.entries > self.limits.entries {
+            return Err(ImapAttachmentDaemonError::ArchiveLimitExceeded("entry count"));
+        }
+        let nested_format = archive_format(name, self.formats);
+        if nested_format.is_none() && !(self.is_accepted)(name) {
+            return Ok(());
+        }
+        let remaining = self.limits.total_size.saturating_sub(self.total_size);
+        let mut content = Vec::new();
+        let _ = reader.take(remaining.saturating_add(1)).read_to_end(&mut content)?;
+        let size = u64::try_from(content.len()).unwrap_or(u64::MAX);
+        if size > remaining {
+            return Err(ImapAttachmentDaemonError::ArchiveLimitExceeded("total size"));
+        }
+        if compressed_size
+            .is_some_and(|compressed_size| size > compressed_size.saturating_mul(self.limits.compression_ratio))
+        {
+            return Err(ImapAttachmentDaemonError::ArchiveLimitExceeded("compression ratio"));
+        }
+        self.total_size += size;
+        let path = format!("{prefix}{name}");
+        match nested_format {
+            Some(_) if depth >= self.limits.depth => {
+                log::warn!("Skipping archive {path:?}, nested too deep");
+            }
+            Some(format) => self.extract(&content, format, &format!("{path}/"), depth + 1)?,
+            None => self.files.push(ExtractedFile { path, content }),
+        }
+        Ok(())
+    }
+}
+
+// Fails reads past a limit, since a truncated stream could not be told apart from the end of an archive
+struct LimitedReader<'a, R> {
+    inner: R,
+    remaining: u64,
+    exceeded: &'a Cell<bool>,
+}
+
+impl<R: Read> Read for LimitedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let Some(remaining) = self.remaining.checked_sub(u64::try_from(read).unwrap_or(u64::MAX)) else {
+            self.exceeded.set(true);
+            return Err(io::Error::other("archive limit exceeded"));
+        };
+        self.remaining = remaining;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+#[path = "test_archives.rs"]
+mod test_archives;
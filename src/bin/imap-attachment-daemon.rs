@@ -1,13 +1,27 @@
 //! The main entry point for the `imap-attachment-daemon` binary.
 
-use imap_attachment_daemon::{init_app, run_daemon};
+use std::process::ExitCode;
 
-fn main() -> anyhow::Result<()> {
+use imap_attachment_daemon::{init_app, run_daemon, run_once, ProcessingSummary, RunMode};
+
+fn main() -> ExitCode {
+    // Fatal errors are reported with their own exit code, distinct from those of the emails that failed
+    run().unwrap_or_else(|err| {
+        eprintln!("Error: {err:?}");
+        ExitCode::from(ProcessingSummary::FATAL_ERROR_EXIT_CODE)
+    })
+}
+
+fn run() -> anyhow::Result<ExitCode> {
     // Initialize the application
     let app_data = init_app()?;
 
-    // Start the daemon
-    run_daemon(&app_data)?;
+    match app_data.run_mode {
+        // Start the daemon, local sources cannot be watched so they are always processed once
+        RunMode::Daemon if app_data.local_source.is_none() => run_daemon(&app_data)?,
+        // Process the backlog and exit with a code describing the outcome
+        RunMode::Daemon | RunMode::Oneshot => return Ok(run_once(&app_data)?.exit_code()),
+    }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
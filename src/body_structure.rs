@@ -0,0 +1,233 @@
+use std::path::Path;
+
+use crate::archives::archive_format;
+use crate::content_type::infer_extension;
+use crate::mail_store::FetchedEmail;
+use crate::models::{is_accepted_file_type, AttachmentFacts, RuleAction};
+use crate::tnef::is_tnef;
+use crate::{AppConfig, ImapAttachmentDaemonError};
+
+use mail_parser::{MessageParser, MimeHeaders};
+
+// Boundary of the emails assembled from selected parts, unlikely to appear in their base64 or quoted-printable content
+const ASSEMBLED_BOUNDARY: &str = "--cwa-selected-parts--";
+
+// The structure of an email, as described by BODYSTRUCTURE, with the leaf parts of multipart emails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FetchedStructure {
+    pub uid: u32,
+    pub multipart: bool,
+    pub parts: Vec<StructurePart>,
+}
+
+// A leaf part of an email, forwarded emails being leaves. The section is the IMAP part number, such as `[2, 1]` for
+// `BODY[2.1]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct StructurePart {
+    pub section: Vec<u32>,
+    // Lowercase, without parameters
+    pub mime_type: String,
+    // From the `Content-Disposition` filename, or else the `Content-Type` name, decoded
+    pub filename: Option<String>,
+    // Whether the filename could not be decoded, leaving the part impossible to judge from its filename
+    pub undecoded_filename: bool,
+    pub is_attachment: bool,
+    // Lowercase `Content-Transfer-Encoding`
+    pub encoding: Option<String>,
+    // Size of the encoded content
+    pub octets: u64,
+}
+
+// The header of an email with some of its parts, each with its MIME header and its encoded content
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FetchedParts {
+    pub uid: u32,
+    pub header: Vec<u8>,
+    pub parts: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl FetchedStructure {
+    // Whether the email is encrypted with PGP/MIME or holds an S/MIME encrypted part, whose attachments can only be
+    // found once the full email is decrypted
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part.mime_type.as_str(),
+                "application/pgp-encrypted" | "application/pkcs7-mime" | "application/x-pkcs7-mime"
+            )
+        })
+    }
+
+    // The sections of the parts that may be accepted, judged from their filename, MIME type and size. Parts whose file
+    // type can only be told from their content, as well as forwarded emails and `winmail.dat` attachments, are always
+    // selected.
+    pub(crate) fn candidate_sections(&self, config: &AppConfig) -> Result<Vec<Vec<u32>>, ImapAttachmentDaemonError> {
+        let mut sections = Vec::new();
+        for part in &self.parts {
+            if part.is_candidate(config)? {
+                sections.push(part.section.clone());
+            }
+        }
+        Ok(sections)
+    }
+}
+
+impl StructurePart {
+    fn is_candidate(&self, config: &AppConfig) -> Result<bool, ImapAttachmentDaemonError> {
+        if self.mime_type == "message/rfc822" {
+            return Ok(config.max_nesting_depth > 0);
+        }
+        // Text parts without filename are the body of the email, where files may be embedded in plain text, or linked
+        // in plain text and HTML
+        if self.mime_type.starts_with("text/") && self.filename.is_none() && !self.is_attachment {
+            return Ok(self.mime_type == "text/plain" && !config.embedded_encodings.is_empty()
+                || matches!(self.mime_type.as_str(), "text/plain" | "text/html") && !config.link_hosts.is_empty());
+        }
+        if self.undecoded_filename
+            || is_tnef(self.filename.as_deref(), Some(&self.mime_type))
+            || self
+                .filename
+                .as_deref()
+                .is_some_and(|filename| archive_format(filename, &config.extract_archives).is_some())
+        {
+            return Ok(true);
+        }
+        let extension = self
+            .filename
+            .as_deref()
+            .and_then(|filename| Path::new(filename).extension())
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .or_else(|| infer_extension(b"", Some(&self.mime_type)).map(str::to_string));
+        let Some(extension) = extension else {
+            return Ok(true);
+        };
+        let size = self.estimated_size();
+        if config
+            .max_attachment_size
+            .is_some_and(|max_attachment_size| size > max_attachment_size)
+        {
+            return Ok(false);
+        }
+        let attachment = AttachmentFacts {
+            extension: Some(&extension),
+            mime_type: Some(&self.mime_type),
+            size: usize::try_from(size).unwrap_or(usize::MAX),
+        };
+        // The conditions of the rules on the email are left to the processing of the downloaded parts
+        Ok(is_accepted_file_type(&config.accepted_file_types, &attachment)
+            || config.rules()?.iter().any(|rule| {
+                rule.action == RuleAction::Accept && rule.matches_attachment(&attachment, &config.accepted_file_types)
+            }))
+    }
+
+    // A lower bound of the decoded size, base64 lines holding 57 bytes in 78 characters
+    fn estimated_size(&self) -> u64 {
+        match self.encoding.as_deref() {
+            Some("base64") => self.octets / 78 * 57,
+            Some("quoted-printable") => self.octets / 3,
+            _ => self.octets,
+        }
+    }
+}
+
+impl FetchedParts {
+    // Assembles the parts into a multipart email with the original header, after an empty text part standing in for
+    // the body, so that the parts are attachments as in the original email. The email keeps the size of the full email,
+    // which the size limits apply to.
+    pub(crate) fn into_email(self, size: u64) -> FetchedEmail {
+        let mut raw = without_content_headers(&self.header);
+        raw.extend_from_slice(
+            format!("Content-Type: multipart/mixed; boundary=\"{ASSEMBLED_BOUNDARY}\"\r\n\r\n").as_bytes(),
+        );
+        raw.extend_from_slice(format!("--{ASSEMBLED_BOUNDARY}\r\nContent-Type: text/plain\r\n\r\n\r\n").as_bytes());
+        for (mime_header, content) in &self.parts {
+            raw.extend_from_slice(format!("--{ASSEMBLED_BOUNDARY}\r\n").as_bytes());
+            raw.extend_from_slice(mime_header.trim_ascii_end());
+            raw.extend_from_slice(b"\r\n\r\n");
+            raw.extend_from_slice(content);
+            raw.extend_from_slice(b"\r\n");
+        }
+        raw.extend_from_slice(format!("--{ASSEMBLED_BOUNDARY}--\r\n").as_bytes());
+        FetchedEmail {
+            uid: self.uid,
+            raw,
+            size,
+        }
+    }
+}
+
+// The header of an email without its trailing empty line and its `Content-Type` and `Content-Transfer-Encoding` fields,
+// along with their folded lines
+fn without_content_headers(header: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(header.len());
+    let mut skipping = false;
+    for line in header.split_inclusive(|&byte| byte == b'\n') {
+        if line.trim_ascii().is_empty() {
+            break;
+        }
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            let name = line.split(|&byte| byte == b':').next().unwrap_or_default().trim_ascii();
+            skipping =
+                name.eq_ignore_ascii_case(b"Content-Type") || name.eq_ignore_ascii_case(b"Content-Transfer-Encoding");
+        }
+        if !skipping {
+            result.extend_from_slice(line);
+        }
+    }
+    result
+}
+
+// Decodes the filename of a part from the parameters of its `Content-Disposition` and `Content-Type` fields, which
+// BODYSTRUCTURE gives as sent, possibly as RFC 2047 encoded words or RFC 2231 extended parameters. The fields are
+// rebuilt and parsed like the downloaded part, so that the filename is the one of the saved attachment. Returns `None`
+// without filename, and `Err` with the raw filename when it cannot be decoded.
+pub(crate) fn decode_filename(
+    disposition_params: &[(&str, &str)],
+    type_params: &[(&str, &str)],
+) -> Result<Option<String>, String> {
+    let is_filename = |key: &str, name: &str| key.split('*').next().is_some_and(|key| key.eq_ignore_ascii_case(name));
+    let raw_filename = disposition_params
+        .iter()
+        .find(|(key, _)| is_filename(key, "filename"))
+        .or_else(|| type_params.iter().find(|(key, _)| is_filename(key, "name")))
+        .map(|(_, value)| (*value).to_string());
+    let Some(raw_filename) = raw_filename else {
+        return Ok(None);
+    };
+    let header = format!(
+        "Content-Disposition: attachment{}\r\nContent-Type: application/octet-stream{}\r\n\r\n",
+        header_params(disposition_params),
+        header_params(type_params)
+    );
+    let filename = MessageParser::default()
+        .parse(header.as_bytes())
+        .and_then(|message| message.attachment_name().map(str::to_string));
+    match filename {
+        // Encoded words left as is were not decoded, for instance because of an unknown encoding
+        Some(filename) if !filename.contains("=?") => Ok(Some(filename)),
+        _ => Err(raw_filename),
+    }
+}
+
+// The parameters of a MIME header field, extended parameters being sent as is and the others quoted
+fn header_params(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(key, value)| {
+            if key.ends_with('*') {
+                format!("; {key}={value}")
+            } else {
+                format!("; {key}=\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        })
+        .collect()
+}
+
+// The IMAP part number of a section, such as `2.1`
+pub(crate) fn section_number(section: &[u32]) -> String {
+    section.iter().map(u32::to_string).collect::<Vec<String>>().join(".")
+}
+
+#[cfg(test)]
+#[path = "test_body_structure.rs"]
+mod test_body_structure;
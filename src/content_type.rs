@@ -0,0 +1,280 @@
+use crate::ImapAttachmentDaemonError;
+
+// A file type recognised from the magic bytes of its content, with the extensions and MIME types that can be declared
+// for it, the first extension being the one inferred for it. Formats sharing a container, such as the ZIP-based
+// formats, share a file type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileType {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub mime_types: &'static [&'static str],
+}
+
+const EPUB: FileType = FileType {
+    name: "EPUB",
+    extensions: &["epub", "kepub", "zip"],
+    mime_types: &[
+        "application/epub+zip",
+        "application/zip",
+        "application/x-zip-compressed",
+    ],
+};
+
+const ZIP: FileType = FileType {
+    name: "ZIP",
+    extensions: &["zip", "cbz", "epub", "kepub", "docx", "odt", "htmlz", "fbz", "txtz"],
+    mime_types: &[
+        "application/zip",
+        "application/x-zip-compressed",
+        "application/x-cbz",
+        "application/vnd.comicbook+zip",
+        "application/epub+zip",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "application/vnd.oasis.opendocument.text",
+    ],
+};
+
+// Signatures with their offset, more specific signatures first
+const SIGNATURES: [(usize, &[u8], FileType); 16] = [
+    (30, b"mimetypeapplication/epub+zip", EPUB),
+    (0, b"PK\x03\x04", ZIP),
+    (
+        0,
+        b"%PDF-",
+        FileType {
+            name: "PDF",
+            extensions: &["pdf"],
+            mime_types: &["application/pdf", "application/x-pdf"],
+        },
+    ),
+    (
+        60,
+        b"BOOKMOBI",
+        FileType {
+            name: "Mobipocket",
+            extensions: &["mobi", "azw", "azw3", "azw4", "prc"],
+            mime_types: &[
+                "application/x-mobipocket-ebook",
+                "application/vnd.amazon.ebook",
+                "application/vnd.amazon.mobi8-ebook",
+            ],
+        },
+    ),
+    (
+        60,
+        b"TEXtREAd",
+        FileType {
+            name: "PalmDOC",
+            extensions: &["pdb", "prc"],
+            mime_types: &["application/vnd.palm", "application/x-aportisdoc"],
+        },
+    ),
+    (
+        0,
+        b"Rar!\x1a\x07",
+        FileType {
+            name: "RAR",
+            extensions: &["cbr", "rar"],
+            mime_types: &[
+                "application/vnd.rar",
+                "application/x-rar-compressed",
+                "application/x-cbr",
+                "application/vnd.comicbook-rar",
+            ],
+        },
+    ),
+    (
+        0,
+        b"7z\xbc\xaf\x27\x1c",
+        FileType {
+            name: "7z",
+            extensions: &["cb7", "7z"],
+            mime_types: &["application/x-7z-compressed", "application/x-cb7"],
+        },
+    ),
+    (
+        0,
+        b"AT&TFORM",
+        FileType {
+            name: "DjVu",
+            extensions: &["djvu", "djv"],
+            mime_types: &["image/vnd.djvu", "image/x-djvu"],
+        },
+    ),
+    (
+        0,
+        b"{\\rtf",
+        FileType {
+            name: "RTF",
+            extensions: &["rtf"],
+            mime_types: &["application/rtf", "text/rtf"],
+        },
+    ),
+    (
+        0,
+        b"ITSF",
+        FileType {
+            name: "CHM",
+            extensions: &["chm"],
+            mime_types: &["application/vnd.ms-htmlhelp"],
+        },
+    ),
+    (
+        0,
+        b"ITOLITLS",
+        FileType {
+            name: "LIT",
+            extensions: &["lit"],
+            mime_types: &["application/x-ms-reader"],
+        },
+    ),
+    (
+        0,
+        b"\x89PNG\r\n\x1a\n",
+        FileType {
+            name: "PNG",
+            extensions: &["png"],
+            mime_types: &["image/png"],
+        },
+    ),
+    (
+        0,
+        b"\xff\xd8\xff",
+        FileType {
+            name: "JPEG",
+            extensions: &["jpg", "jpeg"],
+            mime_types: &["image/jpeg"],
+        },
+    ),
+    (
+        0,
+        b"GIF8",
+        FileType {
+            name: "GIF",
+            extensions: &["gif"],
+            mime_types: &["image/gif"],
+        },
+    ),
+    (
+        0,
+        b"MZ",
+        FileType {
+            name: "Windows executable",
+            extensions: &["exe", "dll"],
+            mime_types: &[
+                "application/x-msdownload",
+                "application/vnd.microsoft.portable-executable",
+            ],
+        },
+    ),
+    (
+        0,
+        b"\x7fELF",
+        FileType {
+            name: "ELF executable",
+            extensions: &[],
+            mime_types: &["application/x-executable", "application/x-elf"],
+        },
+    ),
+];
+
+// Extensions inferred from the MIME type of attachments without extension
+const MIME_TYPE_EXTENSIONS: [(&str, &str); 25] = [
+    ("application/epub+zip", "epub"),
+    ("application/pdf", "pdf"),
+    ("application/x-pdf", "pdf"),
+    ("application/x-mobipocket-ebook", "mobi"),
+    ("application/vnd.amazon.ebook", "azw"),
+    ("application/vnd.amazon.mobi8-ebook", "azw3"),
+    ("application/vnd.palm", "pdb"),
+    ("image/vnd.djvu", "djvu"),
+    ("image/x-djvu", "djvu"),
+    ("application/x-cbz", "cbz"),
+    ("application/vnd.comicbook+zip", "cbz"),
+    ("application/x-cbr", "cbr"),
+    ("application/vnd.comicbook-rar", "cbr"),
+    ("application/x-cb7", "cb7"),
+    ("application/zip", "zip"),
+    ("application/vnd.rar", "rar"),
+    ("application/rtf", "rtf"),
+    ("text/rtf", "rtf"),
+    ("application/x-fictionbook+xml", "fb2"),
+    ("text/html", "html"),
+    ("text/plain", "txt"),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "docx",
+    ),
+    ("application/vnd.oasis.opendocument.text", "odt"),
+    ("application/vnd.ms-htmlhelp", "chm"),
+    ("application/x-ms-reader", "lit"),
+];
+
+// Extensions of the images of comic book archives
+const IMAGE_EXTENSIONS: [&str; 5] = [".jpg", ".jpeg", ".png", ".gif", ".webp"];
+
+// MIME types that say nothing about the content
+const GENERIC_MIME_TYPES: [&str; 2] = ["application/octet-stream", "binary/octet-stream"];
+
+pub(crate) fn detect_file_type(content: &[u8]) -> Option<FileType> {
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| content.get(*offset..).is_some_and(|content| content.starts_with(magic)))
+        .map(|(_, _, file_type)| *file_type)
+}
+
+// Infers the extension of an attachment from its MIME type when it is specific, or else from its content. ZIP archives
+// whose first entry is an image are comic books.
+pub(crate) fn infer_extension(content: &[u8], mime_type: Option<&str>) -> Option<&'static str> {
+    if let Some((_, extension)) = mime_type.and_then(|mime_type| {
+        MIME_TYPE_EXTENSIONS
+            .iter()
+            .find(|(known_mime_type, _)| *known_mime_type == mime_type)
+    }) {
+        return Some(extension);
+    }
+    let file_type = detect_file_type(content)?;
+    if file_type == ZIP && first_zip_entry_name(content).is_some_and(is_image_name) {
+        return Some("cbz");
+    }
+    file_type.extensions.first().copied()
+}
+
+// The name of the first entry of a ZIP archive, from its local file header
+fn first_zip_entry_name(content: &[u8]) -> Option<&[u8]> {
+    let name_length = usize::from(u16::from_le_bytes(content.get(26..28)?.try_into().ok()?));
+    content.get(30..30 + name_length)
+}
+
+fn is_image_name(name: &[u8]) -> bool {
+    let name = String::from_utf8_lossy(name).to_lowercase();
+    IMAGE_EXTENSIONS.iter().any(|extension| name.ends_with(extension))
+}
+
+// Checks that the declared extension and MIME type of an attachment match the type detected from its content, when
+// it has a known signature. Text formats such as FB2 or HTML have no signature and always pass.
+pub(crate) fn verify_content_type(
+    content: &[u8],
+    extension: Option<&str>,
+    mime_type: Option<&str>,
+) -> Result<(), ImapAttachmentDaemonError> {
+    let Some(file_type) = detect_file_type(content) else {
+        return Ok(());
+    };
+    let mime_type = mime_type.filter(|mime_type| !GENERIC_MIME_TYPES.contains(mime_type));
+    let mismatch = |declared: &str| ImapAttachmentDaemonError::ContentTypeMismatch {
+        declared: declared.to_string(),
+        detected: file_type.name.to_string(),
+    };
+    if let Some(extension) = extension.filter(|extension| !file_type.extensions.contains(extension)) {
+        return Err(mismatch(&format!(".{extension}")));
+    }
+    if let Some(mime_type) = mime_type.filter(|mime_type| !file_type.mime_types.contains(mime_type)) {
+        return Err(mismatch(mime_type));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "test_content_type.rs"]
+mod test_content_type;
@@ -0,0 +1,301 @@
+use crate::errors::ImapAttachmentDaemonError;
+use crate::sender_authentication::is_aligned_domain;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::Resolver;
+use mail_auth::common::crypto::Algorithm;
+use mail_auth::common::parse::TxtRecordParser;
+use mail_auth::common::verify::DomainKey;
+use mail_auth::dkim::Signature;
+use mail_auth::hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use mail_auth::{AuthenticatedMessage, DkimResult, Error, MessageAuthenticator, Parameters, ResolverCache, Txt};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::hash::Hash;
+use std::path::Path;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+// RSA keys under 1024 bits can be factored, see <https://www.rfc-editor.org/rfc/rfc8301#section-3.2>
+const MIN_RSA_KEY_BITS: usize = 1024;
+
+// Looks up the public keys of DKIM signatures, see <https://www.rfc-editor.org/rfc/rfc6376#section-3.6>
+pub(crate) trait DkimKeyResolver: fmt::Debug + Send + Sync {
+    // Returns the TXT records of a domain name such as `selector._domainkey.example.com`, the strings of each record
+    // being concatenated
+    fn txt_records(&self, name: &str) -> Result<Vec<String>, ImapAttachmentDaemonError>;
+}
+
+// Looks up DKIM keys with the DNS servers of the system configuration
+pub(crate) struct DnsKeyResolver(Resolver);
+
+impl DnsKeyResolver {
+    pub(crate) fn from_system_conf() -> Result<Self, ImapAttachmentDaemonError> {
+        Ok(Self(Resolver::from_system_conf()?))
+    }
+}
+
+impl fmt::Debug for DnsKeyResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsKeyResolver").finish_non_exhaustive()
+    }
+}
+
+impl DkimKeyResolver for DnsKeyResolver {
+    fn txt_records(&self, name: &str) -> Result<Vec<String>, ImapAttachmentDaemonError> {
+        // A trailing dot avoids appending the search domains of the system configuration
+        match self.0.txt_lookup(format!("{name}.")) {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|txt| txt.iter().map(|data| String::from_utf8_lossy(data)).collect::<String>())
+                .collect()),
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// Looks up DKIM keys in a file where each line is a domain name followed by a TXT record, such as
+// `selector._domainkey.example.com v=DKIM1; k=rsa; p=MIIBIjANBg...`. Empty lines and lines starting with `#` are
+// ignored.
+#[derive(Debug, Default)]
+pub(crate) struct StaticKeyResolver(BTreeMap<String, Vec<String>>);
+
+impl StaticKeyResolver {
+    pub(crate) fn from_file(path: &Path) -> Result<Self, ImapAttachmentDaemonError> {
+        let mut records = BTreeMap::<String, Vec<String>>::new();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, record) =
+                line.split_once(char::is_whitespace)
+                    .ok_or_else(|| ImapAttachmentDaemonError::InvalidDkimKeysFile {
+                        path: path.to_path_buf(),
+                        line: index + 1,
+                    })?;
+            records
+                .entry(name.trim_end_matches('.').to_lowercase())
+                .or_default()
+                .push(record.trim().to_string());
+        }
+        Ok(Self(records))
+    }
+}
+
+impl DkimKeyResolver for StaticKeyResolver {
+    fn txt_records(&self, name: &str) -> Result<Vec<String>, ImapAttachmentDaemonError> {
+        Ok(self.0.get(&name.to_lowercase()).cloned().unwrap_or_default())
+    }
+}
+
+// Why an email does not have a valid DKIM signature aligned with its From domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DkimFailure {
+    Unsigned,
+    Unaligned,
+    Invalid(String),
+}
+
+impl fmt::Display for DkimFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "no DKIM signature"),
+            Self::Unaligned => write!(f, "no DKIM signature from the sender domain"),
+            Self::Invalid(reason) => write!(f, "invalid DKIM signature: {reason}"),
+        }
+    }
+}
+
+// Verifies that the email has at least one valid DKIM signature whose signing domain is aligned with the From domain,
+// see <https://www.rfc-editor.org/rfc/rfc6376#section-6>. The signatures are verified by mail-auth with the public keys
+// of the resolver. Emails with several From header fields are not verified, since a signature could cover another
+// From header field than the one shown by mail clients.
+pub(crate) fn verify_dkim(
+    raw_message: &[u8],
+    from_domain: &str,
+    resolver: &dyn DkimKeyResolver,
+) -> Result<(), DkimFailure> {
+    let raw_message = with_crlf_line_endings(raw_message);
+    // The strict parsing rejects signatures of partial bodies, since content appended after the signed length could be
+    // anything, such as an attachment
+    let message = AuthenticatedMessage::parse(&raw_message)
+        .ok_or_else(|| DkimFailure::Invalid("unparsable email".to_string()))?;
+    if message
+        .headers
+        .iter()
+        .filter(|(name, _)| name.trim_ascii().eq_ignore_ascii_case(b"From"))
+        .count()
+        > 1
+    {
+        return Err(DkimFailure::Invalid("several From header fields".to_string()));
+    }
+
+    let keys = DomainKeys::resolve(&message, resolver);
+    // The authenticator never queries DNS servers since every TXT record is looked up in the keys
+    let authenticator = MessageAuthenticator::new(ResolverConfig::default(), ResolverOpts::default())
+        .map_err(|err| DkimFailure::Invalid(err.to_string()))?;
+    let outputs = block_on(authenticator.verify_dkim(Parameters::new(&message).with_txt_cache(&keys)))
+        .ok_or_else(|| DkimFailure::Invalid("the verification did not complete".to_string()))?;
+
+    let mut failure = DkimFailure::Unsigned;
+    for output in &outputs {
+        let Some(signature) = output.signature() else {
+            if let DkimResult::Neutral(err) | DkimResult::PermError(err) = output.result() {
+                failure = DkimFailure::Invalid(err.to_string());
+            }
+            continue;
+        };
+        if !is_aligned_domain(&signature.d, from_domain) {
+            if failure == DkimFailure::Unsigned {
+                failure = DkimFailure::Unaligned;
+            }
+            continue;
+        }
+        match accepted_signature(signature).and_then(|()| match output.result() {
+            DkimResult::Pass => Ok(()),
+            DkimResult::Neutral(err)
+            | DkimResult::Fail(err)
+            | DkimResult::PermError(err)
+            | DkimResult::TempError(err) => Err(err.to_string()),
+            DkimResult::None => Err("no result".to_string()),
+        }) {
+            Ok(()) => return Ok(()),
+            Err(reason) => failure = DkimFailure::Invalid(reason),
+        }
+    }
+    Err(failure)
+}
+
+// Rejects the signatures that mail-auth verifies but that are not trusted here
+fn accepted_signature(signature: &Signature) -> Result<(), String> {
+    // SHA-1 signatures must not be considered valid, see <https://www.rfc-editor.org/rfc/rfc8301#section-3.1>
+    if signature.a == Algorithm::RsaSha1 {
+        return Err("rsa-sha1 signatures are not accepted".to_string());
+    }
+    if !signature.h.iter().any(|name| name.trim().eq_ignore_ascii_case("From")) {
+        return Err("the From header field is not signed".to_string());
+    }
+    Ok(())
+}
+
+// Emails stored with bare line feeds, as found in mbox files, are signed with CRLF line endings
+fn with_crlf_line_endings(raw_message: &[u8]) -> Vec<u8> {
+    if raw_message.windows(2).any(|window| window == b"\r\n") {
+        return raw_message.to_vec();
+    }
+    raw_message.iter().fold(Vec::new(), |mut message, &byte| {
+        if byte == b'\n' {
+            message.push(b'\r');
+        }
+        message.push(byte);
+        message
+    })
+}
+
+// The public keys of the signatures of an email, looked up with the resolver before the verification. Domain names
+// without key resolve to an error, so that mail-auth never falls back to its own DNS lookups.
+struct DomainKeys(HashMap<String, Txt>);
+
+impl DomainKeys {
+    fn resolve(message: &AuthenticatedMessage, resolver: &dyn DkimKeyResolver) -> Self {
+        let mut keys = HashMap::new();
+        for signature in message
+            .dkim_headers
+            .iter()
+            .filter_map(|header| header.header.as_ref().ok())
+        {
+            let name = format!("{}._domainkey.{}", signature.s, signature.d).to_lowercase();
+            let _ = keys
+                .entry(format!("{name}."))
+                .or_insert_with(|| Self::lookup(&name, resolver));
+        }
+        Self(keys)
+    }
+
+    fn lookup(name: &str, resolver: &dyn DkimKeyResolver) -> Txt {
+        let records = match resolver.txt_records(name) {
+            Ok(records) => records,
+            Err(err) => return Txt::Error(Error::DnsError(format!("failed to look up {name}: {err}"))),
+        };
+        let Some((record, key)) = records
+            .iter()
+            .find_map(|record| DomainKey::parse(record.as_bytes()).ok().map(|key| (record, key)))
+        else {
+            return Txt::Error(Error::DnsError(format!("no public key found at {name}")));
+        };
+        // RSA keys under 1024 bits can be factored, see <https://www.rfc-editor.org/rfc/rfc8301#section-3.2>
+        match rsa_key_bits(record) {
+            Some(bits) if bits < MIN_RSA_KEY_BITS => Txt::Error(Error::CryptoError(format!(
+                "RSA key of {bits} bits, under the minimum of {MIN_RSA_KEY_BITS} bits"
+            ))),
+            _ => key.into(),
+        }
+    }
+}
+
+impl ResolverCache<String, Txt> for DomainKeys {
+    fn get<Q>(&self, name: &Q) -> Option<Txt>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Some(
+            self.0
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| Txt::Error(Error::InvalidRecordType)),
+        )
+    }
+
+    fn remove<Q>(&self, _name: &Q) -> Option<Txt>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        None
+    }
+
+    fn insert(&self, _key: String, _value: Txt, _valid_until: Instant) {}
+}
+
+// The size of the RSA key of a DKIM key record, see <https://www.rfc-editor.org/rfc/rfc6376#section-3.6.1>
+fn rsa_key_bits(record: &str) -> Option<usize> {
+    let tags = record
+        .split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect::<HashMap<&str, &str>>();
+    if tags.get("k").is_some_and(|&key_type| key_type != "rsa") {
+        return None;
+    }
+    let key = STANDARD.decode(tags.get("p")?.replace(char::is_whitespace, "")).ok()?;
+    RsaPublicKey::from_public_key_der(&key)
+        .or_else(|_| RsaPublicKey::from_pkcs1_der(&key))
+        .ok()
+        .map(|key| key.n().bits())
+}
+
+// Runs a future whose lookups are all answered by `DomainKeys`, so that it completes without waiting
+fn block_on<F: Future>(future: F) -> Option<F::Output> {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+#[cfg(test)]
+#[path = "test_dkim.rs"]
+mod test_dkim;
@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+
+use crate::archives::ExtractedFile;
+use crate::models::EmbeddedEncoding;
+
+// Decodes the files embedded in a text body as uuencoded or yEnc blocks, when their encoding is enabled. Blocks that are
+// cut short, fail their size or CRC check, or are parts of multipart yEnc posts are skipped.
+pub(crate) fn decode_embedded_files(text: &[u8], encodings: &BTreeSet<EmbeddedEncoding>) -> Vec<ExtractedFile> {
+    let mut files = Vec::new();
+    let mut lines = text
+        .split(|&byte| byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    while let Some(line) = lines.next() {
+        let file = if let Some(path) = uuencode_begin(line).filter(|_| encodings.contains(&EmbeddedEncoding::Uuencode))
+        {
+            decode_uuencode(&mut lines).map(|content| ExtractedFile { path, content })
+        } else if let Some(header) = yenc_begin(line).filter(|_| encodings.contains(&EmbeddedEncoding::Yenc)) {
+            decode_yenc(&mut lines, &header).map(|content| ExtractedFile {
+                path: header.name,
+                content,
+            })
+        } else {
+            None
+        };
+        files.extend(file);
+    }
+    files
+}
+
+// The filename of a `begin 644 book.epub` line
+fn uuencode_begin(line: &[u8]) -> Option<String> {
+    let rest = std::str::from_utf8(line.strip_prefix(b"begin ")?).ok()?;
+    let (mode, name) = rest.split_once(' ')?;
+    let is_mode = (3..=4).contains(&mode.len()) && mode.bytes().all(|byte| (b'0'..=b'7').contains(&byte));
+    (is_mode && !name.trim().is_empty()).then(|| name.trim().to_string())
+}
+
+// Decodes the lines of a uuencoded block up to its `end` line, each line starting with its decoded length
+fn decode_uuencode<'a>(lines: &mut impl Iterator<Item = &'a [u8]>) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    for line in lines {
+        if line.trim_ascii_end() == b"end" {
+            return Some(content);
+        }
+        let Some((&length, encoded)) = line.split_first() else {
+            continue;
+        };
+        let length = usize::from(uudecode_char(length));
+        let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3 + 3);
+        for group in encoded.chunks(4) {
+            let mut sextets = [0; 4];
+            for (sextet, &char) in sextets.iter_mut().zip(group) {
+                *sextet = uudecode_char(char);
+            }
+            decoded.extend_from_slice(&[
+                (sextets[0] << 2) | (sextets[1] >> 4),
+                (sextets[1] << 4) | (sextets[2] >> 2),
+                (sextets[2] << 6) | sextets[3],
+            ]);
+        }
+        decoded.truncate(length);
+        content.extend_from_slice(&decoded);
+    }
+    None
+}
+
+// Both ` ` and `` ` `` stand for zero
+fn uudecode_char(char: u8) -> u8 {
+    char.wrapping_sub(b' ') & 0x3F
+}
+
+struct YencHeader {
+    name: String,
+    size: Option<usize>,
+}
+
+// The `=ybegin line=128 size=1024 name=book.epub` line of a single-part yEnc block, the name being the rest of the line
+fn yenc_begin(line: &[u8]) -> Option<YencHeader> {
+    let rest = String::from_utf8_lossy(line.strip_prefix(b"=ybegin ")?).into_owned();
+    let (parameters, name) = rest.split_once("name=")?;
+    let parameter = |key: &str| {
+        parameters
+            .split_ascii_whitespace()
+            .find_map(|parameter| parameter.strip_prefix(key)?.strip_prefix('='))
+    };
+    if parameter("part").is_some() && parameter("total") != Some("1") {
+        return None;
+    }
+    let name = name.trim();
+    (!name.is_empty()).then(|| YencHeader {
+        name: name.to_string(),
+        size: parameter("size").and_then(|size| size.parse().ok()),
+    })
+}
+
+// Decodes the lines of a yEnc block up to its `=yend` line, checking the size and CRC32 it declares
+fn decode_yenc<'a>(lines: &mut impl Iterator<Item = &'a [u8]>, header: &YencHeader) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    for line in lines {
+        if let Some(trailer) = line.strip_prefix(b"=yend") {
+            return is_valid_yenc(&content, header, &String::from_utf8_lossy(trailer)).then_some(content);
+        }
+        if line.starts_with(b"=ypart ") {
+            continue;
+        }
+        let mut bytes = line.iter();
+        while let Some(&byte) = bytes.next() {
+            let byte = if byte == b'=' {
+                bytes.next()?.wrapping_sub(64)
+            } else {
+                byte
+            };
+            content.push(byte.wrapping_sub(42));
+        }
+    }
+    None
+}
+
+fn is_valid_yenc(content: &[u8], header: &YencHeader, trailer: &str) -> bool {
+    let parameter = |key: &str| {
+        trailer
+            .split_ascii_whitespace()
+            .find_map(|parameter| parameter.strip_prefix(key)?.strip_prefix('='))
+    };
+    let size = parameter("size").and_then(|size| size.parse::<usize>().ok());
+    let crc32 = parameter("crc32").and_then(|crc32| u32::from_str_radix(crc32, 16).ok());
+    let mut crc = flate2::Crc::new();
+    crc.update(content);
+    size.or(header.size).is_none_or(|size| size == content.len()) && crc32.is_none_or(|crc32| crc32 == crc.sum())
+}
+
+#[cfg(test)]
+#[path = "test_embedded_files.rs"]
+mod test_embedded_files;
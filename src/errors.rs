@@ -21,6 +21,9 @@ pub enum ImapAttachmentDaemonError {
     /// Error when expected body not in message.
     #[error("Could not find body in message")]
     BodyMissing,
+    /// Error when expected size not in message.
+    #[error("Could not find size in message")]
+    SizeMissing,
     /// Error when expected header not in message.
     #[error("Could not find header in message")]
     HeaderMissing,
@@ -45,6 +48,86 @@ pub enum ImapAttachmentDaemonError {
     /// Error when receiving message from channel fails.
     #[error("Could not receive message from channel")]
     ReceiveError(#[from] std::sync::mpsc::RecvError),
+    /// Error when a mandatory setting is missing from the configuration.
+    #[error("Missing configuration: {0} must be set")]
+    MissingConfig(&'static str),
+    /// Error when a local mail source is neither a Maildir, a directory of `.eml` files nor an mbox file.
+    #[error("Unsupported local mail source: {0:?}")]
+    UnsupportedLocalSource(std::path::PathBuf),
+    /// Error when a whitelist or blocklist entry is not a valid pattern.
+    #[error("Invalid sender pattern {pattern:?}: {source}")]
+    InvalidSenderPattern {
+        /// The invalid entry.
+        pattern: String,
+        /// error source.
+        source: regex::Error,
+    },
+    /// Error when looking up DNS records fails.
+    #[error("DNS error: {0}")]
+    DnsError(#[from] hickory_resolver::error::ResolveError),
+    /// Error when a line of the DKIM keys file is not a domain name followed by a TXT record.
+    #[error("Invalid DKIM keys file {path:?} at line {line}")]
+    InvalidDkimKeysFile {
+        /// The DKIM keys file.
+        path: std::path::PathBuf,
+        /// The invalid line number.
+        line: usize,
+    },
+    /// Error when the rules file is not valid TOML or has unknown fields.
+    #[error("Invalid rules file {path:?}: {reason}")]
+    InvalidRulesFile {
+        /// The rules file.
+        path: std::path::PathBuf,
+        /// Why the file is invalid.
+        reason: String,
+    },
+    /// Error when a private key or trusted signers file cannot be read.
+    #[error("Invalid key file {path:?}: {reason}")]
+    InvalidKeyFile {
+        /// The key file.
+        path: std::path::PathBuf,
+        /// Why the file is invalid.
+        reason: String,
+    },
+    /// Error when a rule of the rules file has an invalid condition.
+    #[error("Invalid rule {rule:?}: {reason}")]
+    InvalidRule {
+        /// The name of the invalid rule.
+        rule: String,
+        /// Why the rule is invalid.
+        reason: String,
+    },
+    /// Error when the content of an attachment does not match its declared extension or MIME type.
+    #[error("Attachment declared as {declared} but its content is {detected}")]
+    ContentTypeMismatch {
+        /// The declared extension or MIME type.
+        declared: String,
+        /// The file type detected from the content.
+        detected: String,
+    },
+    /// Error when an archive attachment cannot be read.
+    #[error("Archive error: {0}")]
+    ArchiveError(#[from] zip::result::ZipError),
+    /// Error when an archive attachment exceeds one of the extraction limits.
+    #[error("Archive exceeds the {0} limit")]
+    ArchiveLimitExceeded(&'static str),
+    /// Error when a `winmail.dat` (TNEF) attachment cannot be decoded.
+    #[error("Invalid TNEF attachment: {0}")]
+    InvalidTnef(&'static str),
+    /// Error when a PGP key, message or signature cannot be read or decrypted.
+    #[error("OpenPGP error: {0}")]
+    OpenPgpError(&'static str),
+    /// Error when an S/MIME key, message or signature cannot be read or decrypted.
+    #[error("S/MIME error: {0}")]
+    SmimeError(&'static str),
+    /// Error when a file linked in the body of an email cannot be downloaded.
+    #[error("Could not download linked file {url}: {reason}")]
+    LinkDownloadError {
+        /// The link.
+        url: String,
+        /// Why the download failed.
+        reason: String,
+    },
     /// Error when parsing email fails.
     #[error("Could not parse email")]
     ParsingError,
@@ -1,13 +1,160 @@
 use std::collections::HashSet;
-use std::sync::mpsc::Sender;
+use std::time::Duration;
 
+use crate::body_structure::{decode_filename, section_number, FetchedParts, FetchedStructure, StructurePart};
+use crate::mail_searching::generate_search_criteria;
+use crate::mail_store::{EmailFlag, FetchedEmail, MailStore, SearchQuery};
 use crate::{errors::ImapAttachmentDaemonError, AppConfig};
 
 use imap::types::{Fetches, UnsolicitedResponse};
 use imap::{ImapConnection, Session};
+use imap_proto::types::{BodyParams, BodyStructure, ContentEncoding, MessageSection, SectionPath};
 use secrecy::ExposeSecret;
 
-pub(crate) fn open_session(config: &AppConfig) -> Result<Session<Box<dyn ImapConnection>>, ImapAttachmentDaemonError> {
+// Mail store backed by an IMAP session on the inbox
+#[derive(Debug)]
+pub(crate) struct ImapStore {
+    session: Session<Box<dyn ImapConnection>>,
+}
+
+impl ImapStore {
+    pub(crate) fn connect(config: &AppConfig) -> Result<Self, ImapAttachmentDaemonError> {
+        Ok(Self {
+            session: open_session(config)?,
+        })
+    }
+
+    pub(crate) fn set_debug(&mut self, debug: bool) {
+        self.session.debug = debug;
+    }
+
+    pub(crate) fn logout(mut self) -> Result<(), ImapAttachmentDaemonError> {
+        self.session.logout().map_err(Into::into)
+    }
+}
+
+impl MailStore for ImapStore {
+    fn search(&mut self, query: SearchQuery) -> Result<HashSet<u32>, ImapAttachmentDaemonError> {
+        let search_criteria = match query {
+            SearchQuery::UnseenFromWhitelist(config) => generate_search_criteria(config),
+            SearchQuery::RecentUnseen => "RECENT UNSEEN".to_string(),
+        };
+        imap_uid_search(search_criteria, &mut self.session)
+    }
+
+    fn fetch_headers(&mut self, uids: &[u32]) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError> {
+        let fetches = imap_fetch_headers(uid_set(uids), &mut self.session)?;
+        fetches
+            .iter()
+            .map(|fetch| {
+                Ok(FetchedEmail {
+                    uid: fetch.uid.ok_or(ImapAttachmentDaemonError::UidMissing)?,
+                    raw: fetch.header().ok_or(ImapAttachmentDaemonError::HeaderMissing)?.to_vec(),
+                    size: fetch
+                        .size
+                        .map(u64::from)
+                        .ok_or(ImapAttachmentDaemonError::SizeMissing)?,
+                })
+            })
+            .collect()
+    }
+
+    fn fetch_bodies(&mut self, uids: &[u32]) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError> {
+        let fetches = imap_fetch_body_peek(uid_set(uids), &mut self.session)?;
+        fetches
+            .iter()
+            .map(|fetch| {
+                let raw = fetch.body().ok_or(ImapAttachmentDaemonError::BodyMissing)?.to_vec();
+                Ok(FetchedEmail {
+                    uid: fetch.uid.ok_or(ImapAttachmentDaemonError::UidMissing)?,
+                    size: u64::try_from(raw.len()).unwrap_or(u64::MAX),
+                    raw,
+                })
+            })
+            .collect()
+    }
+
+    fn fetch_structures(&mut self, uids: &[u32]) -> Result<Vec<FetchedStructure>, ImapAttachmentDaemonError> {
+        let fetches = imap_fetch_by_uid(uid_set(uids), &"BODYSTRUCTURE", &mut self.session)?;
+        fetches
+            .iter()
+            .map(|fetch| {
+                let structure = fetch.bodystructure().ok_or(ImapAttachmentDaemonError::BodyMissing)?;
+                let mut parts = Vec::new();
+                structure_parts(structure, &[], &mut parts);
+                Ok(FetchedStructure {
+                    uid: fetch.uid.ok_or(ImapAttachmentDaemonError::UidMissing)?,
+                    multipart: matches!(structure, BodyStructure::Multipart { .. }),
+                    parts,
+                })
+            })
+            .collect()
+    }
+
+    fn fetch_parts(&mut self, uid: u32, sections: &[Vec<u32>]) -> Result<FetchedParts, ImapAttachmentDaemonError> {
+        let query = sections
+            .iter()
+            .map(|section| {
+                let number = section_number(section);
+                format!("BODY.PEEK[{number}.MIME] BODY.PEEK[{number}]")
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        let fetches = imap_fetch_by_uid(
+            uid.to_string(),
+            &format!("(BODY.PEEK[HEADER] {query})"),
+            &mut self.session,
+        )?;
+        let fetch = fetches.iter().next().ok_or(ImapAttachmentDaemonError::UidMissing)?;
+        let parts = sections
+            .iter()
+            .map(|section| {
+                let mime_header = fetch
+                    .section(&SectionPath::Part(section.clone(), Some(MessageSection::Mime)))
+                    .ok_or(ImapAttachmentDaemonError::HeaderMissing)?;
+                let content = fetch
+                    .section(&SectionPath::Part(section.clone(), None))
+                    .ok_or(ImapAttachmentDaemonError::BodyMissing)?;
+                Ok((mime_header.to_vec(), content.to_vec()))
+            })
+            .collect::<Result<Vec<(Vec<u8>, Vec<u8>)>, ImapAttachmentDaemonError>>()?;
+        Ok(FetchedParts {
+            uid,
+            header: fetch.header().ok_or(ImapAttachmentDaemonError::HeaderMissing)?.to_vec(),
+            parts,
+        })
+    }
+
+    fn move_email(&mut self, uid: u32, mailbox: &str) -> Result<(), ImapAttachmentDaemonError> {
+        self.session.uid_mv(uid.to_string(), mailbox).map_err(Into::into)
+    }
+
+    fn delete_email(&mut self, uid: u32) -> Result<(), ImapAttachmentDaemonError> {
+        self.add_flag(uid, EmailFlag::Deleted)?;
+        // UID EXPUNGE only removes this email, unlike EXPUNGE which would remove every email flagged as deleted
+        let _ = self.session.uid_expunge(uid.to_string())?;
+        Ok(())
+    }
+
+    fn add_flag(&mut self, uid: u32, flag: EmailFlag) -> Result<(), ImapAttachmentDaemonError> {
+        let _ = self
+            .session
+            .uid_store(uid.to_string(), format!("+FLAGS ({})", flag.imap_name()))?;
+        Ok(())
+    }
+
+    fn idle(&mut self, timeout: Duration) -> Result<bool, ImapAttachmentDaemonError> {
+        let mut changed = false;
+        // A timeout leaves `changed` untouched, so there is nothing to check
+        let _ = self.session.idle().timeout(timeout).wait_while(|response| {
+            changed = process_idle_update(response);
+            false
+        })?;
+        Ok(changed)
+    }
+}
+
+fn open_session(config: &AppConfig) -> Result<Session<Box<dyn ImapConnection>>, ImapAttachmentDaemonError> {
     let mut session = imap::ClientBuilder::new(&config.imap_server, 993)
         .connect()?
         .login(&config.username, config.password.expose_secret())
@@ -15,96 +162,109 @@ pub(crate) fn open_session(config: &AppConfig) -> Result<Session<Box<dyn ImapCon
     let _ = session.select("INBOX")?;
     Ok(session)
 }
-pub(crate) fn move_email_to_trash(
-    uid: &str,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<(), ImapAttachmentDaemonError> {
-    imap_session.uid_mv(uid, "Trash")?;
-    log::debug!("Moved email to Trash");
-    Ok(())
-}
-
-pub(crate) fn mark_email_as_unread(
-    uid: &str,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<(), ImapAttachmentDaemonError> {
-    let _ = imap_session.uid_store(uid, "-FLAGS \\Seen")?;
-    log::debug!("Email marked as unread");
-    Ok(())
-}
 
 /// A convenience function to always cause the IDLE handler to exit on any change.
-/// Always returns false to release the thread so a new connection can be made, workaround for
-/// <https://github.com/jonhoo/rust-imap/issues/300>
-pub(crate) fn process_idle_update(response: UnsolicitedResponse, sender: &Sender<u32>) -> bool {
+/// Returns whether the change may be a new unread email. The IDLE handler is always released so a new connection can
+/// be made, workaround for <https://github.com/jonhoo/rust-imap/issues/300>
+fn process_idle_update(response: UnsolicitedResponse) -> bool {
     match response {
-        UnsolicitedResponse::Fetch { id, attributes } => {
-            // If the email is not marked as seen, send the ID to the main thread
-            if attributes.iter().clone().any(|attr| {
-                matches!(attr, imap::types::AttributeValue::Flags(vals) if
-                !vals.contains(&std::borrow::Cow::Borrowed("\\Seen")))
-            }) {
-                sender.send(id).expect("Send failed, channel is closed");
-            }
-        }
+        // If the email is not marked as seen, notify the main thread
+        UnsolicitedResponse::Fetch { attributes, .. } => attributes.iter().any(|attr| {
+            matches!(attr, imap::types::AttributeValue::Flags(vals) if
+            !vals.contains(&std::borrow::Cow::Borrowed("\\Seen")))
+        }),
         // New emails are marked as EXISTS, without any flags in the unsolicited response
-        // It also comes as a UnsolicitedResponse::Recent, but we don't need to handle it because the id is not useful
-        UnsolicitedResponse::Exists(id) => {
-            sender.send(id).expect("Send failed, channel is closed");
-        }
+        // It also comes as a UnsolicitedResponse::Recent, but we don't need to handle it
+        UnsolicitedResponse::Exists(_) => true,
         UnsolicitedResponse::Bye {
             ref code,
             ref information,
         } => {
-            log::error!("Server disconnected: {:?} {:?}", code, information);
+            log::error!("Server disconnected: {code:?} {information:?}");
+            false
         }
-        _ => {}
+        _ => false,
     }
-    false
 }
 
-pub(crate) fn imap_search(
-    search_criteria: impl AsRef<str>,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<HashSet<u32>, ImapAttachmentDaemonError> {
-    imap_session.search(search_criteria).map_err(Into::into)
+// Lists the leaf parts of a BODYSTRUCTURE, numbering them as IMAP sections. Forwarded emails are leaves.
+fn structure_parts(structure: &BodyStructure, section: &[u32], parts: &mut Vec<StructurePart>) {
+    let (common, other) = match structure {
+        BodyStructure::Multipart { bodies, .. } => {
+            for (number, body) in (1..).zip(bodies) {
+                structure_parts(body, &[section, &[number]].concat(), parts);
+            }
+            return;
+        }
+        BodyStructure::Basic { common, other, .. }
+        | BodyStructure::Text { common, other, .. }
+        | BodyStructure::Message { common, other, .. } => (common, other),
+    };
+    let disposition = common.disposition.as_ref();
+    let disposition_params = disposition
+        .map(|disposition| body_params(&disposition.params))
+        .unwrap_or_default();
+    let (filename, undecoded_filename) = match decode_filename(&disposition_params, &body_params(&common.ty.params)) {
+        Ok(filename) => (filename, false),
+        Err(raw_filename) => {
+            log::debug!(
+                "Could not decode the filename {raw_filename} of part {}",
+                section_number(section)
+            );
+            (Some(raw_filename), true)
+        }
+    };
+    parts.push(StructurePart {
+        section: section.to_vec(),
+        mime_type: format!("{}/{}", common.ty.ty, common.ty.subtype).to_lowercase(),
+        filename,
+        undecoded_filename,
+        is_attachment: disposition.is_some_and(|disposition| disposition.ty.eq_ignore_ascii_case("attachment")),
+        encoding: match &other.transfer_encoding {
+            ContentEncoding::SevenBit => Some("7bit".to_string()),
+            ContentEncoding::EightBit => Some("8bit".to_string()),
+            ContentEncoding::Binary => Some("binary".to_string()),
+            ContentEncoding::Base64 => Some("base64".to_string()),
+            ContentEncoding::QuotedPrintable => Some("quoted-printable".to_string()),
+            ContentEncoding::Other(encoding) => Some(encoding.to_lowercase()),
+        },
+        octets: u64::from(other.octets),
+    });
 }
 
-pub(crate) fn imap_fetch_uids(
-    sequence_set: impl AsRef<str>,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<Vec<String>, ImapAttachmentDaemonError> {
-    imap_fetch_by_seq(sequence_set, &"UID", imap_session)?
+fn body_params<'a>(params: &'a BodyParams) -> Vec<(&'a str, &'a str)> {
+    params
         .iter()
-        .map(|query_response| {
-            query_response
-                .uid
-                .ok_or(ImapAttachmentDaemonError::UidMissing)
-                .map(|uid| uid.to_string())
-        })
-        .collect::<Result<Vec<String>, ImapAttachmentDaemonError>>()
+        .flatten()
+        .map(|(key, value)| (key.as_ref(), value.as_ref()))
+        .collect()
 }
 
-pub(crate) fn imap_fetch_rfc822(
-    sequence_set: impl AsRef<str>,
+fn uid_set(uids: &[u32]) -> String {
+    uids.iter().map(u32::to_string).collect::<Vec<String>>().join(",")
+}
+
+fn imap_uid_search(
+    search_criteria: impl AsRef<str>,
     imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<Fetches, ImapAttachmentDaemonError> {
-    imap_fetch_by_uid(sequence_set, &"RFC822", imap_session)
+) -> Result<HashSet<u32>, ImapAttachmentDaemonError> {
+    imap_session.uid_search(search_criteria).map_err(Into::into)
 }
 
-pub(crate) fn imap_fetch_headers(
+fn imap_fetch_body_peek(
     sequence_set: impl AsRef<str>,
     imap_session: &mut Session<Box<dyn ImapConnection>>,
 ) -> Result<Fetches, ImapAttachmentDaemonError> {
-    imap_fetch_by_uid(sequence_set, &"RFC822.HEADER", imap_session)
+    // BODY.PEEK[] does not set the \Seen flag as a side effect
+    imap_fetch_by_uid(sequence_set, &"BODY.PEEK[]", imap_session)
 }
 
-fn imap_fetch_by_seq(
+fn imap_fetch_headers(
     sequence_set: impl AsRef<str>,
-    query: &impl AsRef<str>,
     imap_session: &mut Session<Box<dyn ImapConnection>>,
 ) -> Result<Fetches, ImapAttachmentDaemonError> {
-    imap_session.fetch(sequence_set, query).map_err(Into::into)
+    // RFC822.SIZE gives the size of the full email, to check the size limits before downloading it
+    imap_fetch_by_uid(sequence_set, &"(BODY.PEEK[HEADER] RFC822.SIZE)", imap_session)
 }
 
 fn imap_fetch_by_uid(
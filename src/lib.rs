@@ -4,21 +4,39 @@
 //!  them based on the sender and recipient addresses. Attachments from whitelisted senders are saved to a specified
 //!  directory and the emails are moved to trash, while other emails are kept unread.
 
+pub(crate) mod archives;
+pub(crate) mod body_structure;
+pub(crate) mod content_type;
+pub(crate) mod dkim;
+pub(crate) mod embedded_files;
 mod errors;
 pub(crate) mod imap_ops;
+pub(crate) mod linked_files;
+pub(crate) mod local_sources;
 pub(crate) mod mail_parsing;
 pub(crate) mod mail_searching;
+pub(crate) mod mail_store;
+#[cfg(test)]
+pub(crate) mod memory_store;
 mod models;
+pub(crate) mod openpgp;
+pub(crate) mod secure_mime;
+pub(crate) mod sender_authentication;
+pub(crate) mod smime;
+pub(crate) mod tnef;
 
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 use std::time::Duration;
 
 pub use errors::ImapAttachmentDaemonError;
-use imap_ops::{open_session, process_idle_update};
+use imap_ops::ImapStore;
+use local_sources::local_source_processing;
 use log::log_enabled;
 use mail_searching::{idle_update_email_search, startup_email_search};
-use models::AppConfig;
+use mail_store::MailStore;
+use models::{AppConfig, RequiredAuthentication, RequiredSignature};
+pub use models::{ProcessingSummary, RunMode};
 
 use env_logger::{Builder, Env};
 
@@ -37,7 +55,11 @@ use env_logger::{Builder, Env};
 /// * If loading environment variables from the `.env` file fails.
 /// * If initialising the logging system fails.
 /// * If reading the configuration from environment variables fails.
-/// * If creating the attachments directory fails.
+/// * If a whitelist entry is not a valid pattern.
+/// * If a private key or trusted signers file cannot be read, or a private key cannot be unlocked.
+/// * If a signature is required but no trusted signers file is configured.
+/// * If the IMAP server or username are missing and no local source is configured.
+/// * If creating the attachments directory fails, unless running in dry run mode.
 /// * If connecting to the IMAP server fails.
 /// * If logging into the IMAP server fails.
 /// * If selecting the "INBOX" folder on the IMAP server fails.
@@ -47,13 +69,45 @@ pub fn init_app() -> Result<AppConfig, ImapAttachmentDaemonError> {
         log::warn!("No .env file found, using environment variables");
     } else {
         log::info!("Loaded environment variables from .env file");
-    };
+    }
 
     // Initialize logging, default to info level
     let env = Env::new().filter_or("RUST_LOG", "info");
     Builder::from_env(env).init();
 
     let config = envy::prefixed("CWA_").from_env::<AppConfig>()?;
+    let _ = config.whitelist_patterns()?;
+    let _ = config.blocklist_patterns()?;
+    let _ = config.rules()?;
+    if config.verify_dkim {
+        let _ = config.dkim_key_resolver()?;
+    }
+    let _ = config.secure_mime_keys()?;
+
+    // The IMAP settings are only needed when not processing a local source
+    if config.local_source.is_none() {
+        if config.imap_server.is_empty() {
+            return Err(ImapAttachmentDaemonError::MissingConfig("CWA_IMAP_SERVER"));
+        }
+        if config.username.is_empty() {
+            return Err(ImapAttachmentDaemonError::MissingConfig("CWA_USERNAME"));
+        }
+    } else if config.target_address.is_none() && config.username.is_empty() {
+        return Err(ImapAttachmentDaemonError::MissingConfig("CWA_TARGET_ADDRESS"));
+    }
+    // Only the `Authentication-Results` headers of the trusted receiving server can be relied upon
+    if config.require_authentication != RequiredAuthentication::None && config.authserv_id.is_none() {
+        return Err(ImapAttachmentDaemonError::MissingConfig("CWA_AUTHSERV_ID"));
+    }
+    // Signatures are only trusted when made by one of the trusted signers
+    if config.require_signature != RequiredSignature::None && config.signers_file.is_none() {
+        return Err(ImapAttachmentDaemonError::MissingConfig("CWA_SIGNERS_FILE"));
+    }
+
+    if config.dry_run {
+        log::warn!("Dry run enabled, no attachments will be saved and the mailbox will not be changed");
+        return Ok(config);
+    }
 
     // Create attachments directory if it doesn't exist
     std::fs::create_dir_all(&config.attachments_dir).map_err(|err| {
@@ -71,9 +125,12 @@ pub fn init_app() -> Result<AppConfig, ImapAttachmentDaemonError> {
 /// This function will start the daemon, fetching emails from the IMAP server and processing them. It will then enter
 /// IDLE mode to listen for changes in the mailbox and process new emails as they arrive. All emails are checked for
 /// destination address and sender address, with only emails from the whitelist being processed. Attachments are saved
-/// to the specified directory and the email is marked as read or moved to the trash.
+/// to the specified directory and the email is marked as read and moved to the trash.
+///
+/// Emails are fetched without being marked as seen, so an email without any accepted attachments is left unread.
 ///
-/// If an email does not contain any attachments, it will be marked as unread.
+/// In dry run mode, no attachments are saved and no emails are marked or moved. Instead, the actions that would have
+/// been taken are logged.
 ///
 /// # Arguments
 /// * `config` - The application configuration.
@@ -89,7 +146,7 @@ pub fn init_app() -> Result<AppConfig, ImapAttachmentDaemonError> {
 /// * If the email search after an update fails.
 /// * If the email parsing and processing fails.
 /// * If moving an email to the trash fails.
-/// * If marking an email as unread fails.
+/// * If marking an email as read fails.
 /// * If logging out of the IMAP session fails.
 /// * If the channel receiver fails to receive a message.
 /// * If the channel sender fails to send a message.
@@ -107,28 +164,67 @@ pub fn run_daemon(config: &AppConfig) -> Result<(), ImapAttachmentDaemonError> {
     );
 
     // Check for unread emails on startup
-    startup_email_search(config)?;
+    let _ = startup_email_search(config, &mut ImapStore::connect(config)?)?;
+    log::info!("Waiting for new emails");
 
-    let (sender, receiver): (Sender<u32>, Receiver<u32>) = channel();
+    let (sender, receiver): (Sender<()>, Receiver<()>) = channel();
 
-    let mut idle_imap_session = open_session(config)?;
+    let mut idle_store = ImapStore::connect(config)?;
     if log_enabled!(log::Level::Debug) {
-        idle_imap_session.debug = true;
+        idle_store.set_debug(true);
     }
 
     // Spawn a thread for IDLE mode
     let _ = thread::spawn(move || -> Result<(), ImapAttachmentDaemonError> {
         loop {
             // Enter IDLE mode
-            let _ = idle_imap_session
-                .idle()
-                .timeout(Duration::from_secs(300))
-                .wait_while(|response| process_idle_update(response, &sender))?;
+            if idle_store.idle(Duration::from_secs(300))? {
+                sender.send(())?;
+            }
         }
     });
 
     loop {
-        let response = receiver.recv()?;
-        idle_update_email_search(response, config)?;
+        receiver.recv()?;
+        let mut store = ImapStore::connect(config)?;
+        let _ = idle_update_email_search(config, &mut store)?;
+        store.logout()?;
+    }
+}
+
+/// Fetches and processes the backlog of unread emails from the IMAP server, then returns.
+///
+/// This performs the same startup search and post-processing as [`run_daemon`], without entering IDLE mode. It is
+/// meant for environments where only scheduled jobs can be run, such as cron or systemd timers.
+///
+/// If a local source is configured, emails are read from it instead of the IMAP server. A local source can be a
+/// Maildir, a directory of `.eml` files, a single `.eml` file or an mbox file. Processed Maildir emails are moved to
+/// `cur/` with the seen and trashed flags set, while `.eml` and mbox files are left untouched.
+///
+/// # Arguments
+/// * `config` - The application configuration.
+///
+/// # Returns
+/// * `Ok(ProcessingSummary)` - Counts of processed, skipped and failed emails.
+/// * `Err(ImapAttachmentDaemonError)` - If connecting to or searching the IMAP server fails.
+///
+/// # Errors
+/// This function will return an error in the following cases:
+/// * If the local source cannot be read.
+/// * If connecting to the IMAP server fails.
+/// * If the email search fails.
+/// * If fetching the email bodies fails.
+pub fn run_once(config: &AppConfig) -> Result<ProcessingSummary, ImapAttachmentDaemonError> {
+    if let Some(local_source) = &config.local_source {
+        let summary = local_source_processing(local_source, config)?;
+        log::info!("Finished processing local emails: {summary}");
+        return Ok(summary);
     }
+    log::info!(
+        "Processing unread emails on account: {}",
+        config.target_address.as_ref().unwrap_or(&config.username)
+    );
+    let summary = startup_email_search(config, &mut ImapStore::connect(config)?)?;
+    log::info!("Finished processing unread emails: {summary}");
+    Ok(summary)
 }
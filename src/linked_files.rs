@@ -0,0 +1,235 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mail_parser::{Message, MimeHeaders};
+use percent_encoding::percent_decode_str;
+use ureq::native_tls::TlsConnector;
+use ureq::{AgentBuilder, Response};
+use url::Url;
+
+use crate::models::LinkSchemes;
+use crate::ImapAttachmentDaemonError;
+
+// How many links of an email are downloaded, and how many redirects are followed for each link
+pub(crate) const MAX_LINKS: usize = 10;
+const MAX_REDIRECTS: usize = 5;
+
+// Limits on the download of a linked file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LinkLimits {
+    pub size: u64,
+    // For the whole download, redirects included
+    pub timeout: Duration,
+    // The schemes of the links and redirects that are followed
+    pub schemes: LinkSchemes,
+}
+
+// A file downloaded from a link, with the filename and MIME type given by the server or its URL
+#[derive(Debug, Clone)]
+pub(crate) struct LinkedFile {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    pub content: Vec<u8>,
+}
+
+// The links to the allowed hosts in the plain text and HTML bodies of an email, in order and without duplicates
+pub(crate) fn body_links(message: &Message, hosts: &BTreeSet<String>) -> Vec<Url> {
+    let mut part_ids = Vec::new();
+    for &part_id in message.text_body.iter().chain(&message.html_body) {
+        if !part_ids.contains(&part_id) && !message.attachments.contains(&part_id) {
+            part_ids.push(part_id);
+        }
+    }
+    let mut links = Vec::new();
+    for part in part_ids.into_iter().filter_map(|part_id| message.parts.get(part_id)) {
+        let is_body_text = part.content_type().is_none_or(|content_type| {
+            content_type.ctype().eq_ignore_ascii_case("text")
+                && content_type.subtype().is_some_and(|subtype| {
+                    subtype.eq_ignore_ascii_case("plain") || subtype.eq_ignore_ascii_case("html")
+                })
+        });
+        if !is_body_text {
+            continue;
+        }
+        for link in extract_links(part.text_contents().unwrap_or_default(), hosts) {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+// The http and https links to the allowed hosts in a text, found as bare URLs or as the attributes of HTML links. Bare
+// URLs end before whitespace, quotes or angle brackets, and without their trailing punctuation.
+pub(crate) fn extract_links(text: &str, hosts: &BTreeSet<String>) -> Vec<Url> {
+    // ASCII lowercasing keeps the byte offsets of the text
+    let lowercase = text.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut position = 0;
+    while let Some(start) = ["http://", "https://"]
+        .iter()
+        .filter_map(|scheme| lowercase[position..].find(scheme))
+        .min()
+    {
+        let candidate = &text[position + start..];
+        let end = candidate
+            .find(|char: char| char.is_whitespace() || ['"', '\'', '<', '>'].contains(&char))
+            .unwrap_or(candidate.len());
+        position += start + end;
+        let link = candidate[..end]
+            .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']'])
+            .replace("&amp;", "&");
+        if let Ok(url) = Url::parse(&link) {
+            if is_allowed_host(&url, hosts) && !links.contains(&url) {
+                links.push(url);
+            }
+        }
+    }
+    links
+}
+
+// Whether a URL is an http or https URL of an allowed host, where `*.example.com` allows the subdomains of
+// `example.com`
+pub(crate) fn is_allowed_host(url: &Url, hosts: &BTreeSet<String>) -> bool {
+    let Some(host) = url.host_str().map(str::to_lowercase) else {
+        return false;
+    };
+    matches!(url.scheme(), "http" | "https")
+        && hosts
+            .iter()
+            .map(|allowed| allowed.to_lowercase())
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => host == allowed,
+            })
+}
+
+// Downloads a linked file, following redirects as long as they stay on the allowed hosts. Web pages, such as the
+// landing page of a share that needs a password, files over the size limit and plain http links, unless allowed, are
+// rejected. A single deadline applies to the whole download, so that each redirect only gets the time left.
+pub(crate) fn download_linked_file(
+    url: &Url,
+    hosts: &BTreeSet<String>,
+    limits: LinkLimits,
+) -> Result<LinkedFile, ImapAttachmentDaemonError> {
+    let connector = TlsConnector::new().map_err(|err| link_error(url, err.to_string()))?;
+    let agent = AgentBuilder::new()
+        .redirects(0)
+        .tls_connector(Arc::new(connector))
+        .build();
+    let deadline = Instant::now() + limits.timeout;
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        if url.scheme() != "https" && limits.schemes == LinkSchemes::Https {
+            return Err(link_error(&url, "plain http links are not allowed".to_string()));
+        }
+        let time_left = deadline.saturating_duration_since(Instant::now());
+        if time_left.is_zero() {
+            return Err(link_error(&url, "timed out".to_string()));
+        }
+        // The timeout of a request also covers the reading of its response body
+        let response = agent
+            .request_url("GET", &url)
+            .timeout(time_left)
+            .call()
+            .map_err(|err| link_error(&url, err.to_string()))?;
+        if !(300..400).contains(&response.status()) {
+            return read_linked_file(&url, response, limits);
+        }
+        let location = response
+            .header("Location")
+            .ok_or_else(|| link_error(&url, "redirect without location".to_string()))?;
+        let redirect = url.join(location).map_err(|err| link_error(&url, err.to_string()))?;
+        if !is_allowed_host(&redirect, hosts) {
+            return Err(link_error(
+                &url,
+                format!("redirected to {redirect}, which is not an allowed host"),
+            ));
+        }
+        url = redirect;
+    }
+    Err(link_error(&url, "too many redirects".to_string()))
+}
+
+fn read_linked_file(
+    url: &Url,
+    response: Response,
+    limits: LinkLimits,
+) -> Result<LinkedFile, ImapAttachmentDaemonError> {
+    let mime_type = response
+        .header("Content-Type")
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime_type| mime_type.trim().to_lowercase())
+        .filter(|mime_type| !mime_type.is_empty());
+    if mime_type.as_deref() == Some("text/html") {
+        return Err(link_error(url, "the link is a web page, not a file".to_string()));
+    }
+    let over_limit = || link_error(url, format!("over the limit of {} bytes", limits.size));
+    if response
+        .header("Content-Length")
+        .and_then(|length| length.trim().parse::<u64>().ok())
+        .is_some_and(|length| length > limits.size)
+    {
+        return Err(over_limit());
+    }
+    let filename = response
+        .header("Content-Disposition")
+        .and_then(disposition_filename)
+        .or_else(|| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        });
+    let mut content = Vec::new();
+    let _ = response
+        .into_reader()
+        .take(limits.size.saturating_add(1))
+        .read_to_end(&mut content)
+        .map_err(|err| link_error(url, err.to_string()))?;
+    if content.len() as u64 > limits.size {
+        return Err(over_limit());
+    }
+    Ok(LinkedFile {
+        filename,
+        mime_type,
+        content,
+    })
+}
+
+// The filename of a `Content-Disposition` header, preferring the RFC 5987 `filename*=UTF-8''book%20name.epub` form
+fn disposition_filename(disposition: &str) -> Option<String> {
+    let parameters = disposition.split(';').skip(1).filter_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        Some((name.trim().to_lowercase(), value.trim()))
+    });
+    let mut filename = None;
+    for (name, value) in parameters {
+        match name.as_str() {
+            "filename*" => {
+                if let Some((_, encoded)) = value.rsplit_once('\'') {
+                    return Some(percent_decode_str(encoded).decode_utf8_lossy().to_string());
+                }
+            }
+            "filename" => filename = Some(value.trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    filename.filter(|filename| !filename.is_empty())
+}
+
+fn link_error(url: &Url, reason: String) -> ImapAttachmentDaemonError {
+    ImapAttachmentDaemonError::LinkDownloadError {
+        url: url.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+#[path = "test_linked_files.rs"]
+pub(crate) mod test_linked_files;
@@ -0,0 +1,223 @@
+use crate::errors::ImapAttachmentDaemonError;
+use crate::mail_parsing::{
+    extract_descriptors, format_email_metadata_message, is_dkim_verified, is_within_size_limits, log_target_tag,
+    parse_message, record_sender_usage, save_attachments, sender_verdict, subject_commands, SenderVerdict,
+};
+use crate::models::ProcessingSummary;
+use crate::AppConfig;
+
+use mail_parser::mailbox::mbox::MessageIterator;
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+// Maildir flags set on processed emails, standing in for marking them as read and moving them to the trash
+const PROCESSED_MAILDIR_FLAGS: [char; 2] = ['S', 'T'];
+
+#[derive(Debug, PartialEq, Eq)]
+enum LocalSource {
+    Maildir(PathBuf),
+    EmlDirectory(PathBuf),
+    EmlFile(PathBuf),
+    Mbox(PathBuf),
+}
+
+impl LocalSource {
+    fn detect(path: &Path) -> Result<Self, ImapAttachmentDaemonError> {
+        if path.join("cur").is_dir() && path.join("new").is_dir() {
+            Ok(Self::Maildir(path.to_path_buf()))
+        } else if path.is_dir() {
+            Ok(Self::EmlDirectory(path.to_path_buf()))
+        } else if path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("eml")) {
+            Ok(Self::EmlFile(path.to_path_buf()))
+        } else if path.is_file() {
+            Ok(Self::Mbox(path.to_path_buf()))
+        } else {
+            Err(ImapAttachmentDaemonError::UnsupportedLocalSource(path.to_path_buf()))
+        }
+    }
+}
+
+pub(crate) fn local_source_processing(
+    source: &Path,
+    config: &AppConfig,
+) -> Result<ProcessingSummary, ImapAttachmentDaemonError> {
+    let mut summary = ProcessingSummary::default();
+    match LocalSource::detect(source)? {
+        LocalSource::Maildir(path) => {
+            log::info!("Processing unread emails in Maildir {}", path.display());
+            for email_path in maildir_unread_emails(&path)? {
+                let outcome = fs::read(&email_path)
+                    .map_err(Into::into)
+                    .and_then(|raw_bytes| process_local_email(&raw_bytes, Some((&path, &email_path)), config));
+                record_outcome(&mut summary, &email_path.display().to_string(), outcome);
+            }
+        }
+        LocalSource::EmlDirectory(path) => {
+            log::info!("Processing .eml files in {}", path.display());
+            let mut email_paths = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+            email_paths.retain(|email_path| {
+                email_path.is_file()
+                    && email_path
+                        .extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+            });
+            email_paths.sort();
+            for email_path in email_paths {
+                let outcome = fs::read(&email_path)
+                    .map_err(Into::into)
+                    .and_then(|raw_bytes| process_local_email(&raw_bytes, None, config));
+                record_outcome(&mut summary, &email_path.display().to_string(), outcome);
+            }
+        }
+        LocalSource::EmlFile(path) => {
+            log::info!("Processing .eml file {}", path.display());
+            let outcome = fs::read(&path)
+                .map_err(Into::into)
+                .and_then(|raw_bytes| process_local_email(&raw_bytes, None, config));
+            record_outcome(&mut summary, &path.display().to_string(), outcome);
+        }
+        LocalSource::Mbox(path) => {
+            log::info!("Processing mbox file {}", path.display());
+            for (index, message) in MessageIterator::new(BufReader::new(File::open(&path)?)).enumerate() {
+                let outcome = message
+                    .map_err(Into::into)
+                    .and_then(|message| process_local_email(message.contents(), None, config));
+                record_outcome(&mut summary, &format!("#{index} in {}", path.display()), outcome);
+            }
+        }
+    }
+    log::info!("All local emails processed ({summary})");
+    Ok(summary)
+}
+
+fn record_outcome(summary: &mut ProcessingSummary, email: &str, outcome: Result<bool, ImapAttachmentDaemonError>) {
+    match outcome {
+        Ok(true) => summary.record_processed(),
+        Ok(false) => summary.record_skipped(),
+        Err(err) => {
+            log::error!("Failed to process email {email}: {err}");
+            summary.record_failed();
+        }
+    }
+}
+
+// Processes a local email, marking it as processed when it comes from a Maildir, given with the path of the email
+fn process_local_email(
+    raw_bytes: &[u8],
+    maildir_email: Option<(&Path, &Path)>,
+    config: &AppConfig,
+) -> Result<bool, ImapAttachmentDaemonError> {
+    let parsed_email = parse_message(raw_bytes)?;
+    let message_metadata = extract_descriptors(&parsed_email)?;
+    match sender_verdict(&message_metadata, config)? {
+        SenderVerdict::Accepted => {}
+        // Local sources are only read, so blocked emails are left untouched whatever the blocked action
+        SenderVerdict::Blocked => {
+            log::info!(
+                "Email {} is from a blocked sender, skipping",
+                format_email_metadata_message(&message_metadata)
+            );
+            return Ok(false);
+        }
+        SenderVerdict::Ignored => {
+            log::debug!(
+                "Email {} is not from the whitelist, skipping",
+                format_email_metadata_message(&message_metadata)
+            );
+            return Ok(false);
+        }
+    }
+    // Local sources are only read, so oversized emails are skipped whatever the oversized action
+    let size = u64::try_from(raw_bytes.len()).unwrap_or(u64::MAX);
+    if !is_within_size_limits(None, size, &message_metadata, config) {
+        return Ok(false);
+    }
+    if !is_dkim_verified(raw_bytes, &message_metadata, config)? {
+        return Ok(false);
+    }
+    log_target_tag(&message_metadata, config);
+    let commands = subject_commands(&message_metadata, config);
+    if save_attachments(&parsed_email, &message_metadata, &commands, config)?.is_some() {
+        // Test sends are saved, and only leave the email as it is
+        record_sender_usage(None, size, &message_metadata, config.dry_run, config);
+        if let Some((maildir, email_path)) = maildir_email {
+            mark_maildir_email_as_processed(maildir, email_path, config.dry_run || commands.dry)?;
+        }
+        return Ok(true);
+    }
+    log::info!(
+        "No accepted attachments found in email {}, leaving it unread",
+        format_email_metadata_message(&message_metadata)
+    );
+    Ok(false)
+}
+
+// Lists the emails in `new/` and the emails in `cur/` without the seen or trashed flags, mirroring an IMAP search for
+// unseen emails.
+fn maildir_unread_emails(maildir: &Path) -> Result<Vec<PathBuf>, ImapAttachmentDaemonError> {
+    let mut email_paths = Vec::new();
+    for subdirectory in ["new", "cur"] {
+        for entry in fs::read_dir(maildir.join(subdirectory))? {
+            let email_path = entry?.path();
+            let Some(filename) = email_path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if filename.starts_with('.') || !email_path.is_file() {
+                continue;
+            }
+            let (_, flags) = split_maildir_filename(filename);
+            if !flags.contains(['S', 'T']) {
+                email_paths.push(email_path);
+            }
+        }
+    }
+    email_paths.sort();
+    Ok(email_paths)
+}
+
+// Splits a Maildir filename into its unique name and its flags, see <https://cr.yp.to/proto/maildir.html>
+fn split_maildir_filename(filename: &str) -> (&str, &str) {
+    filename
+        .rsplit_once(":2,")
+        .or_else(|| filename.rsplit_once("!2,"))
+        .unwrap_or((filename, ""))
+}
+
+fn mark_maildir_email_as_processed(
+    maildir: &Path,
+    email_path: &Path,
+    dry_run: bool,
+) -> Result<(), ImapAttachmentDaemonError> {
+    let filename = email_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(ImapAttachmentDaemonError::FilenameMissing)?;
+    let processed_path = maildir.join("cur").join(processed_maildir_filename(filename));
+    if dry_run {
+        log::info!(
+            "Dry run: would move email {} to {}",
+            email_path.display(),
+            processed_path.display()
+        );
+        return Ok(());
+    }
+    fs::rename(email_path, &processed_path)?;
+    log::debug!("Moved email to {}", processed_path.display());
+    Ok(())
+}
+
+fn processed_maildir_filename(filename: &str) -> String {
+    let (unique_name, flags) = split_maildir_filename(filename);
+    let mut flags = flags.chars().chain(PROCESSED_MAILDIR_FLAGS).collect::<Vec<char>>();
+    flags.sort_unstable();
+    flags.dedup();
+    format!("{unique_name}:2,{}", flags.into_iter().collect::<String>())
+}
+
+#[cfg(test)]
+#[path = "test_local_sources.rs"]
+mod test_local_sources;
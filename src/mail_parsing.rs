@@ -1,77 +1,676 @@
-use crate::imap_ops::{mark_email_as_unread, move_email_to_trash};
-use crate::models::MessageMetadata;
+use crate::archives::{archive_format, extract_archive, ExtractedFile};
+use crate::content_type::{infer_extension, verify_content_type};
+use crate::dkim::verify_dkim;
+use crate::embedded_files::decode_embedded_files;
+use crate::linked_files::{body_links, download_linked_file, MAX_LINKS};
+use crate::mail_store::{handle_oversized_email, post_process_email, FetchedEmail, MailStore};
+use crate::models::{
+    current_day, is_accepted_file_type, ArchiveFormat, AttachmentFacts, EmailFacts, ExistingFiles, InlineParts,
+    MessageMetadata, PostProcess, ProcessingSummary, RequiredSignature, RuleAction, SubjectCommands,
+};
+use crate::secure_mime::open_email;
+use crate::sender_authentication::is_sender_authenticated;
+use crate::tnef::{decode_tnef, is_tnef};
 use crate::{AppConfig, ImapAttachmentDaemonError};
-use imap::types::{Fetch, Fetches};
-use mail_parser::{Message, MessageParser, MessagePart, MimeHeaders};
+use mail_parser::{Addr, Address, Message, MessageParser, MessagePart, MimeHeaders};
 use std::{
     fs::File,
     io::Write,
     path::{Path, PathBuf},
+    sync::PoisonError,
 };
 
-use imap::{ImapConnection, Session};
-
 pub(crate) fn parse_and_process_emails(
     config: &AppConfig,
-    fetched_emails: &Fetches,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<(), ImapAttachmentDaemonError> {
-    for message in fetched_emails.iter() {
-        let uid = message.uid.ok_or(ImapAttachmentDaemonError::UidMissing)?.to_string();
-        let parsed_email = parse_body(message)?;
-        let message_metadata = extract_descriptors(&parsed_email)?;
-        let saved_attachments = parsed_email
-            .attachments()
-            .map(|x| check_and_save_attachment(x, &message_metadata, config))
-            .collect::<Result<Vec<bool>, ImapAttachmentDaemonError>>()?;
-        if saved_attachments.iter().any(|&saved| saved) {
-            move_email_to_trash(&uid, imap_session)?;
+    fetched_emails: &[FetchedEmail],
+    store: &mut impl MailStore,
+) -> ProcessingSummary {
+    let mut summary = ProcessingSummary::default();
+    for message in fetched_emails {
+        match process_email(config, message, store) {
+            Ok(true) => summary.record_processed(),
+            Ok(false) => summary.record_skipped(),
+            Err(err) => {
+                log::error!("Failed to process email with UID {}: {}", message.uid, err);
+                summary.record_failed();
+            }
+        }
+    }
+    log::info!("All emails processed ({summary})");
+    summary
+}
+
+fn process_email(
+    config: &AppConfig,
+    message: &FetchedEmail,
+    store: &mut impl MailStore,
+) -> Result<bool, ImapAttachmentDaemonError> {
+    let parsed_email = parse_message(&message.raw)?;
+    let message_metadata = extract_descriptors(&parsed_email)?;
+    if !is_dkim_verified(&message.raw, &message_metadata, config)? {
+        return Ok(false);
+    }
+    // Emails saved earlier in the same search may have brought the sender over the daily limit since the filtering
+    if !is_within_size_limits(Some(message.uid), message.size, &message_metadata, config) {
+        handle_oversized_email(message.uid, store, config)?;
+        return Ok(false);
+    }
+    log_target_tag(&message_metadata, config);
+    let commands = subject_commands(&message_metadata, config);
+    if let Some(post_process) = save_attachments(&parsed_email, &message_metadata, &commands, config)? {
+        // Test sends are saved, and only leave the email as it is
+        record_sender_usage(
+            Some(message.uid),
+            message.size,
+            &message_metadata,
+            config.dry_run,
+            config,
+        );
+        post_process_email(message.uid, store, &post_process, config.dry_run || commands.dry)?;
+        return Ok(true);
+    }
+    // Bodies are fetched with BODY.PEEK[], so the email is still unread and nothing needs to be changed
+    log::info!(
+        "No accepted attachments found in email {}, leaving it unread",
+        format_email_metadata_message(&message_metadata)
+    );
+    Ok(false)
+}
+
+// Saves the accepted attachments of an email, returning the post-processing of the first saved attachment, if any.
+// Encrypted emails are decrypted first, and emails without the required signature by their sender are skipped.
+pub(crate) fn save_attachments(
+    parsed_email: &Message,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let keys = config.secure_mime_keys()?;
+    if keys.is_empty() && config.require_signature == RequiredSignature::None {
+        return save_nested_attachments(parsed_email, "", 0, message_metadata, commands, config);
+    }
+    let opened_email = open_email(parsed_email, keys)?;
+    if !opened_email.is_signed_by(message_metadata.from(), config.require_signature) {
+        log::warn!(
+            "Email {} is not signed by its sender with a trusted key, treating its sender as not whitelisted",
+            format_email_metadata_message(message_metadata)
+        );
+        return Ok(None);
+    }
+    match &opened_email.content {
+        Some(content) => {
+            let signers = opened_email
+                .signers
+                .iter()
+                .map(|signer| signer.email.as_str())
+                .collect::<Vec<&str>>();
+            log::info!(
+                "Opened the encrypted or signed email {}, {}",
+                format_email_metadata_message(message_metadata),
+                if signers.is_empty() {
+                    "without a valid signature by a trusted signer".to_string()
+                } else {
+                    format!("with valid signatures by {}", signers.join(", "))
+                }
+            );
+            let inner_email = parse_message(content)?;
+            save_nested_attachments(&inner_email, "", 0, message_metadata, commands, config)
+        }
+        None => save_nested_attachments(parsed_email, "", 0, message_metadata, commands, config),
+    }
+}
+
+// Saves the attachments of an email or of a forwarded email attached to it, up to the maximum nesting depth. The
+// attachments of forwarded emails are checked as attachments of the outer email, and their location is the path of
+// their parts, such as `2.1` for the first attachment of the email forwarded as the second attachment.
+fn save_nested_attachments(
+    message: &Message,
+    path: &str,
+    depth: usize,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let mut post_process = None;
+    for (index, part) in attachment_parts(message, config).into_iter().enumerate() {
+        let location = format!("{path}{}", index + 1);
+        let saved = match part.message() {
+            Some(_) if depth >= config.max_nesting_depth => {
+                log::info!(
+                    "Not looking into the email forwarded as part {location} of email {}, nested too deep",
+                    format_email_metadata_message(message_metadata)
+                );
+                None
+            }
+            Some(forwarded_email) => {
+                log::debug!(
+                    "Looking into the email forwarded as part {location} of email {}",
+                    format_email_metadata_message(message_metadata)
+                );
+                let path = format!("{location}.");
+                save_nested_attachments(forwarded_email, &path, depth + 1, message_metadata, commands, config)?
+            }
+            None => save_part(part, location, message_metadata, commands, config)?,
+        };
+        if post_process.is_none() {
+            post_process = saved;
+        }
+    }
+    if !config.embedded_encodings.is_empty() {
+        let saved = save_embedded_files(message, path, message_metadata, commands, config)?;
+        if post_process.is_none() {
+            post_process = saved;
+        }
+    }
+    if !config.link_hosts.is_empty() {
+        let saved = save_linked_files(message, path, message_metadata, commands, config)?;
+        if post_process.is_none() {
+            post_process = saved;
+        }
+    }
+    Ok(post_process)
+}
+
+// Saves the files embedded in the plain text body of an email as uuencoded or yEnc blocks, located as `text/book.epub`
+// for the file `book.epub`
+fn save_embedded_files(
+    message: &Message,
+    path: &str,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let mut post_process = None;
+    for &part_id in &message.text_body {
+        let Some(part) = message.parts.get(part_id) else {
+            continue;
+        };
+        if message.attachments.contains(&part_id) || mime_type(part).is_some_and(|mime_type| mime_type != "text/plain")
+        {
+            continue;
+        }
+        let files = decode_embedded_files(text_bytes(message, part), &config.embedded_encodings);
+        if files.is_empty() {
             continue;
         }
         log::info!(
-            "No accepted attachments found in email {}, marking as unread",
-            format_email_metadata_message(&message_metadata)
+            "Decoded {} files embedded in the text body of email {}",
+            files.len(),
+            format_email_metadata_message(message_metadata)
         );
-        mark_email_as_unread(&uid, imap_session)?;
+        let saved = save_extracted_files(&format!("{path}text"), &files, message_metadata, commands, config)?;
+        if post_process.is_none() {
+            post_process = saved;
+        }
     }
-    log::info!("All emails processed, waiting for new emails");
-    Ok(())
+    Ok(post_process)
 }
 
-pub(crate) fn parse_body<'a>(fetch: &'a Fetch) -> Result<Message<'a>, ImapAttachmentDaemonError> {
-    let raw_bytes = fetch.body().ok_or(ImapAttachmentDaemonError::BodyMissing)?;
+// Downloads and saves the files linked in the text and HTML bodies of an email from the allowed hosts, located as
+// `link/1` for the first link. Links that cannot be downloaded are skipped.
+fn save_linked_files(
+    message: &Message,
+    path: &str,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let mut post_process = None;
+    for (index, url) in body_links(message, &config.link_hosts)
+        .iter()
+        .take(MAX_LINKS)
+        .enumerate()
+    {
+        let file = match download_linked_file(url, &config.link_hosts, config.link_limits()) {
+            Ok(file) => file,
+            Err(err) => {
+                log::warn!(
+                    "Skipped link in email {}: {}",
+                    format_email_metadata_message(message_metadata),
+                    err
+                );
+                continue;
+            }
+        };
+        log::info!(
+            "Downloaded {} bytes from link {url} in email {}",
+            file.content.len(),
+            format_email_metadata_message(message_metadata)
+        );
+        let location = format!("{path}link/{}", index + 1);
+        let attachment = Attachment {
+            filename: attachment_filename(
+                file.filename.as_deref(),
+                &file.content,
+                &location,
+                message_metadata,
+                file.mime_type.as_deref(),
+            ),
+            mime_type: file.mime_type.clone(),
+            content: &file.content,
+            location,
+        };
+        let saved = save_file(
+            &attachment,
+            file.filename.as_deref(),
+            message_metadata,
+            commands,
+            config,
+        )?;
+        if post_process.is_none() {
+            post_process = saved;
+        }
+    }
+    Ok(post_process)
+}
+
+// The bytes of a text part, as sent when it has no transfer encoding, since decoding its charset would garble the 8-bit
+// yEnc content
+fn text_bytes<'a>(message: &'a Message, part: &'a MessagePart) -> &'a [u8] {
+    let is_unencoded = part.content_transfer_encoding().is_none_or(|encoding| {
+        ["7bit", "8bit", "binary"]
+            .iter()
+            .any(|unencoded| encoding.eq_ignore_ascii_case(unencoded))
+    });
+    is_unencoded
+        .then(|| message.raw_message.get(part.offset_body..part.offset_end))
+        .flatten()
+        .unwrap_or_else(|| part.contents())
+}
+
+// Saves an attachment, the files embedded in a `winmail.dat` attachment, or the accepted files of an archive attachment
+// when its format is unpacked. Attachments are named after their filename or their `Content-Location`.
+fn save_part(
+    part: &MessagePart,
+    location: String,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let mime_type = mime_type(part);
+    let name = part.attachment_name().or_else(|| {
+        part.content_location()
+            .and_then(|location| location.split(['?', '#']).next())
+    });
+    let attachment = Attachment {
+        filename: attachment_filename(name, part.contents(), &location, message_metadata, mime_type.as_deref()),
+        mime_type,
+        content: part.contents(),
+        location,
+    };
+    save_file(&attachment, part.attachment_name(), message_metadata, commands, config)
+}
+
+// Saves a file as an attachment, the files embedded in it when it is a `winmail.dat` file named as such or by its MIME
+// type, or its accepted files when it is an archive whose format is unpacked
+fn save_file(
+    attachment: &Attachment,
+    name: Option<&str>,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    if is_tnef(name, attachment.mime_type.as_deref()) {
+        return save_tnef_files(attachment, message_metadata, commands, config);
+    }
+    match archive_format(&attachment.filename.to_string_lossy(), &config.extract_archives) {
+        Some(format) => save_archive_files(attachment, format, message_metadata, commands, config),
+        None => check_and_save_attachment(attachment, message_metadata, commands, config),
+    }
+}
+
+// Saves the accepted files of an archive attachment, located as `2/books/dune.epub` for the file `books/dune.epub` of
+// the second attachment. Archives that cannot be read or exceed the limits are skipped.
+fn save_archive_files(
+    archive: &Attachment,
+    format: ArchiveFormat,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let is_accepted = |name: &str| {
+        let extension = Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let attachment = AttachmentFacts {
+            extension: extension.as_deref(),
+            ..Default::default()
+        };
+        is_accepted_file_type(&config.accepted_file_types, &attachment)
+    };
+    let files = match extract_archive(
+        archive.content,
+        format,
+        &config.extract_archives,
+        &is_accepted,
+        config.archive_limits(),
+    ) {
+        Ok(files) => files,
+        Err(err) => {
+            log::warn!(
+                "Skipped archive {} in email {}: {}",
+                archive.description(),
+                format_email_metadata_message(message_metadata),
+                err
+            );
+            return Ok(None);
+        }
+    };
+    log::info!(
+        "Extracted {} accepted files from archive {} in email {}",
+        files.len(),
+        archive.description(),
+        format_email_metadata_message(message_metadata)
+    );
+    save_extracted_files(&archive.location, &files, message_metadata, commands, config)
+}
+
+// Saves the attachments embedded in a `winmail.dat` (TNEF) attachment, as sent by Outlook, located as `2/book.epub` for
+// the file `book.epub` of the second attachment. Attachments that cannot be decoded are skipped.
+fn save_tnef_files(
+    tnef: &Attachment,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let files = match decode_tnef(tnef.content) {
+        Ok(files) => files,
+        Err(err) => {
+            log::warn!(
+                "Skipped TNEF attachment {} in email {}: {}",
+                tnef.description(),
+                format_email_metadata_message(message_metadata),
+                err
+            );
+            return Ok(None);
+        }
+    };
+    log::info!(
+        "Decoded {} files from TNEF attachment {} in email {}",
+        files.len(),
+        tnef.description(),
+        format_email_metadata_message(message_metadata)
+    );
+    save_extracted_files(&tnef.location, &files, message_metadata, commands, config)
+}
+
+// Checks and saves the files extracted from the part at a location, as attachments named after their lowercase filename
+fn save_extracted_files(
+    location: &str,
+    files: &[ExtractedFile],
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let mut post_process = None;
+    for file in files {
+        let Some(name) = safe_filename(&file.path) else {
+            continue;
+        };
+        let attachment = Attachment {
+            filename: PathBuf::from(name.to_lowercase()),
+            mime_type: None,
+            content: &file.content,
+            location: format!("{location}/{}", file.path),
+        };
+        let saved = check_and_save_attachment(&attachment, message_metadata, commands, config)?;
+        if post_process.is_none() {
+            post_process = saved;
+        }
+    }
+    Ok(post_process)
+}
+
+// The attachments of an email followed, when enabled, by the inline parts of its body that carry a filename, or that are
+// not text and have an accepted MIME type. Parts that are both in the body and attachments are only returned once.
+fn attachment_parts<'a>(message: &'a Message, config: &AppConfig) -> Vec<&'a MessagePart<'a>> {
+    let mut part_ids = message.attachments.clone();
+    if config.inline_parts == InlineParts::Save {
+        for &part_id in message.text_body.iter().chain(&message.html_body) {
+            let Some(part) = message.parts.get(part_id) else {
+                continue;
+            };
+            let has_accepted_mime_type = || {
+                !part.is_text()
+                    && mime_type(part).is_some_and(|mime_type| {
+                        let attachment = AttachmentFacts {
+                            mime_type: Some(&mime_type),
+                            ..Default::default()
+                        };
+                        is_accepted_file_type(&config.accepted_file_types, &attachment)
+                    })
+            };
+            if !part_ids.contains(&part_id) && (part.attachment_name().is_some() || has_accepted_mime_type()) {
+                part_ids.push(part_id);
+            }
+        }
+    }
+    part_ids
+        .iter()
+        .filter_map(|&part_id| message.parts.get(part_id))
+        .collect()
+}
+
+pub(crate) fn parse_message(raw_bytes: &[u8]) -> Result<Message<'_>, ImapAttachmentDaemonError> {
     MessageParser::default()
         .parse(raw_bytes)
         .ok_or(ImapAttachmentDaemonError::ParsingError)
 }
 
+// The UIDs of the emails to process, of the emails exceeding the size limits and of the emails from blocked senders, all
+// sent to the target address
+#[derive(Debug, Default)]
+pub(crate) struct FilteredMessages {
+    // Along with their size, to fetch them in batches
+    pub accepted: Vec<(u32, u64)>,
+    pub oversized: Vec<u32>,
+    pub blocked: Vec<u32>,
+}
+
+impl FilteredMessages {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.accepted.is_empty() && self.oversized.is_empty() && self.blocked.is_empty()
+    }
+}
+
 pub(crate) fn filter_messages_by_source_and_whitelist(
-    messages_headers: &Fetches,
+    messages_headers: &[FetchedEmail],
     config: &AppConfig,
-) -> Result<Vec<u32>, ImapAttachmentDaemonError> {
-    let mut accepted_messages = Vec::new();
-    for message_header in messages_headers.iter() {
-        let message = parse_header(message_header)?;
+) -> Result<FilteredMessages, ImapAttachmentDaemonError> {
+    let mut filtered_messages = FilteredMessages::default();
+    for message_header in messages_headers {
+        let message = parse_message(&message_header.raw)?;
         let message_descriptors = extract_descriptors(&message)?;
-        if message_descriptors
-            .to()
-            .contains(&config.target_address.as_ref().unwrap_or(&config.username).as_str())
-            && config.whitelist.contains(message_descriptors.from())
-        {
-            accepted_messages.push(message_header.uid.ok_or(ImapAttachmentDaemonError::UidMissing)?);
+        match sender_verdict(&message_descriptors, config)? {
+            SenderVerdict::Accepted
+                if is_within_size_limits(
+                    Some(message_header.uid),
+                    message_header.size,
+                    &message_descriptors,
+                    config,
+                ) =>
+            {
+                filtered_messages
+                    .accepted
+                    .push((message_header.uid, message_header.size));
+            }
+            SenderVerdict::Accepted => filtered_messages.oversized.push(message_header.uid),
+            SenderVerdict::Blocked => filtered_messages.blocked.push(message_header.uid),
+            SenderVerdict::Ignored => {}
         }
     }
-    Ok(accepted_messages)
+    Ok(filtered_messages)
 }
 
-pub(crate) fn parse_header<'a>(fetch: &'a Fetch) -> Result<Message<'a>, ImapAttachmentDaemonError> {
-    let raw_bytes = fetch.header().ok_or(ImapAttachmentDaemonError::HeaderMissing)?;
-    MessageParser::default()
-        .parse(raw_bytes)
-        .ok_or(ImapAttachmentDaemonError::ParsingError)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SenderVerdict {
+    // Sent to the target address by a whitelisted sender that is not blocked
+    Accepted,
+    // Sent to the target address by a blocked sender, whether whitelisted or not
+    Blocked,
+    // Sent to another address, by a sender that is not whitelisted, or by a whitelisted sender that failed the
+    // required authentication
+    Ignored,
 }
 
-fn format_email_metadata_message(message_metadata: &MessageMetadata) -> String {
+// The destination of an email matching the target address, either exactly or as a sub-address such as
+// `user+books@example.com` for `user@example.com`, with its tag exposed for routing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TargetDestination<'a> {
+    pub address: &'a str,
+    pub tag: Option<&'a str>,
+}
+
+pub(crate) fn target_destination<'a>(
+    message_descriptors: &MessageMetadata<'a>,
+    config: &AppConfig,
+) -> Option<TargetDestination<'a>> {
+    let target = config.target_address.as_ref().unwrap_or(&config.username);
+    let (target_user, target_domain) = target.rsplit_once('@')?;
+    message_descriptors.destinations().iter().find_map(|&address| {
+        if address.eq_ignore_ascii_case(target) {
+            return Some(TargetDestination { address, tag: None });
+        }
+        // A target address with a tag only matches exactly
+        let (user, domain) = address.rsplit_once('@')?;
+        let (user, tag) = user.split_once('+')?;
+        (!target_user.contains('+')
+            && user.eq_ignore_ascii_case(target_user)
+            && domain.eq_ignore_ascii_case(target_domain))
+        .then_some(TargetDestination {
+            address,
+            tag: Some(tag),
+        })
+    })
+}
+
+pub(crate) fn sender_verdict(
+    message_descriptors: &MessageMetadata,
+    config: &AppConfig,
+) -> Result<SenderVerdict, ImapAttachmentDaemonError> {
+    if target_destination(message_descriptors, config).is_none() {
+        return Ok(SenderVerdict::Ignored);
+    }
+    // The blocklist takes precedence, so that `noreply@example.com` can be blocked while `@example.com` is whitelisted
+    if config.blocklist_patterns()?.matches(message_descriptors.from()) {
+        Ok(SenderVerdict::Blocked)
+    } else if !config.whitelist_patterns()?.matches(message_descriptors.from()) {
+        Ok(SenderVerdict::Ignored)
+    } else if is_sender_authenticated(message_descriptors, config) {
+        Ok(SenderVerdict::Accepted)
+    } else {
+        // The whitelist matches the `From:` header, which can be forged
+        log::warn!(
+            "Email {} failed sender authentication, ignoring it",
+            format_email_metadata_message(message_descriptors)
+        );
+        Ok(SenderVerdict::Ignored)
+    }
+}
+
+// Checks the size of an email against the message size limit, then against the daily size limit of its sender. Emails
+// only count towards the daily size of their sender once saved, see `record_sender_usage`.
+pub(crate) fn is_within_size_limits(
+    uid: Option<u32>,
+    size: u64,
+    message_metadata: &MessageMetadata,
+    config: &AppConfig,
+) -> bool {
+    if let Some(max_message_size) = config
+        .max_message_size
+        .filter(|&max_message_size| size > max_message_size)
+    {
+        log::warn!(
+            "Email {} is {size} bytes, over the limit of {max_message_size} bytes",
+            format_email_metadata_message(message_metadata)
+        );
+        return false;
+    }
+    let Some(max_daily_sender_size) = config.max_daily_sender_size else {
+        return true;
+    };
+    let fits = config.sender_usage.lock().unwrap_or_else(PoisonError::into_inner).fits(
+        uid,
+        message_metadata.from(),
+        size,
+        current_day(),
+        max_daily_sender_size,
+    );
+    if !fits {
+        log::warn!(
+            "Email {} would bring its sender over the daily limit of {max_daily_sender_size} bytes",
+            format_email_metadata_message(message_metadata)
+        );
+    }
+    fits
+}
+
+// Counts a saved email towards the daily size of its sender, unless it was only saved in a dry run
+pub(crate) fn record_sender_usage(
+    uid: Option<u32>,
+    size: u64,
+    message_metadata: &MessageMetadata,
+    dry_run: bool,
+    config: &AppConfig,
+) {
+    if dry_run || config.max_daily_sender_size.is_none() {
+        return;
+    }
+    config
+        .sender_usage
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .record(uid, message_metadata.from(), size, current_day());
+}
+
+// Verifies the DKIM signature of the full email when enabled, since headers alone cannot be verified. Emails that fail
+// the verification are treated as coming from a sender that is not whitelisted.
+pub(crate) fn is_dkim_verified(
+    raw_bytes: &[u8],
+    message_metadata: &MessageMetadata,
+    config: &AppConfig,
+) -> Result<bool, ImapAttachmentDaemonError> {
+    if !config.verify_dkim {
+        return Ok(true);
+    }
+    let from_domain = message_metadata
+        .from()
+        .rsplit_once('@')
+        .map_or("", |(_, domain)| domain);
+    match verify_dkim(raw_bytes, from_domain, config.dkim_key_resolver()?) {
+        Ok(()) => Ok(true),
+        Err(failure) => {
+            log::warn!(
+                "Email {} failed DKIM verification ({failure}), treating its sender as not whitelisted",
+                format_email_metadata_message(message_metadata)
+            );
+            Ok(false)
+        }
+    }
+}
+
+pub(crate) fn log_target_tag(message_metadata: &MessageMetadata, config: &AppConfig) {
+    if let Some(tag) = target_destination(message_metadata, config).and_then(|destination| destination.tag) {
+        log::info!(
+            "Email {} was sent to the sub-address tag {tag:?}",
+            format_email_metadata_message(message_metadata)
+        );
+    }
+}
+
+// Parses the enabled subject commands of an email, logging them when present
+pub(crate) fn subject_commands(message_metadata: &MessageMetadata, config: &AppConfig) -> SubjectCommands {
+    let commands = SubjectCommands::parse(message_metadata.subject().unwrap_or_default(), &config.subject_commands);
+    if !commands.is_empty() {
+        log::info!(
+            "Email {} has subject commands: {commands:?}",
+            format_email_metadata_message(message_metadata)
+        );
+    }
+    commands
+}
+
+pub(crate) fn format_email_metadata_message(message_metadata: &MessageMetadata) -> String {
     format!(
         "from {} {}",
         message_metadata.from(),
@@ -83,40 +682,275 @@ fn format_email_metadata_message(message_metadata: &MessageMetadata) -> String {
     )
 }
 
-pub(crate) fn check_and_save_attachment(
-    part: &MessagePart,
+// An attachment to check and save, either a part of an email or a file extracted from an archive attachment
+struct Attachment<'a> {
+    filename: PathBuf,
+    mime_type: Option<String>,
+    content: &'a [u8],
+    // The path of the part in the email, followed by the path of the file in the archive for extracted files
+    location: String,
+}
+
+impl Attachment<'_> {
+    fn description(&self) -> String {
+        format!("{:?} (part {})", self.filename.display().to_string(), self.location)
+    }
+}
+
+// Evaluates the rules in order for an attachment, saving it for each accepting rule until one does not continue the
+// evaluation, or until a rule rejects it. Without any matching rule, the accepted file types of the configuration
+// apply. Returns the post-processing of the first accepting rule when the attachment was saved. A shelf given in the
+// subject is a subdirectory of the chosen directory.
+fn check_and_save_attachment(
+    attachment: &Attachment,
     message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
     config: &AppConfig,
-) -> Result<bool, ImapAttachmentDaemonError> {
-    if part.is_message() {
-        return Ok(false);
+) -> Result<Option<PostProcess>, ImapAttachmentDaemonError> {
+    let description = attachment.description();
+    // Attachments without an inferred extension can still be accepted by their MIME type
+    let extension = attachment
+        .filename
+        .extension()
+        .map(|extension| {
+            extension
+                .to_str()
+                .ok_or(ImapAttachmentDaemonError::ExtensionConvertError)
+        })
+        .transpose()?;
+    let destination = target_destination(message_metadata, config);
+    let email = EmailFacts {
+        sender: message_metadata.from(),
+        target: destination.map(|destination| destination.address),
+        tag: commands
+            .tag
+            .as_deref()
+            .or_else(|| destination.and_then(|destination| destination.tag)),
+        subject: message_metadata.subject(),
+    };
+    let facts = AttachmentFacts {
+        extension,
+        mime_type: attachment.mime_type.as_deref(),
+        size: attachment.content.len(),
+    };
+    if let Some(max_attachment_size) = config
+        .max_attachment_size
+        .filter(|&max_attachment_size| attachment.content.len() as u64 > max_attachment_size)
+    {
+        log::warn!(
+            "Rejected attachment {} in email {}: over the limit of {max_attachment_size} bytes",
+            description,
+            format_email_metadata_message(message_metadata)
+        );
+        return Ok(None);
+    }
+    if config.verify_content_type {
+        if let Err(err) = verify_content_type(attachment.content, extension, facts.mime_type) {
+            log::warn!(
+                "Rejected attachment {} in email {}: {}",
+                description,
+                format_email_metadata_message(message_metadata),
+                err
+            );
+            return Ok(None);
+        }
     }
 
-    let filename = part
-        .attachment_name()
-        .ok_or(ImapAttachmentDaemonError::FilenameMissing)?;
-    let filename = PathBuf::from(filename.to_lowercase());
-    let extension = filename
-        .extension()
-        .ok_or(ImapAttachmentDaemonError::ExtensionMissing)?
-        .to_str()
-        .ok_or(ImapAttachmentDaemonError::ExtensionConvertError)?;
-    if !config.accepted_file_types.contains(extension) {
-        log::debug!("Unsupported file type: {}", extension);
-        return Ok(false);
+    let mut post_process = None;
+    for rule in config.rules()?.iter() {
+        if !rule.matches_email(&email) || !rule.matches_attachment(&facts, &config.accepted_file_types) {
+            continue;
+        }
+        if rule.action == RuleAction::Reject {
+            log::info!(
+                "Rule {:?} rejected attachment {} in email {}",
+                rule.name,
+                description,
+                format_email_metadata_message(message_metadata)
+            );
+            return Ok(post_process);
+        }
+        // Relative rule directories are relative to the attachments directory
+        let attachments_dir = rule.attachments_dir(&email).map_or_else(
+            || PathBuf::from(&config.attachments_dir),
+            |directory| Path::new(&config.attachments_dir).join(directory),
+        );
+        log::info!(
+            "Rule {:?} accepted attachment {} in email {}",
+            rule.name,
+            description,
+            format_email_metadata_message(message_metadata)
+        );
+        save_attachment(attachment, &attachments_dir, message_metadata, commands, config)?;
+        if post_process.is_none() {
+            post_process = Some(rule.post_process.clone().unwrap_or_default());
+        }
+        if !rule.continue_evaluation {
+            break;
+        }
+    }
+    if post_process.is_some() {
+        return Ok(post_process);
+    }
+
+    if !is_accepted_file_type(&config.accepted_file_types, &facts) {
+        log::debug!(
+            "Unsupported file type: {} ({})",
+            extension.unwrap_or("no extension"),
+            facts.mime_type.unwrap_or("no MIME type")
+        );
+        return Ok(None);
+    }
+    log::debug!("No rule matched attachment {description}, using the accepted file types");
+    save_attachment(
+        attachment,
+        Path::new(&config.attachments_dir),
+        message_metadata,
+        commands,
+        config,
+    )?;
+    Ok(Some(PostProcess::default()))
+}
+
+// The lowercase filename of an attachment, with an extension inferred from its MIME type or its content when missing,
+// since some mail clients send attachments such as `attachment` or `Untitled` without extension. Attachments without
+// name are named after the subject of the email, or else after its sender, date and their location.
+fn attachment_filename(
+    name: Option<&str>,
+    content: &[u8],
+    location: &str,
+    message_metadata: &MessageMetadata,
+    mime_type: Option<&str>,
+) -> PathBuf {
+    let inferred_extension = || infer_extension(content, mime_type);
+    if let Some(name) = name.and_then(safe_filename) {
+        let mut filename = PathBuf::from(name.to_lowercase());
+        if filename.extension().is_none() {
+            if let Some(extension) = inferred_extension() {
+                log::info!(
+                    "Attachment {} has no extension, inferred {extension:?}",
+                    filename.display()
+                );
+                let _ = filename.set_extension(extension);
+            }
+        }
+        return filename;
     }
-    let filepath = Path::new(&config.attachments_dir).join(&filename);
+
+    let stem = message_metadata
+        .subject()
+        .and_then(|subject| safe_filename(&subject.replace(['/', '\\'], "_")))
+        .unwrap_or_else(|| {
+            let date = message_metadata.date().map_or_else(
+                || "undated".to_string(),
+                |date| {
+                    format!(
+                        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+                        date.year, date.month, date.day, date.hour, date.minute, date.second
+                    )
+                },
+            );
+            format!(
+                "{}-{date}-{location}",
+                message_metadata.from().replace(['/', '\\'], "_")
+            )
+        });
+    let filename = match inferred_extension() {
+        Some(extension) => format!("{stem}.{extension}"),
+        None => stem,
+    };
+    log::info!(
+        "Attachment {location} in email {} has no filename, naming it {filename:?}",
+        format_email_metadata_message(message_metadata)
+    );
+    PathBuf::from(filename.to_lowercase())
+}
+
+// The last path segment of a name, without control characters, when it is a valid filename
+fn safe_filename(name: &str) -> Option<String> {
+    let filename = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|char| !char.is_control())
+        .collect::<String>();
+    let filename = filename.trim();
+    (!filename.is_empty() && !filename.chars().all(|char| char == '.')).then(|| filename.to_string())
+}
+
+// The lowercase `Content-Type` of a part, without its parameters
+fn mime_type(part: &MessagePart) -> Option<String> {
+    part.content_type().map(|content_type| {
+        content_type
+            .subtype()
+            .map_or_else(
+                || content_type.ctype().to_string(),
+                |subtype| format!("{}/{subtype}", content_type.ctype()),
+            )
+            .to_lowercase()
+    })
+}
+
+// Saves an attachment, replacing existing files unless they are kept, in which case the subject can still ask to
+// overwrite them
+fn save_attachment(
+    attachment: &Attachment,
+    attachments_dir: &Path,
+    message_metadata: &MessageMetadata,
+    commands: &SubjectCommands,
+    config: &AppConfig,
+) -> Result<(), ImapAttachmentDaemonError> {
+    let attachments_dir = match &commands.shelf {
+        Some(shelf) => attachments_dir.join(shelf),
+        None => attachments_dir.to_path_buf(),
+    };
+    let filepath = if commands.overwrite || config.existing_files == ExistingFiles::Overwrite {
+        attachments_dir.join(&attachment.filename)
+    } else {
+        available_filepath(&attachments_dir, &attachment.filename)
+    };
+    if config.dry_run {
+        log::info!(
+            "Dry run: would save attachment {} in email {} at: {}",
+            attachment.description(),
+            format_email_metadata_message(message_metadata),
+            filepath.display()
+        );
+        return Ok(());
+    }
+    std::fs::create_dir_all(&attachments_dir)?;
     let mut file = File::create(&filepath)?;
-    file.write_all(part.contents())?;
+    file.write_all(attachment.content)?;
     log::info!(
-        "Attachment {:?} in email {} saved at: {:?}",
-        filename,
+        "Attachment {} in email {} saved at: {}",
+        attachment.description(),
         format_email_metadata_message(message_metadata),
-        filepath
+        filepath.display()
     );
-    Ok(true)
+    Ok(())
+}
+
+// The path of a file in a directory, numbered as `book-1.epub` when `book.epub` already exists
+fn available_filepath(directory: &Path, filename: &Path) -> PathBuf {
+    let filepath = directory.join(filename);
+    if !filepath.exists() {
+        return filepath;
+    }
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = filename
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..=u32::MAX)
+        .map(|number| directory.join(format!("{stem}-{number}{extension}")))
+        .find(|filepath| !filepath.exists())
+        .unwrap_or(filepath)
 }
 
+// Headers holding the address an email was delivered to
+pub(crate) const DELIVERY_HEADERS: [&str; 3] = ["Delivered-To", "X-Original-To", "Envelope-To"];
+
 pub(crate) fn extract_descriptors<'x>(message: &'x Message) -> Result<MessageMetadata<'x>, ImapAttachmentDaemonError> {
     let from = message
         .from()
@@ -125,15 +959,47 @@ pub(crate) fn extract_descriptors<'x>(message: &'x Message) -> Result<MessageMet
         .ok_or(ImapAttachmentDaemonError::SenderMissing)?
         .address()
         .ok_or(ImapAttachmentDaemonError::SenderMissing)?;
-    let destinations = message
-        .to()
-        .ok_or(ImapAttachmentDaemonError::DestinationsMissing)?
-        .as_list()
-        .ok_or(ImapAttachmentDaemonError::DestinationsMissing)?
-        .iter()
-        .map(|x| x.address().ok_or(ImapAttachmentDaemonError::DestinationsMissing))
-        .collect::<Result<Vec<&str>, ImapAttachmentDaemonError>>()?;
+    // Bcc'd emails, mailing list deliveries and forwarded emails may not have the target address in `To:`, or no
+    // `To:` at all, so the delivery headers added by the receiving servers are included
+    let mut destinations = [message.to(), message.cc()]
+        .into_iter()
+        .flatten()
+        .flat_map(Address::iter)
+        .filter_map(Addr::address)
+        .collect::<Vec<&str>>();
+    destinations.extend(
+        message
+            .headers_raw()
+            .filter(|(name, _)| DELIVERY_HEADERS.iter().any(|header| name.eq_ignore_ascii_case(header)))
+            .flat_map(|(_, value)| value.split(','))
+            .map(|address| {
+                let address = address.trim();
+                address
+                    .rsplit_once('<')
+                    .and_then(|(_, address)| address.split_once('>'))
+                    .map_or(address, |(address, _)| address.trim())
+            })
+            .filter(|address| !address.is_empty()),
+    );
 
     let subject = message.subject();
-    Ok(MessageMetadata::new(from, destinations, subject))
+    // Only the headers added by the receiving server, above the first `Received:` header, can be trusted: the sender
+    // may add any header below it
+    let authentication_results = message
+        .headers_raw()
+        .take_while(|(name, _)| !name.eq_ignore_ascii_case("Received"))
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results"))
+        .map(|(_, value)| value)
+        .collect();
+    Ok(MessageMetadata::new(
+        from,
+        destinations,
+        subject,
+        message.date(),
+        authentication_results,
+    ))
 }
+
+#[cfg(test)]
+#[path = "test_mail_parsing.rs"]
+mod test_mail_parsing;
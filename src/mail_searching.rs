@@ -1,112 +1,223 @@
+use crate::body_structure::section_number;
 use crate::errors::ImapAttachmentDaemonError;
-use crate::imap_ops::{imap_fetch_headers, imap_fetch_rfc822, imap_fetch_uids, imap_search, open_session};
-use crate::mail_parsing::{filter_messages_by_source_and_whitelist, parse_and_process_emails};
+use crate::mail_parsing::{
+    filter_messages_by_source_and_whitelist, parse_and_process_emails, FilteredMessages, DELIVERY_HEADERS,
+};
+use crate::mail_store::{handle_blocked_email, handle_oversized_email, FetchedEmail, MailStore, SearchQuery};
+use crate::models::{BlockedAction, BodyDownload, ProcessingSummary, RequiredSignature, SenderPatterns};
 use crate::AppConfig;
 
-use imap::types::Fetches;
-use imap::{ImapConnection, Session};
-
-use std::collections::HashSet;
-
-pub(crate) fn startup_email_search(config: &AppConfig) -> Result<(), ImapAttachmentDaemonError> {
-    let mut imap_session = open_session(config)?;
+pub(crate) fn startup_email_search(
+    config: &AppConfig,
+    store: &mut impl MailStore,
+) -> Result<ProcessingSummary, ImapAttachmentDaemonError> {
     log::info!("Checking for unread emails at startup");
 
     // Search for unread emails
-    let search_result = whitelist_imap_search(&mut imap_session, config)?;
+    let search_result = store.search(SearchQuery::UnseenFromWhitelist(config))?;
     if search_result.is_empty() {
-        log::info!("No unread emails from whitelist found, waiting for new emails");
-        return Ok(());
+        log::info!("No unread emails from whitelist found");
+        return Ok(ProcessingSummary::default());
     }
 
-    log::info!("Found {} unread emails from whitelist, processing", search_result.len());
-    let bodies = fetch_bodies_by_seq(search_result, &mut imap_session)?;
-    parse_and_process_emails(config, &bodies, &mut imap_session)?;
-    Ok(())
+    // The IMAP search may match more senders than the whitelist patterns, so the results are filtered locally
+    let messages_headers = store.fetch_headers(&sorted_uids(search_result))?;
+    let messages_to_process = filter_messages_by_source_and_whitelist(&messages_headers, config)?;
+    if messages_to_process.is_empty() {
+        log::info!("No unread emails from whitelist found");
+        return Ok(ProcessingSummary::default());
+    }
+
+    log::info!(
+        "Found {} unread emails from whitelist, processing",
+        messages_to_process.accepted.len()
+    );
+    process_filtered_emails(config, &messages_to_process, store)
 }
 
-pub(crate) fn idle_update_email_search(response: u32, config: &AppConfig) -> Result<(), ImapAttachmentDaemonError> {
-    let mut imap_session = open_session(config)?;
+pub(crate) fn idle_update_email_search(
+    config: &AppConfig,
+    store: &mut impl MailStore,
+) -> Result<ProcessingSummary, ImapAttachmentDaemonError> {
     log::info!("Change detected in inbox, checking for new emails");
-    let search_result = recent_unseen_imap_search(&mut imap_session)?;
-    if !search_result.contains(&response) {
-        log::info!("Email isn't unread and recent, waiting for new emails");
-        return Ok(());
+    // Used when responding to an idle update, must not filter by whitelist or target address, because IMAP search
+    // indexing is too slow and will return empty results.
+    let search_result = store.search(SearchQuery::RecentUnseen)?;
+    if search_result.is_empty() {
+        log::info!("No unread and recent emails, waiting for new emails");
+        return Ok(ProcessingSummary::default());
     }
-    let query = search_result
-        .into_iter()
-        .map(|arg0: u32| arg0.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-    let fetched_uids = imap_fetch_uids(query, &mut imap_session)?;
-    let messages_headers = fetch_headers(fetched_uids, &mut imap_session)?;
+    let messages_headers = store.fetch_headers(&sorted_uids(search_result))?;
     let messages_to_process = filter_messages_by_source_and_whitelist(&messages_headers, config)?;
-    let bodies = fetch_bodies_by_uid(messages_to_process, &mut imap_session)?;
-    parse_and_process_emails(config, &bodies, &mut imap_session)?;
-    imap_session.logout()?;
-    Ok(())
+    if messages_to_process.is_empty() {
+        log::info!("No unread and recent emails from whitelist, waiting for new emails");
+        return Ok(ProcessingSummary::default());
+    }
+    process_filtered_emails(config, &messages_to_process, store)
 }
 
-fn fetch_bodies_by_uid(
-    search_result: impl IntoIterator<Item = u32>,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<Fetches, ImapAttachmentDaemonError> {
-    let query = search_result
-        .into_iter()
-        .map(|arg0: u32| arg0.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-    imap_fetch_rfc822(query, imap_session).map_err(Into::into)
+// Applies the oversized action to the emails exceeding the size limits and the blocked action to the emails from blocked
+// senders, then processes the accepted emails. Oversized and blocked emails are counted as skipped.
+fn process_filtered_emails(
+    config: &AppConfig,
+    messages: &FilteredMessages,
+    store: &mut impl MailStore,
+) -> Result<ProcessingSummary, ImapAttachmentDaemonError> {
+    let mut summary = ProcessingSummary::default();
+    for &uid in &messages.oversized {
+        match handle_oversized_email(uid, store, config) {
+            Ok(()) => summary.record_skipped(),
+            Err(err) => {
+                log::error!("Failed to handle oversized email with UID {uid}: {err}");
+                summary.record_failed();
+            }
+        }
+    }
+    for &uid in &messages.blocked {
+        match handle_blocked_email(uid, store, config) {
+            Ok(()) => summary.record_skipped(),
+            Err(err) => {
+                log::error!("Failed to handle blocked email with UID {uid}: {err}");
+                summary.record_failed();
+            }
+        }
+    }
+    // Each batch is processed and dropped before fetching the next one, bounding the memory used by large emails
+    for batch in body_batches(&messages.accepted, config.fetch_batch_count, config.fetch_batch_bytes) {
+        let bodies = match config.body_download {
+            // DKIM, S/MIME and OpenPGP signatures cover the full body
+            BodyDownload::Parts if !config.verify_dkim && config.require_signature == RequiredSignature::None => {
+                fetch_candidate_parts(&batch, &messages.accepted, store, config, &mut summary)?
+            }
+            _ => store.fetch_bodies(&batch)?,
+        };
+        summary.merge(parse_and_process_emails(config, &bodies, store));
+    }
+    Ok(summary)
 }
 
-fn fetch_bodies_by_seq(
-    search_result: impl IntoIterator<Item = u32>,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<Fetches, ImapAttachmentDaemonError> {
-    let query = search_result
-        .into_iter()
-        .map(|arg0: u32| arg0.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-    let fetched_uids = imap_fetch_uids(&query, imap_session)?;
-    imap_fetch_rfc822(fetched_uids.join(","), imap_session).map_err(Into::into)
+// Fetches the parts of emails that may be accepted, judging from their structure, as emails holding only these parts.
+// Emails without such parts are counted as skipped without being downloaded, and single-part or encrypted emails are
+// downloaded in full. The sizes of the accepted emails are kept for the emails holding their parts.
+fn fetch_candidate_parts(
+    uids: &[u32],
+    sizes: &[(u32, u64)],
+    store: &mut impl MailStore,
+    config: &AppConfig,
+    summary: &mut ProcessingSummary,
+) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError> {
+    let mut emails = Vec::new();
+    for structure in store.fetch_structures(uids)? {
+        if !structure.multipart || structure.is_encrypted() {
+            emails.extend(store.fetch_bodies(&[structure.uid])?);
+            continue;
+        }
+        let sections = structure.candidate_sections(config)?;
+        if sections.is_empty() {
+            log::info!(
+                "No accepted attachments found in the structure of email {}, leaving it unread without downloading it",
+                structure.uid
+            );
+            summary.record_skipped();
+            continue;
+        }
+        log::debug!(
+            "Downloading parts {} of email {}",
+            sections
+                .iter()
+                .map(|section| section_number(section))
+                .collect::<Vec<String>>()
+                .join(", "),
+            structure.uid
+        );
+        let size = sizes
+            .iter()
+            .find_map(|&(uid, size)| (uid == structure.uid).then_some(size))
+            .unwrap_or_default();
+        emails.push(store.fetch_parts(structure.uid, &sections)?.into_email(size));
+    }
+    Ok(emails)
 }
 
-fn fetch_headers(
-    query: impl IntoIterator<Item = String>,
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<Fetches, ImapAttachmentDaemonError> {
-    imap_fetch_headers(query.into_iter().collect::<Vec<_>>().join(","), imap_session)
+// Splits the UIDs of emails into batches of at most the given count and total size, an email larger than the byte budget
+// being fetched on its own
+pub(crate) fn body_batches(emails: &[(u32, u64)], max_count: usize, max_bytes: u64) -> Vec<Vec<u32>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0_u64;
+    for &(uid, size) in emails {
+        if !batch.is_empty() && (batch.len() >= max_count || batch_bytes.saturating_add(size) > max_bytes) {
+            batches.push(std::mem::take(&mut batch));
+            batch_bytes = 0;
+        }
+        batch.push(uid);
+        batch_bytes = batch_bytes.saturating_add(size);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
 }
 
-fn recent_unseen_imap_search(
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-) -> Result<HashSet<u32>, ImapAttachmentDaemonError> {
-    // Used when responding to an idle update, must not filter by whitelist or target address, because IMAP search
-    // indexing is too slow and will return empty results.
-    imap_search("RECENT UNSEEN", imap_session)
+fn sorted_uids(search_result: impl IntoIterator<Item = u32>) -> Vec<u32> {
+    let mut uids = search_result.into_iter().collect::<Vec<u32>>();
+    uids.sort_unstable();
+    uids
 }
 
-fn whitelist_imap_search(
-    imap_session: &mut Session<Box<dyn ImapConnection>>,
-    config: &AppConfig,
-) -> Result<HashSet<u32>, ImapAttachmentDaemonError> {
-    let search_criteria = generate_search_criteria(config);
-    imap_search(&search_criteria, imap_session)
+pub(crate) fn generate_search_criteria(config: &AppConfig) -> String {
+    let target = config.target_address.as_ref().unwrap_or(&config.username);
+    // Blocked senders must also be found to move or delete their emails, even when they are not whitelisted
+    let mut sender_patterns = vec![config.whitelist_patterns()];
+    if config.blocked_action != BlockedAction::Leave {
+        sender_patterns.push(config.blocklist_patterns());
+    }
+    // Invalid patterns are rejected by `init_app`, and the search results are filtered locally anyway
+    let Some(from_terms) = sender_patterns
+        .into_iter()
+        .map(|patterns| patterns.ok().and_then(SenderPatterns::imap_search_terms))
+        .collect::<Option<Vec<Vec<&str>>>>()
+        .map(|terms| terms.concat())
+    else {
+        // Some patterns cannot be expressed as IMAP search keys, so the sender is only filtered locally
+        return format!("UNSEEN {}", target_search_criteria(target));
+    };
+    format!(
+        "UNSEEN {} {}",
+        target_search_criteria(target),
+        any_search_key(
+            &from_terms
+                .iter()
+                .map(|term| format!("FROM {term:?}"))
+                .collect::<Vec<String>>()
+        )
+    )
+}
+
+// Matches the target address in the destination and delivery headers, along with its sub-addresses such as
+// `user+books@example.com`, since the IMAP search keys match substrings
+fn target_search_criteria(target: &str) -> String {
+    let mut terms = vec![target.to_string()];
+    if let Some((user, _)) = target.rsplit_once('@').filter(|(user, _)| !user.contains('+')) {
+        terms.push(format!("{user}+"));
+    }
+    let mut keys = Vec::new();
+    for term in &terms {
+        keys.push(format!("TO {term:?}"));
+        keys.push(format!("CC {term:?}"));
+        for header in DELIVERY_HEADERS {
+            keys.push(format!("HEADER {header} {term:?}"));
+        }
+    }
+    any_search_key(&keys)
 }
 
-fn generate_search_criteria(config: &AppConfig) -> String {
-    let from_criteria = config
-        .whitelist
-        .iter()
-        .map(|addr| format!("FROM {addr:?}"))
-        .collect::<Vec<String>>()
-        .join(" ");
+// OR only takes two search keys, so it must be repeated to combine more of them
+fn any_search_key(keys: &[String]) -> String {
     format!(
-        "UNSEEN TO {:?} ({} {})",
-        config.target_address.as_ref().unwrap_or(&config.username),
-        if config.whitelist.len() > 1 { "OR" } else { "" },
-        from_criteria
+        "({} {})",
+        vec!["OR"; keys.len().saturating_sub(1)].join(" "),
+        keys.join(" ")
     )
 }
 
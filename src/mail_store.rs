@@ -0,0 +1,177 @@
+use crate::body_structure::{FetchedParts, FetchedStructure};
+use crate::errors::ImapAttachmentDaemonError;
+use crate::models::{BlockedAction, OversizedAction, PostProcess};
+use crate::AppConfig;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+// A mailbox the daemon can search, fetch emails from and change the state of. The IMAP implementation is
+// `imap_ops::ImapStore`, and an in-memory implementation is used in tests.
+pub(crate) trait MailStore {
+    // Returns the UIDs of the emails in the inbox matching the query
+    fn search(&mut self, query: SearchQuery) -> Result<HashSet<u32>, ImapAttachmentDaemonError>;
+
+    // Fetches the headers of the emails with the given UIDs, without marking them as seen
+    fn fetch_headers(&mut self, uids: &[u32]) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError>;
+
+    // Fetches the full emails with the given UIDs, without marking them as seen
+    fn fetch_bodies(&mut self, uids: &[u32]) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError>;
+
+    // Fetches the structure of the emails with the given UIDs, without downloading them
+    fn fetch_structures(&mut self, uids: &[u32]) -> Result<Vec<FetchedStructure>, ImapAttachmentDaemonError>;
+
+    // Fetches the header of the email with the given UID and the given parts, without marking it as seen
+    fn fetch_parts(&mut self, uid: u32, sections: &[Vec<u32>]) -> Result<FetchedParts, ImapAttachmentDaemonError>;
+
+    // Moves the email with the given UID from the inbox to another mailbox
+    fn move_email(&mut self, uid: u32, mailbox: &str) -> Result<(), ImapAttachmentDaemonError>;
+
+    // Permanently deletes the email with the given UID from the inbox
+    fn delete_email(&mut self, uid: u32) -> Result<(), ImapAttachmentDaemonError>;
+
+    // Adds a flag to the email with the given UID
+    fn add_flag(&mut self, uid: u32, flag: EmailFlag) -> Result<(), ImapAttachmentDaemonError>;
+
+    // Waits for a change in the inbox, returning whether new unread emails may have arrived before the timeout
+    fn idle(&mut self, timeout: Duration) -> Result<bool, ImapAttachmentDaemonError>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SearchQuery<'a> {
+    // Unread emails sent by the whitelist to the target address
+    UnseenFromWhitelist(&'a AppConfig),
+    // Unread emails that arrived since the last check, regardless of sender and destination
+    RecentUnseen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum EmailFlag {
+    Seen,
+    Deleted,
+    Flagged,
+}
+
+impl EmailFlag {
+    pub(crate) fn imap_name(self) -> &'static str {
+        match self {
+            Self::Seen => "\\Seen",
+            Self::Deleted => "\\Deleted",
+            Self::Flagged => "\\Flagged",
+        }
+    }
+}
+
+pub(crate) fn move_email_to_trash(
+    uid: u32,
+    store: &mut impl MailStore,
+    dry_run: bool,
+) -> Result<(), ImapAttachmentDaemonError> {
+    if dry_run {
+        log::info!("Dry run: would move email {uid} to Trash");
+        return Ok(());
+    }
+    store.move_email(uid, "Trash")?;
+    log::debug!("Moved email to Trash");
+    Ok(())
+}
+
+pub(crate) fn mark_email_as_read(
+    uid: u32,
+    store: &mut impl MailStore,
+    dry_run: bool,
+) -> Result<(), ImapAttachmentDaemonError> {
+    if dry_run {
+        log::info!("Dry run: would mark email {uid} as read");
+        return Ok(());
+    }
+    store.add_flag(uid, EmailFlag::Seen)?;
+    log::debug!("Email marked as read");
+    Ok(())
+}
+
+// Applies the post-processing of the rule that saved the attachments of an email
+pub(crate) fn post_process_email(
+    uid: u32,
+    store: &mut impl MailStore,
+    post_process: &PostProcess,
+    dry_run: bool,
+) -> Result<(), ImapAttachmentDaemonError> {
+    match post_process {
+        PostProcess::Trash => {
+            mark_email_as_read(uid, store, dry_run)?;
+            move_email_to_trash(uid, store, dry_run)?;
+        }
+        PostProcess::Read => mark_email_as_read(uid, store, dry_run)?,
+        PostProcess::Leave => log::info!("Leaving email {uid} unread"),
+        PostProcess::Move(mailbox) => {
+            mark_email_as_read(uid, store, dry_run)?;
+            if dry_run {
+                log::info!("Dry run: would move email {uid} to {mailbox}");
+            } else {
+                store.move_email(uid, mailbox)?;
+                log::debug!("Moved email to {mailbox}");
+            }
+        }
+    }
+    Ok(())
+}
+
+// Applies the configured blocked action to an email from a blocked sender
+pub(crate) fn handle_blocked_email(
+    uid: u32,
+    store: &mut impl MailStore,
+    config: &AppConfig,
+) -> Result<(), ImapAttachmentDaemonError> {
+    match config.blocked_action {
+        BlockedAction::Leave => log::info!("Email {uid} is from a blocked sender, leaving it unread"),
+        BlockedAction::Junk if config.dry_run => {
+            log::info!("Dry run: would move email {uid} to {}", config.junk_mailbox);
+        }
+        BlockedAction::Junk => {
+            store.move_email(uid, &config.junk_mailbox)?;
+            log::info!("Moved email {uid} from a blocked sender to {}", config.junk_mailbox);
+        }
+        BlockedAction::Delete if config.dry_run => log::info!("Dry run: would delete email {uid}"),
+        BlockedAction::Delete => {
+            store.delete_email(uid)?;
+            log::info!("Deleted email {uid} from a blocked sender");
+        }
+    }
+    Ok(())
+}
+
+// Applies the configured oversized action to an email exceeding the size limits
+pub(crate) fn handle_oversized_email(
+    uid: u32,
+    store: &mut impl MailStore,
+    config: &AppConfig,
+) -> Result<(), ImapAttachmentDaemonError> {
+    match config.oversized_action {
+        OversizedAction::Skip => log::info!("Email {uid} exceeds the size limits, leaving it unread"),
+        OversizedAction::Flag if config.dry_run => log::info!("Dry run: would flag email {uid}"),
+        OversizedAction::Flag => {
+            store.add_flag(uid, EmailFlag::Flagged)?;
+            log::info!("Flagged email {uid} exceeding the size limits");
+        }
+        OversizedAction::Move if config.dry_run => {
+            log::info!("Dry run: would move email {uid} to {}", config.oversized_mailbox);
+        }
+        OversizedAction::Move => {
+            store.move_email(uid, &config.oversized_mailbox)?;
+            log::info!(
+                "Moved email {uid} exceeding the size limits to {}",
+                config.oversized_mailbox
+            );
+        }
+    }
+    Ok(())
+}
+
+// The raw header or full content of an email, along with its UID and the size of the full email in bytes
+#[derive(Debug, Clone)]
+pub(crate) struct FetchedEmail {
+    pub uid: u32,
+    pub raw: Vec<u8>,
+    pub size: u64,
+}
@@ -0,0 +1,266 @@
+use crate::body_structure::{FetchedParts, FetchedStructure, StructurePart};
+use crate::errors::ImapAttachmentDaemonError;
+use crate::mail_parsing::{extract_descriptors, parse_message, sender_verdict, SenderVerdict};
+use crate::mail_store::{EmailFlag, FetchedEmail, MailStore, SearchQuery};
+use crate::models::BlockedAction;
+
+use mail_parser::{ContentType, Message, MessagePart, MimeHeaders, PartType};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::Duration;
+
+const INBOX: &str = "INBOX";
+
+// In-memory mail store, standing in for an IMAP server in tests
+#[derive(Debug, Default)]
+pub(crate) struct MemoryStore {
+    mailboxes: BTreeMap<String, Vec<StoredEmail>>,
+    next_uid: u32,
+    pending_idle_updates: usize,
+    // The UIDs of each call to `fetch_bodies`, and the UID and sections of each call to `fetch_parts`
+    pub body_fetches: Vec<Vec<u32>>,
+    pub part_fetches: Vec<(u32, Vec<Vec<u32>>)>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StoredEmail {
+    pub uid: u32,
+    pub raw: Vec<u8>,
+    pub flags: BTreeSet<EmailFlag>,
+    pub recent: bool,
+}
+
+impl StoredEmail {
+    fn size(&self) -> u64 {
+        u64::try_from(self.raw.len()).unwrap_or(u64::MAX)
+    }
+}
+
+impl MemoryStore {
+    // Delivers an email to the inbox as recent and unread, notifying any IDLE waiter
+    pub(crate) fn deliver(&mut self, raw: impl Into<Vec<u8>>) -> u32 {
+        self.pending_idle_updates += 1;
+        self.push_to_inbox(raw.into(), true)
+    }
+
+    // Delivers an unread email to the inbox that was already there before the last check
+    pub(crate) fn deliver_old(&mut self, raw: impl Into<Vec<u8>>) -> u32 {
+        self.push_to_inbox(raw.into(), false)
+    }
+
+    pub(crate) fn mailbox(&self, name: &str) -> &[StoredEmail] {
+        self.mailboxes.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub(crate) fn email(&self, uid: u32) -> Option<&StoredEmail> {
+        self.mailboxes.values().flatten().find(|email| email.uid == uid)
+    }
+
+    fn push_to_inbox(&mut self, raw: Vec<u8>, recent: bool) -> u32 {
+        self.next_uid += 1;
+        let uid = self.next_uid;
+        self.inbox_mut().push(StoredEmail {
+            uid,
+            raw,
+            flags: BTreeSet::new(),
+            recent,
+        });
+        uid
+    }
+
+    fn inbox(&self) -> &[StoredEmail] {
+        self.mailbox(INBOX)
+    }
+
+    fn inbox_mut(&mut self) -> &mut Vec<StoredEmail> {
+        self.mailboxes.entry(INBOX.to_string()).or_default()
+    }
+
+    fn fetch<'a>(&'a self, uids: &'a [u32]) -> impl Iterator<Item = &'a StoredEmail> {
+        self.inbox().iter().filter(|email| uids.contains(&email.uid))
+    }
+}
+
+impl MailStore for MemoryStore {
+    fn search(&mut self, query: SearchQuery) -> Result<HashSet<u32>, ImapAttachmentDaemonError> {
+        let mut uids = HashSet::new();
+        for email in self.inbox() {
+            if email.flags.contains(&EmailFlag::Seen) {
+                continue;
+            }
+            let matches = match query {
+                SearchQuery::UnseenFromWhitelist(config) => {
+                    // Like the IMAP search, blocked senders are only searched for when there is something to do
+                    let message = parse_message(&email.raw)?;
+                    match sender_verdict(&extract_descriptors(&message)?, config)? {
+                        SenderVerdict::Accepted => true,
+                        SenderVerdict::Blocked => config.blocked_action != BlockedAction::Leave,
+                        SenderVerdict::Ignored => false,
+                    }
+                }
+                SearchQuery::RecentUnseen => email.recent,
+            };
+            if matches {
+                let _ = uids.insert(email.uid);
+            }
+        }
+        Ok(uids)
+    }
+
+    fn fetch_headers(&mut self, uids: &[u32]) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError> {
+        Ok(self
+            .fetch(uids)
+            .map(|email| FetchedEmail {
+                uid: email.uid,
+                raw: email.raw[..header_length(&email.raw)].to_vec(),
+                size: email.size(),
+            })
+            .collect())
+    }
+
+    fn fetch_bodies(&mut self, uids: &[u32]) -> Result<Vec<FetchedEmail>, ImapAttachmentDaemonError> {
+        self.body_fetches.push(uids.to_vec());
+        Ok(self
+            .fetch(uids)
+            .map(|email| FetchedEmail {
+                uid: email.uid,
+                raw: email.raw.clone(),
+                size: email.size(),
+            })
+            .collect())
+    }
+
+    fn fetch_structures(&mut self, uids: &[u32]) -> Result<Vec<FetchedStructure>, ImapAttachmentDaemonError> {
+        self.fetch(uids)
+            .map(|email| {
+                let message = parse_message(&email.raw)?;
+                let mut parts = Vec::new();
+                structure_parts(&message, 0, &[], &mut parts);
+                Ok(FetchedStructure {
+                    uid: email.uid,
+                    multipart: message
+                        .parts
+                        .first()
+                        .is_some_and(|part| matches!(part.body, PartType::Multipart(_))),
+                    parts,
+                })
+            })
+            .collect()
+    }
+
+    fn fetch_parts(&mut self, uid: u32, sections: &[Vec<u32>]) -> Result<FetchedParts, ImapAttachmentDaemonError> {
+        self.part_fetches.push((uid, sections.to_vec()));
+        let email = self
+            .inbox()
+            .iter()
+            .find(|email| email.uid == uid)
+            .ok_or(ImapAttachmentDaemonError::UidMissing)?;
+        let message = parse_message(&email.raw)?;
+        let parts = sections
+            .iter()
+            .map(|section| {
+                let part = section_part(&message, section).ok_or(ImapAttachmentDaemonError::BodyMissing)?;
+                Ok((
+                    email.raw[part.offset_header..part.offset_body].to_vec(),
+                    email.raw[part.offset_body..part.offset_end].to_vec(),
+                ))
+            })
+            .collect::<Result<Vec<(Vec<u8>, Vec<u8>)>, ImapAttachmentDaemonError>>()?;
+        Ok(FetchedParts {
+            uid,
+            header: email.raw[..header_length(&email.raw)].to_vec(),
+            parts,
+        })
+    }
+
+    fn move_email(&mut self, uid: u32, mailbox: &str) -> Result<(), ImapAttachmentDaemonError> {
+        let inbox = self.inbox_mut();
+        let position = inbox
+            .iter()
+            .position(|email| email.uid == uid)
+            .ok_or(ImapAttachmentDaemonError::UidMissing)?;
+        let email = inbox.remove(position);
+        self.mailboxes.entry(mailbox.to_string()).or_default().push(email);
+        Ok(())
+    }
+
+    fn delete_email(&mut self, uid: u32) -> Result<(), ImapAttachmentDaemonError> {
+        let inbox = self.inbox_mut();
+        let position = inbox
+            .iter()
+            .position(|email| email.uid == uid)
+            .ok_or(ImapAttachmentDaemonError::UidMissing)?;
+        let _ = inbox.remove(position);
+        Ok(())
+    }
+
+    fn add_flag(&mut self, uid: u32, flag: EmailFlag) -> Result<(), ImapAttachmentDaemonError> {
+        let email = self
+            .inbox_mut()
+            .iter_mut()
+            .find(|email| email.uid == uid)
+            .ok_or(ImapAttachmentDaemonError::UidMissing)?;
+        let _ = email.flags.insert(flag);
+        Ok(())
+    }
+
+    fn idle(&mut self, _timeout: Duration) -> Result<bool, ImapAttachmentDaemonError> {
+        // Never blocks, a timeout is reported when no email was delivered since the last call
+        let changed = self.pending_idle_updates > 0;
+        self.pending_idle_updates = 0;
+        Ok(changed)
+    }
+}
+
+// The header ends at the first empty line
+fn header_length(raw: &[u8]) -> usize {
+    raw.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(raw.len(), |position| position + 4)
+}
+
+// Lists the leaf parts of a parsed email like a BODYSTRUCTURE, see `imap_ops::structure_parts`
+fn structure_parts(message: &Message, part_id: usize, section: &[u32], parts: &mut Vec<StructurePart>) {
+    let Some(part) = message.parts.get(part_id) else {
+        return;
+    };
+    if let PartType::Multipart(children) = &part.body {
+        for (number, &child) in (1..).zip(children) {
+            structure_parts(message, child, &[section, &[number]].concat(), parts);
+        }
+        return;
+    }
+    parts.push(StructurePart {
+        section: section.to_vec(),
+        mime_type: part.content_type().map_or_else(
+            || "text/plain".to_string(),
+            |content_type| {
+                format!(
+                    "{}/{}",
+                    content_type.ctype(),
+                    content_type.subtype().unwrap_or_default()
+                )
+                .to_lowercase()
+            },
+        ),
+        filename: part.attachment_name().map(str::to_string),
+        undecoded_filename: false,
+        is_attachment: part.content_disposition().is_some_and(ContentType::is_attachment),
+        encoding: part.content_transfer_encoding().map(str::to_lowercase),
+        octets: u64::try_from(part.offset_end - part.offset_body).unwrap_or(u64::MAX),
+    });
+}
+
+// The part of a parsed email at an IMAP section
+fn section_part<'a>(message: &'a Message, section: &[u32]) -> Option<&'a MessagePart<'a>> {
+    let mut part = message.parts.first()?;
+    for &number in section {
+        let PartType::Multipart(children) = &part.body else {
+            return None;
+        };
+        part = message
+            .parts
+            .get(*children.get(usize::try_from(number).ok()?.checked_sub(1)?)?)?;
+    }
+    Some(part)
+}
@@ -1,8 +1,18 @@
 use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
 use secrecy::SecretString;
 use serde::Deserialize;
 
+use super::{Rules, SenderPatterns, SenderUsage};
+use crate::archives::ArchiveLimits;
+use crate::dkim::{DkimKeyResolver, DnsKeyResolver, StaticKeyResolver};
+use crate::linked_files::LinkLimits;
+use crate::secure_mime::SecureMimeKeys;
+use crate::ImapAttachmentDaemonError;
+
 // Default accepted file types for attachments. Mirrors CWA accepted file types.
 fn default_accepted_file_types() -> BTreeSet<String> {
     BTreeSet::from_iter([
@@ -41,17 +51,383 @@ fn default_attachments_dir() -> String {
     "/attachments".to_string()
 }
 
+fn default_max_nesting_depth() -> usize {
+    3
+}
+
+fn default_archive_max_entries() -> usize {
+    100
+}
+
+fn default_archive_max_size() -> u64 {
+    500 * 1024 * 1024
+}
+
+fn default_archive_max_ratio() -> u64 {
+    100
+}
+
+fn default_archive_max_depth() -> usize {
+    2
+}
+
+fn default_fetch_batch_count() -> usize {
+    10
+}
+
+fn default_fetch_batch_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_link_max_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_link_timeout() -> u64 {
+    30
+}
+
+fn default_junk_mailbox() -> String {
+    "Junk".to_string()
+}
+
+fn default_oversized_mailbox() -> String {
+    "Oversized".to_string()
+}
+
+/// How the application runs once initialised.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    /// Process the backlog of unread emails, then wait for new emails in IDLE mode.
+    #[default]
+    Daemon,
+    /// Process the backlog of unread emails and exit.
+    Oneshot,
+}
+
+/// What to do with emails from blocked senders.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockedAction {
+    /// Leave the email unread in the inbox.
+    #[default]
+    Leave,
+    /// Move the email to the junk mailbox.
+    Junk,
+    /// Permanently delete the email.
+    Delete,
+}
+
+/// How much of the emails to download.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyDownload {
+    /// Download full emails.
+    #[default]
+    Full,
+    /// Download only the parts that may be accepted, judging from the structure of the emails. Emails without such parts
+    /// are not downloaded. Full emails are still downloaded to verify their DKIM signature.
+    Parts,
+}
+
+/// What to do with emails exceeding the message size limit or the daily size limit of their sender.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedAction {
+    /// Leave the email unread in the inbox, without downloading it.
+    #[default]
+    Skip,
+    /// Flag the email, leaving it unread in the inbox.
+    Flag,
+    /// Move the email to the oversized mailbox.
+    Move,
+}
+
+/// Sender authentication required before accepting an email from a whitelisted sender.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequiredAuthentication {
+    /// Trust the `From:` header.
+    #[default]
+    None,
+    /// Require a passing DKIM signature aligned with the `From:` domain.
+    Dkim,
+    /// Require a passing DMARC evaluation of the `From:` domain.
+    Dmarc,
+    /// Require either an aligned DKIM signature or a passing DMARC evaluation.
+    Any,
+}
+
+/// Signature required on emails from whitelisted senders, made by a trusted signer with the sender address.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequiredSignature {
+    /// Accept unsigned emails.
+    #[default]
+    None,
+    /// Require a valid S/MIME signature.
+    Smime,
+    /// Require a valid PGP/MIME signature.
+    Pgp,
+    /// Require either a valid S/MIME or PGP/MIME signature.
+    Any,
+}
+
+/// What to do with the inline parts of the body of emails, besides their attachments.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InlineParts {
+    /// Only save attachments.
+    #[default]
+    Ignore,
+    /// Also save the inline parts that carry a filename, or that are not text and have an accepted MIME type.
+    Save,
+}
+
+/// What to do when an attachment is saved with the name of an existing file.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingFiles {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Keep both files, saving the attachment with a numbered name such as `book-1.epub`.
+    Rename,
+}
+
+/// An archive format whose attachments are unpacked, saving the accepted files they contain.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    /// `.zip` archives, but not ZIP-based formats such as `.cbz` or `.epub`.
+    Zip,
+    /// `.tar` archives.
+    Tar,
+    /// `.tar.gz` and `.tgz` archives.
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+}
+
+/// The URL schemes of the linked files that are downloaded, and of the redirects that are followed.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkSchemes {
+    /// Only https links, so that downloads cannot be read or changed on the way.
+    #[default]
+    Https,
+    /// Plain http links as well, for servers without TLS on a trusted network.
+    Any,
+}
+
+/// An encoding of files embedded in the text body of emails, decoding the files it holds into attachments.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddedEncoding {
+    /// `begin 644 book.epub` blocks, as sent by legacy mailers.
+    Uuencode,
+    /// Single-part `=ybegin` blocks, as sent by Usenet gateways.
+    Yenc,
+}
+
+/// A command that senders can give in the subject of an email.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum SubjectCommand {
+    /// `[shelf:name]` saves the attachments in a subdirectory of the attachments directory.
+    Shelf,
+    /// `[tag:name]` sets the tag used by the rules, instead of the sub-address tag.
+    Tag,
+    /// `[overwrite]` replaces existing files with the same name.
+    Overwrite,
+    /// `[dry]` processes the email as a dry run.
+    Dry,
+}
+
 // Application configuration extracted from environment variables.
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct AppConfig {
+    // The IMAP settings are only mandatory when no local source is configured, see `init_app`
+    #[serde(default)]
     pub imap_server: String,
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: SecretString,
     pub target_address: Option<String>,
     #[serde(default)]
     pub whitelist: BTreeSet<String>,
     #[serde(default = "default_attachments_dir")]
     pub attachments_dir: String,
+    // Extensions, or MIME types such as `application/epub+zip` or `image/*`
     #[serde(default = "default_accepted_file_types")]
     pub accepted_file_types: BTreeSet<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub run_mode: RunMode,
+    pub local_source: Option<PathBuf>,
+    #[serde(default)]
+    pub blocklist: BTreeSet<String>,
+    #[serde(default)]
+    pub blocked_action: BlockedAction,
+    #[serde(default = "default_junk_mailbox")]
+    pub junk_mailbox: String,
+    // The authserv-id of the `Authentication-Results` headers added by the trusted receiving server
+    pub authserv_id: Option<String>,
+    #[serde(default)]
+    pub require_authentication: RequiredAuthentication,
+    #[serde(default)]
+    pub verify_dkim: bool,
+    // DKIM keys to use instead of DNS lookups, see `dkim::StaticKeyResolver`
+    pub dkim_keys_file: Option<PathBuf>,
+    #[serde(default)]
+    pub inline_parts: InlineParts,
+    // How deep to look into emails forwarded as attachments, 0 to ignore them
+    #[serde(default = "default_max_nesting_depth")]
+    pub max_nesting_depth: usize,
+    // Rejects attachments whose magic bytes do not match their extension or MIME type
+    #[serde(default)]
+    pub verify_content_type: bool,
+    // Archive formats to unpack, none by default, and the limits guarding against archive bombs
+    #[serde(default)]
+    pub extract_archives: BTreeSet<ArchiveFormat>,
+    #[serde(default = "default_archive_max_entries")]
+    pub archive_max_entries: usize,
+    // Total uncompressed size of the extracted files, in bytes
+    #[serde(default = "default_archive_max_size")]
+    pub archive_max_size: u64,
+    #[serde(default = "default_archive_max_ratio")]
+    pub archive_max_ratio: u64,
+    // How deep archives can be nested, 1 to ignore archives within archives
+    #[serde(default = "default_archive_max_depth")]
+    pub archive_max_depth: usize,
+    // Encodings of files embedded in plain text bodies to decode, none by default
+    #[serde(default)]
+    pub embedded_encodings: BTreeSet<EmbeddedEncoding>,
+    // Hosts whose links in the bodies of emails are downloaded, as `cloud.example.com` or `*.example.com`, none by
+    // default. Downloads are limited in size, in bytes, and in time, in seconds, and use https unless http is allowed.
+    #[serde(default)]
+    pub link_hosts: BTreeSet<String>,
+    #[serde(default = "default_link_max_size")]
+    pub link_max_size: u64,
+    #[serde(default = "default_link_timeout")]
+    pub link_timeout: u64,
+    #[serde(default)]
+    pub link_schemes: LinkSchemes,
+    // Size limits in bytes, none by default. The message size is checked before downloading the email, and the daily
+    // size per sender counts the emails saved from each sender during the current UTC day.
+    pub max_attachment_size: Option<u64>,
+    pub max_message_size: Option<u64>,
+    pub max_daily_sender_size: Option<u64>,
+    #[serde(default)]
+    pub oversized_action: OversizedAction,
+    #[serde(default = "default_oversized_mailbox")]
+    pub oversized_mailbox: String,
+    #[serde(default)]
+    pub body_download: BodyDownload,
+    // Emails are downloaded in batches of at most this many emails and bytes, by their RFC822.SIZE
+    #[serde(default = "default_fetch_batch_count")]
+    pub fetch_batch_count: usize,
+    #[serde(default = "default_fetch_batch_bytes")]
+    pub fetch_batch_bytes: u64,
+    // Routing rules, see `models::rule`
+    pub rules_file: Option<PathBuf>,
+    #[serde(default)]
+    pub existing_files: ExistingFiles,
+    // Commands accepted in the subject of emails, none by default since they give senders control over the saved files
+    #[serde(default)]
+    pub subject_commands: BTreeSet<SubjectCommand>,
+    // Private keys of the account to decrypt S/MIME and PGP/MIME emails, and their passphrase when they are protected
+    pub smime_key_file: Option<PathBuf>,
+    pub pgp_key_file: Option<PathBuf>,
+    pub key_passphrase: Option<SecretString>,
+    // Certificates and OpenPGP public keys of the trusted signers
+    pub signers_file: Option<PathBuf>,
+    #[serde(default)]
+    pub require_signature: RequiredSignature,
+    // Whitelist and blocklist patterns and rules, compiled on first use
+    #[serde(skip)]
+    pub(crate) compiled_whitelist: OnceLock<SenderPatterns>,
+    #[serde(skip)]
+    pub(crate) compiled_blocklist: OnceLock<SenderPatterns>,
+    #[serde(skip)]
+    pub(crate) compiled_rules: OnceLock<Rules>,
+    // Sizes of the emails saved from each sender today, shared by the clones of the configuration
+    #[serde(skip)]
+    pub(crate) sender_usage: Arc<Mutex<SenderUsage>>,
+    // DKIM key resolver, created on first use
+    #[serde(skip)]
+    pub(crate) dkim_key_resolver: OnceLock<Arc<dyn DkimKeyResolver>>,
+    // Private keys and trusted signers, loaded on first use
+    #[serde(skip)]
+    pub(crate) secure_mime_keys: OnceLock<SecureMimeKeys>,
+}
+
+impl AppConfig {
+    pub(crate) fn whitelist_patterns(&self) -> Result<&SenderPatterns, ImapAttachmentDaemonError> {
+        compiled_patterns(&self.compiled_whitelist, &self.whitelist)
+    }
+
+    pub(crate) fn blocklist_patterns(&self) -> Result<&SenderPatterns, ImapAttachmentDaemonError> {
+        compiled_patterns(&self.compiled_blocklist, &self.blocklist)
+    }
+
+    pub(crate) fn rules(&self) -> Result<&Rules, ImapAttachmentDaemonError> {
+        if let Some(rules) = self.compiled_rules.get() {
+            return Ok(rules);
+        }
+        let rules = match &self.rules_file {
+            Some(path) => Rules::load(path)?,
+            None => Rules::default(),
+        };
+        Ok(self.compiled_rules.get_or_init(|| rules))
+    }
+
+    pub(crate) fn archive_limits(&self) -> ArchiveLimits {
+        ArchiveLimits {
+            entries: self.archive_max_entries,
+            total_size: self.archive_max_size,
+            compression_ratio: self.archive_max_ratio,
+            depth: self.archive_max_depth,
+        }
+    }
+
+    pub(crate) fn link_limits(&self) -> LinkLimits {
+        LinkLimits {
+            size: self.link_max_size,
+            timeout: Duration::from_secs(self.link_timeout),
+            schemes: self.link_schemes,
+        }
+    }
+
+    pub(crate) fn dkim_key_resolver(&self) -> Result<&dyn DkimKeyResolver, ImapAttachmentDaemonError> {
+        if let Some(resolver) = self.dkim_key_resolver.get() {
+            return Ok(resolver.as_ref());
+        }
+        let resolver: Arc<dyn DkimKeyResolver> = match &self.dkim_keys_file {
+            Some(path) => Arc::new(StaticKeyResolver::from_file(path)?),
+            None => Arc::new(DnsKeyResolver::from_system_conf()?),
+        };
+        Ok(self.dkim_key_resolver.get_or_init(|| resolver).as_ref())
+    }
+
+    pub(crate) fn secure_mime_keys(&self) -> Result<&SecureMimeKeys, ImapAttachmentDaemonError> {
+        if let Some(keys) = self.secure_mime_keys.get() {
+            return Ok(keys);
+        }
+        let keys = SecureMimeKeys::load(self)?;
+        Ok(self.secure_mime_keys.get_or_init(|| keys))
+    }
+}
+
+fn compiled_patterns<'a>(
+    compiled: &'a OnceLock<SenderPatterns>,
+    entries: &BTreeSet<String>,
+) -> Result<&'a SenderPatterns, ImapAttachmentDaemonError> {
+    if let Some(patterns) = compiled.get() {
+        return Ok(patterns);
+    }
+    let patterns = SenderPatterns::parse(entries)?;
+    Ok(compiled.get_or_init(|| patterns))
 }
@@ -1,25 +1,49 @@
+use mail_parser::DateTime;
+
 // Message metadata struct
 #[derive(Debug, Clone, Default)]
 pub struct MessageMetadata<'a> {
     from: &'a str,
-    to: Vec<&'a str>,
+    destinations: Vec<&'a str>,
     subject: Option<&'a str>,
+    date: Option<&'a DateTime>,
+    authentication_results: Vec<&'a str>,
 }
 
 impl<'a> MessageMetadata<'a> {
-    pub fn new(from: &'a str, to: Vec<&'a str>, subject: Option<&'a str>) -> Self {
-        Self { from, to, subject }
+    pub fn new(
+        from: &'a str,
+        destinations: Vec<&'a str>,
+        subject: Option<&'a str>,
+        date: Option<&'a DateTime>,
+        authentication_results: Vec<&'a str>,
+    ) -> Self {
+        Self {
+            from,
+            destinations,
+            subject,
+            date,
+            authentication_results,
+        }
     }
     pub fn from(&self) -> &str {
         self.from
     }
 
-    #[allow(dead_code)]
-    pub fn to(&self) -> &[&str] {
-        &self.to
+    // The addresses of the `To:`, `Cc:` and delivery headers
+    pub fn destinations(&self) -> &[&'a str] {
+        &self.destinations
     }
 
     pub fn subject(&self) -> Option<&str> {
         self.subject
     }
+
+    pub fn date(&self) -> Option<&DateTime> {
+        self.date
+    }
+
+    pub fn authentication_results(&self) -> &[&str] {
+        &self.authentication_results
+    }
 }
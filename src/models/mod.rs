@@ -1,5 +1,19 @@
 mod config;
 mod message_metadata;
+mod processing_summary;
+mod rule;
+mod sender_pattern;
+mod sender_usage;
+mod subject_commands;
 
-pub(crate) use config::AppConfig;
+pub use config::RunMode;
+pub(crate) use config::{
+    AppConfig, ArchiveFormat, BlockedAction, BodyDownload, EmbeddedEncoding, ExistingFiles, InlineParts, LinkSchemes,
+    OversizedAction, RequiredAuthentication, RequiredSignature, SubjectCommand,
+};
 pub(crate) use message_metadata::MessageMetadata;
+pub use processing_summary::ProcessingSummary;
+pub(crate) use rule::{is_accepted_file_type, AttachmentFacts, EmailFacts, PostProcess, RuleAction, Rules};
+pub(crate) use sender_pattern::SenderPatterns;
+pub(crate) use sender_usage::{current_day, SenderUsage};
+pub(crate) use subject_commands::SubjectCommands;
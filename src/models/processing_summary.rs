@@ -0,0 +1,83 @@
+use std::fmt;
+use std::process::ExitCode;
+
+/// Counts of the emails handled in a processing run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessingSummary {
+    processed: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+impl ProcessingSummary {
+    /// Exit code of a run stopped by a fatal error, such as an invalid configuration or a lost connection.
+    pub const FATAL_ERROR_EXIT_CODE: u8 = 1;
+    /// Exit code of a run where at least one email failed to be processed.
+    pub const FAILED_EMAILS_EXIT_CODE: u8 = 2;
+    /// Exit code of a run with nothing to do.
+    pub const NOTHING_TO_DO_EXIT_CODE: u8 = 3;
+
+    pub(crate) fn record_processed(&mut self) {
+        self.processed += 1;
+    }
+
+    pub(crate) fn record_skipped(&mut self) {
+        self.skipped += 1;
+    }
+
+    pub(crate) fn record_failed(&mut self) {
+        self.failed += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.processed += other.processed;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+    }
+
+    /// Number of emails with at least one saved attachment.
+    #[must_use]
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+
+    /// Number of emails without any accepted attachment, or from a blocked sender.
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Number of emails that could not be processed.
+    #[must_use]
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Exit code describing the outcome of a oneshot run that completed.
+    ///
+    /// * `0` - At least one email was processed and none failed.
+    /// * `2` - At least one email failed to be processed.
+    /// * `3` - There was nothing to do.
+    ///
+    /// Fatal errors that stop the run exit with [`FATAL_ERROR_EXIT_CODE`](Self::FATAL_ERROR_EXIT_CODE) instead.
+    #[must_use]
+    pub fn exit_code(&self) -> ExitCode {
+        if self.failed > 0 {
+            ExitCode::from(Self::FAILED_EMAILS_EXIT_CODE)
+        } else if self.processed > 0 {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(Self::NOTHING_TO_DO_EXIT_CODE)
+        }
+    }
+}
+
+impl fmt::Display for ProcessingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} processed, {} skipped, {} failed",
+            self.processed, self.skipped, self.failed
+        )
+    }
+}
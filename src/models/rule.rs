@@ -0,0 +1,268 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use super::SenderPatterns;
+use crate::ImapAttachmentDaemonError;
+
+// Whether a matching rule saves or discards an attachment. Rejecting stops the evaluation of the following rules.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RuleAction {
+    #[default]
+    Accept,
+    Reject,
+}
+
+// What to do with an email once one of its attachments was saved
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PostProcess {
+    // Mark the email as read and move it to the trash
+    #[default]
+    Trash,
+    // Mark the email as read and leave it in the inbox
+    Read,
+    // Leave the email unread in the inbox
+    Leave,
+    // Mark the email as read and move it to the given mailbox
+    Move(String),
+}
+
+impl fmt::Display for PostProcess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Trash => write!(f, "move to Trash"),
+            Self::Read => write!(f, "mark as read"),
+            Self::Leave => write!(f, "leave unread"),
+            Self::Move(mailbox) => write!(f, "move to {mailbox}"),
+        }
+    }
+}
+
+// A rule as written in the rules file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    name: String,
+    #[serde(default)]
+    senders: BTreeSet<String>,
+    target: Option<String>,
+    subject: Option<String>,
+    tag: Option<String>,
+    #[serde(default)]
+    extensions: BTreeSet<String>,
+    #[serde(default)]
+    mime_types: BTreeSet<String>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+    #[serde(default)]
+    action: RuleAction,
+    directory: Option<String>,
+    post_process: Option<PostProcess>,
+    #[serde(default, rename = "continue")]
+    continue_evaluation: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RuleDefinition>,
+}
+
+// An email as seen by the rules
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct EmailFacts<'a> {
+    pub sender: &'a str,
+    pub target: Option<&'a str>,
+    pub tag: Option<&'a str>,
+    pub subject: Option<&'a str>,
+}
+
+// An attachment as seen by the rules, with its lowercase extension and MIME type
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct AttachmentFacts<'a> {
+    pub extension: Option<&'a str>,
+    pub mime_type: Option<&'a str>,
+    pub size: usize,
+}
+
+// A routing rule, matching emails and attachments when all its conditions are met. Rules without extension condition
+// only match the accepted file types of the configuration. Like accepted file types, extensions may be MIME types.
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub name: String,
+    senders: Option<SenderPatterns>,
+    target: Option<String>,
+    subject: Option<Regex>,
+    tag: Option<String>,
+    extensions: BTreeSet<String>,
+    mime_types: BTreeSet<String>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+    pub action: RuleAction,
+    pub directory: Option<String>,
+    pub post_process: Option<PostProcess>,
+    pub continue_evaluation: bool,
+}
+
+impl Rule {
+    fn compile(definition: RuleDefinition) -> Result<Self, ImapAttachmentDaemonError> {
+        let subject = definition
+            .subject
+            .map(|subject| {
+                RegexBuilder::new(&subject)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|source| ImapAttachmentDaemonError::InvalidRule {
+                        rule: definition.name.clone(),
+                        reason: source.to_string(),
+                    })
+            })
+            .transpose()?;
+        Ok(Self {
+            senders: (!definition.senders.is_empty())
+                .then(|| SenderPatterns::parse(&definition.senders))
+                .transpose()?,
+            target: definition.target,
+            subject,
+            tag: definition.tag,
+            extensions: lowercase(definition.extensions),
+            mime_types: lowercase(definition.mime_types),
+            min_size: definition.min_size,
+            max_size: definition.max_size,
+            action: definition.action,
+            directory: definition.directory,
+            post_process: definition.post_process,
+            continue_evaluation: definition.continue_evaluation,
+            name: definition.name,
+        })
+    }
+
+    pub(crate) fn matches_email(&self, email: &EmailFacts) -> bool {
+        self.senders
+            .as_ref()
+            .is_none_or(|senders| senders.matches(email.sender))
+            && self.target.as_ref().is_none_or(|target| {
+                email
+                    .target
+                    .is_some_and(|email_target| email_target.eq_ignore_ascii_case(target))
+            })
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| email.tag.is_some_and(|email_tag| email_tag.eq_ignore_ascii_case(tag)))
+            && self
+                .subject
+                .as_ref()
+                .is_none_or(|subject| subject.is_match(email.subject.unwrap_or_default()))
+    }
+
+    pub(crate) fn matches_attachment(
+        &self,
+        attachment: &AttachmentFacts,
+        accepted_file_types: &BTreeSet<String>,
+    ) -> bool {
+        let extensions = if self.extensions.is_empty() {
+            accepted_file_types
+        } else {
+            &self.extensions
+        };
+        is_accepted_file_type(extensions, attachment)
+            && (self.mime_types.is_empty()
+                || attachment.mime_type.is_some_and(|mime_type| {
+                    self.mime_types
+                        .iter()
+                        .any(|pattern| mime_type_matches(pattern, mime_type))
+                }))
+            && self.min_size.is_none_or(|min_size| attachment.size >= min_size)
+            && self.max_size.is_none_or(|max_size| attachment.size <= max_size)
+    }
+
+    // The directory of saved attachments, with the `{sender}`, `{sender_domain}`, `{tag}` and `{rule}` placeholders
+    // replaced. Placeholder values cannot escape the directory.
+    pub(crate) fn attachments_dir(&self, email: &EmailFacts) -> Option<String> {
+        let directory = self.directory.as_ref()?;
+        let sender_domain = email.sender.rsplit_once('@').map_or("", |(_, domain)| domain);
+        Some(
+            directory
+                .replace("{sender}", &path_component(email.sender))
+                .replace("{sender_domain}", &path_component(sender_domain))
+                .replace("{tag}", &path_component(email.tag.unwrap_or_default()))
+                .replace("{rule}", &path_component(&self.name)),
+        )
+    }
+}
+
+// Whether an attachment has one of the file types, given as extensions or as MIME types such as `application/epub+zip`
+// or `image/*`
+pub(crate) fn is_accepted_file_type(file_types: &BTreeSet<String>, attachment: &AttachmentFacts) -> bool {
+    attachment
+        .extension
+        .is_some_and(|extension| file_types.contains(extension))
+        || attachment.mime_type.is_some_and(|mime_type| {
+            file_types
+                .iter()
+                .filter(|file_type| file_type.contains('/'))
+                .any(|file_type| mime_type_matches(file_type, mime_type))
+        })
+}
+
+fn lowercase(values: BTreeSet<String>) -> BTreeSet<String> {
+    values.into_iter().map(|value| value.to_lowercase()).collect()
+}
+
+// Patterns such as `image/*` match any subtype
+fn mime_type_matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(main_type) => mime_type
+            .split_once('/')
+            .is_some_and(|(mime_main_type, _)| mime_main_type == main_type),
+        None => pattern == mime_type,
+    }
+}
+
+pub(super) fn path_component(value: &str) -> String {
+    let component = value.to_lowercase().replace(['/', '\\'], "_");
+    if component.is_empty() || component.chars().all(|char| char == '.') {
+        "_".to_string()
+    } else {
+        component
+    }
+}
+
+// The ordered rules of the rules file
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub(crate) fn load(path: &Path) -> Result<Self, ImapAttachmentDaemonError> {
+        Self::parse(&std::fs::read_to_string(path)?, path)
+    }
+
+    pub(crate) fn parse(content: &str, path: &Path) -> Result<Self, ImapAttachmentDaemonError> {
+        let rules_file =
+            toml::from_str::<RulesFile>(content).map_err(|err| ImapAttachmentDaemonError::InvalidRulesFile {
+                path: path.to_path_buf(),
+                reason: err.message().to_string(),
+            })?;
+        rules_file
+            .rules
+            .into_iter()
+            .map(Rule::compile)
+            .collect::<Result<Vec<Rule>, ImapAttachmentDaemonError>>()
+            .map(Self)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+#[path = "test_rule.rs"]
+mod test_rule;
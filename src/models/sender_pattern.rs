@@ -0,0 +1,112 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::ImapAttachmentDaemonError;
+
+// A whitelist or blocklist entry matching sender addresses, case-insensitively:
+// * `alice@example.com` matches that address only.
+// * `@example.com` matches any address of the domain.
+// * `*.example.com` matches any address of the subdomains of the domain.
+// * `/^alice(\+.*)?@example\.com$/` matches addresses with the regex between slashes.
+// * Any other entry containing `*` or `?` is a glob pattern matched against the whole address.
+#[derive(Debug, Clone)]
+pub(crate) enum SenderPattern {
+    Address(String),
+    Domain(String),
+    Subdomains(String),
+    Glob { pattern: String, regex: Regex },
+    Regex(Regex),
+}
+
+impl SenderPattern {
+    pub(crate) fn parse(entry: &str) -> Result<Self, ImapAttachmentDaemonError> {
+        let entry = entry.trim();
+        if let Some(regex) = entry
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+            .filter(|regex| !regex.is_empty())
+        {
+            return Ok(Self::Regex(compile_pattern(entry, regex)?));
+        }
+        if let Some(domain) = entry.strip_prefix("*.").filter(|domain| !domain.contains(['*', '?'])) {
+            return Ok(Self::Subdomains(format!(".{domain}")));
+        }
+        if entry.contains(['*', '?']) {
+            let regex = entry
+                .split('*')
+                .map(|part| part.split('?').map(regex::escape).collect::<Vec<String>>().join("."))
+                .collect::<Vec<String>>()
+                .join(".*");
+            return Ok(Self::Glob {
+                pattern: entry.to_string(),
+                regex: compile_pattern(entry, &format!("^{regex}$"))?,
+            });
+        }
+        if entry.starts_with('@') {
+            return Ok(Self::Domain(entry.to_string()));
+        }
+        Ok(Self::Address(entry.to_string()))
+    }
+
+    pub(crate) fn matches(&self, address: &str) -> bool {
+        let address = address.to_lowercase();
+        match self {
+            Self::Address(expected) => address == expected.to_lowercase(),
+            // Both keep the separator, `@example.com` and `.example.com`, so that `badexample.com` does not match
+            Self::Domain(suffix) => address.ends_with(&suffix.to_lowercase()),
+            Self::Subdomains(suffix) => address
+                .rsplit_once('@')
+                .is_some_and(|(_, domain)| domain.ends_with(&suffix.to_lowercase())),
+            Self::Glob { regex, .. } | Self::Regex(regex) => regex.is_match(&address),
+        }
+    }
+
+    // A string contained in every matching address, for the substring matching of IMAP `FROM` search keys. Regex
+    // patterns, and glob patterns without literal text, cannot be expressed this way.
+    pub(crate) fn imap_search_term(&self) -> Option<&str> {
+        match self {
+            Self::Address(term) | Self::Domain(term) | Self::Subdomains(term) => Some(term),
+            Self::Glob { pattern, .. } => pattern
+                .split(['*', '?'])
+                .max_by_key(|literal| literal.len())
+                .filter(|literal| !literal.is_empty()),
+            Self::Regex(_) => None,
+        }
+    }
+}
+
+fn compile_pattern(entry: &str, regex: &str) -> Result<Regex, ImapAttachmentDaemonError> {
+    RegexBuilder::new(regex)
+        .case_insensitive(true)
+        .build()
+        .map_err(|source| ImapAttachmentDaemonError::InvalidSenderPattern {
+            pattern: entry.to_string(),
+            source,
+        })
+}
+
+// The sender patterns of a whitelist or blocklist
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SenderPatterns(Vec<SenderPattern>);
+
+impl SenderPatterns {
+    pub(crate) fn parse<'a>(entries: impl IntoIterator<Item = &'a String>) -> Result<Self, ImapAttachmentDaemonError> {
+        entries
+            .into_iter()
+            .map(|entry| SenderPattern::parse(entry))
+            .collect::<Result<Vec<SenderPattern>, ImapAttachmentDaemonError>>()
+            .map(Self)
+    }
+
+    pub(crate) fn matches(&self, address: &str) -> bool {
+        self.0.iter().any(|pattern| pattern.matches(address))
+    }
+
+    // The IMAP `FROM` search terms of all patterns, or `None` if any pattern cannot be expressed as a search term
+    pub(crate) fn imap_search_terms(&self) -> Option<Vec<&str>> {
+        self.0.iter().map(SenderPattern::imap_search_term).collect()
+    }
+}
+
+#[cfg(test)]
+#[path = "test_sender_pattern.rs"]
+mod test_sender_pattern;
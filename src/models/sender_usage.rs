@@ -0,0 +1,60 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// The total size of the emails saved from each sender during the current UTC day, for the daily size limit per sender.
+// It is kept in memory, so it starts over when the daemon restarts and is not carried over between oneshot runs.
+#[derive(Debug, Default)]
+pub(crate) struct SenderUsage {
+    // Days since the Unix epoch
+    day: u64,
+    sizes: HashMap<String, u64>,
+    // UIDs of the recorded emails, so that an email found by several searches is only counted once
+    uids: HashSet<u32>,
+}
+
+impl SenderUsage {
+    // Checks whether an email from a sender on a given day fits within the limit, along with the emails already
+    // recorded for the sender on that day. An email that is already recorded always fits.
+    pub(crate) fn fits(&self, uid: Option<u32>, sender: &str, size: u64, day: u64, limit: u64) -> bool {
+        if day != self.day {
+            return size <= limit;
+        }
+        if uid.is_some_and(|uid| self.uids.contains(&uid)) {
+            return true;
+        }
+        let total = self.sizes.get(&sender.to_lowercase()).copied().unwrap_or_default();
+        total.saturating_add(size) <= limit
+    }
+
+    // Records an email saved from a sender on a given day, unless its UID is already recorded. Emails from local sources
+    // have no UID and are always recorded.
+    pub(crate) fn record(&mut self, uid: Option<u32>, sender: &str, size: u64, day: u64) {
+        self.start_day(day);
+        if uid.is_some_and(|uid| !self.uids.insert(uid)) {
+            return;
+        }
+        let total = self.sizes.entry(sender.to_lowercase()).or_default();
+        *total = total.saturating_add(size);
+    }
+
+    fn start_day(&mut self, day: u64) {
+        if day != self.day {
+            self.day = day;
+            self.sizes.clear();
+            self.uids.clear();
+        }
+    }
+}
+
+// The current UTC day, in days since the Unix epoch
+pub(crate) fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+#[path = "test_sender_usage.rs"]
+mod test_sender_usage;
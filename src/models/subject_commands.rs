@@ -0,0 +1,65 @@
+use std::collections::BTreeSet;
+
+use super::rule::path_component;
+use super::SubjectCommand;
+
+// The commands given in the subject of an email as bracketed tokens, such as `[shelf:scifi] [overwrite] My book`.
+// Unknown tokens, disabled commands and commands missing their value are ignored, so that subjects such as
+// `[EXTERNAL] My book` are left alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SubjectCommands {
+    // Subdirectory of the attachments directory, safe to use as a path component
+    pub shelf: Option<String>,
+    // Tag used by the rules instead of the sub-address tag
+    pub tag: Option<String>,
+    // Replace existing files, which only matters when they are renamed by default
+    pub overwrite: bool,
+    // Save the attachments of a test send without post-processing the email
+    pub dry: bool,
+}
+
+impl SubjectCommands {
+    pub(crate) fn parse(subject: &str, enabled: &BTreeSet<SubjectCommand>) -> Self {
+        let mut commands = Self::default();
+        for token in bracketed_tokens(subject) {
+            let (name, value) = token
+                .split_once(':')
+                .map_or((token, None), |(name, value)| (name, Some(value.trim())));
+            let command = match name.trim().to_lowercase().as_str() {
+                "shelf" => SubjectCommand::Shelf,
+                "tag" => SubjectCommand::Tag,
+                "overwrite" => SubjectCommand::Overwrite,
+                "dry" => SubjectCommand::Dry,
+                _ => continue,
+            };
+            if !enabled.contains(&command) {
+                continue;
+            }
+            match (command, value) {
+                (SubjectCommand::Shelf, Some(shelf)) if !shelf.is_empty() => {
+                    commands.shelf = Some(path_component(shelf));
+                }
+                (SubjectCommand::Tag, Some(tag)) if !tag.is_empty() => commands.tag = Some(tag.to_string()),
+                (SubjectCommand::Overwrite, None) => commands.overwrite = true,
+                (SubjectCommand::Dry, None) => commands.dry = true,
+                _ => {}
+            }
+        }
+        commands
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn bracketed_tokens(subject: &str) -> impl Iterator<Item = &str> {
+    subject
+        .split('[')
+        .skip(1)
+        .filter_map(|part| part.split_once(']').map(|(token, _)| token.trim()))
+}
+
+#[cfg(test)]
+#[path = "test_subject_commands.rs"]
+mod test_subject_commands;
@@ -0,0 +1,158 @@
+mod rule_tests {
+    use std::collections::BTreeSet;
+    use std::path::Path;
+
+    use super::super::{AttachmentFacts, EmailFacts, PostProcess, RuleAction, Rules};
+    use crate::ImapAttachmentDaemonError;
+
+    fn rules(content: &str) -> Rules {
+        Rules::parse(content, Path::new("rules.toml")).unwrap()
+    }
+
+    fn accepted_file_types() -> BTreeSet<String> {
+        BTreeSet::from_iter(["epub".to_string(), "pdf".to_string()])
+    }
+
+    const EMAIL: EmailFacts = EmailFacts {
+        sender: "Alice@Publisher.example",
+        target: Some("target+books@example.com"),
+        tag: Some("books"),
+        subject: Some("Your weekly Newsletter"),
+    };
+
+    // Rules are parsed in order, with their actions and defaults
+    #[test]
+    fn test_parse_rules() {
+        let rules = rules(
+            r#"
+            [[rules]]
+            name = "newsletters"
+            subject = "newsletter"
+            action = "reject"
+
+            [[rules]]
+            name = "books"
+            senders = ["@publisher.example"]
+            directory = "books/{sender_domain}"
+            post_process = { move = "Archive" }
+            continue = true
+
+            [[rules]]
+            name = "default"
+            "#,
+        );
+
+        let rules = rules.iter().collect::<Vec<_>>();
+        assert_eq!(
+            rules.iter().map(|rule| rule.name.as_str()).collect::<Vec<&str>>(),
+            vec!["newsletters", "books", "default"]
+        );
+        assert_eq!(rules[0].action, RuleAction::Reject);
+        assert_eq!(rules[1].action, RuleAction::Accept);
+        assert_eq!(rules[1].post_process, Some(PostProcess::Move("Archive".to_string())));
+        assert!(rules[1].continue_evaluation);
+        assert_eq!(rules[2].post_process, None);
+        assert!(!rules[2].continue_evaluation);
+    }
+
+    // Email conditions must all match, ignoring case
+    #[test]
+    fn test_email_conditions() {
+        let matches = |content: &str| rules(content).iter().next().unwrap().matches_email(&EMAIL);
+
+        assert!(matches("[[rules]]\nname = \"any\""));
+        assert!(matches(
+            "[[rules]]\nname = \"sender\"\nsenders = [\"@publisher.example\"]"
+        ));
+        assert!(!matches("[[rules]]\nname = \"sender\"\nsenders = [\"@other.example\"]"));
+        assert!(matches(
+            "[[rules]]\nname = \"target\"\ntarget = \"Target+Books@example.com\""
+        ));
+        assert!(!matches(
+            "[[rules]]\nname = \"target\"\ntarget = \"target@example.com\""
+        ));
+        assert!(matches("[[rules]]\nname = \"tag\"\ntag = \"BOOKS\""));
+        assert!(!matches("[[rules]]\nname = \"tag\"\ntag = \"papers\""));
+        assert!(matches(
+            "[[rules]]\nname = \"subject\"\nsubject = \"^your .* newsletter$\""
+        ));
+        assert!(!matches("[[rules]]\nname = \"subject\"\nsubject = \"invoice\""));
+        assert!(!matches(
+            "[[rules]]\nname = \"all\"\nsenders = [\"@publisher.example\"]\ntag = \"papers\""
+        ));
+    }
+
+    // Attachment conditions fall back to the accepted file types when no extension is given
+    #[test]
+    fn test_attachment_conditions() {
+        let attachment = AttachmentFacts {
+            extension: Some("pdf"),
+            mime_type: Some("application/pdf"),
+            size: 1000,
+        };
+        let matches = |content: &str| {
+            rules(content)
+                .iter()
+                .next()
+                .unwrap()
+                .matches_attachment(&attachment, &accepted_file_types())
+        };
+
+        assert!(matches("[[rules]]\nname = \"default\""));
+        assert!(!matches("[[rules]]\nname = \"extensions\"\nextensions = [\"CBZ\"]"));
+        assert!(matches("[[rules]]\nname = \"extensions\"\nextensions = [\"PDF\"]"));
+        assert!(matches("[[rules]]\nname = \"mime\"\nmime_types = [\"application/*\"]"));
+        assert!(!matches(
+            "[[rules]]\nname = \"mime\"\nmime_types = [\"image/*\", \"text/plain\"]"
+        ));
+        assert!(matches("[[rules]]\nname = \"size\"\nmin_size = 1000\nmax_size = 1000"));
+        assert!(!matches("[[rules]]\nname = \"size\"\nmin_size = 1001"));
+        assert!(!matches("[[rules]]\nname = \"size\"\nmax_size = 999"));
+    }
+
+    // Directory placeholders are replaced by values that cannot escape the directory
+    #[test]
+    fn test_attachments_dir_placeholders() {
+        let rules = rules("[[rules]]\nname = \"Books\"\ndirectory = \"{rule}/{sender_domain}/{tag}/{sender}\"");
+        let rule = rules.iter().next().unwrap();
+
+        assert_eq!(
+            rule.attachments_dir(&EMAIL).unwrap(),
+            "books/publisher.example/books/alice@publisher.example"
+        );
+        let email = EmailFacts {
+            sender: "../../etc@..",
+            tag: None,
+            ..EMAIL
+        };
+        assert_eq!(rule.attachments_dir(&email).unwrap(), "books/_/_/.._.._etc@..");
+    }
+
+    // Invalid rules files are reported with their path or the invalid rule
+    #[test]
+    fn test_invalid_rules() {
+        let error = Rules::parse(
+            "[[rules]]\nname = \"typo\"\nsender = [\"a@example.com\"]",
+            Path::new("rules.toml"),
+        );
+        assert!(matches!(error, Err(ImapAttachmentDaemonError::InvalidRulesFile { .. })));
+
+        let error = Rules::parse(
+            "[[rules]]\nname = \"regex\"\nsubject = \"(unclosed\"",
+            Path::new("rules.toml"),
+        );
+        assert!(matches!(
+            error,
+            Err(ImapAttachmentDaemonError::InvalidRule { rule, .. }) if rule == "regex"
+        ));
+
+        let error = Rules::parse(
+            "[[rules]]\nname = \"pattern\"\nsenders = [\"/(unclosed/\"]",
+            Path::new("rules.toml"),
+        );
+        assert!(matches!(
+            error,
+            Err(ImapAttachmentDaemonError::InvalidSenderPattern { .. })
+        ));
+    }
+}
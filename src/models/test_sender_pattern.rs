@@ -0,0 +1,84 @@
+mod sender_pattern_tests {
+    use super::super::{SenderPattern, SenderPatterns};
+
+    fn matches(entry: &str, address: &str) -> bool {
+        SenderPattern::parse(entry).unwrap().matches(address)
+    }
+
+    // Addresses match exactly, ignoring case
+    #[test]
+    fn test_address_pattern() {
+        assert!(matches("alice@example.com", "alice@example.com"));
+        assert!(matches("alice@example.com", "Alice@Example.COM"));
+        assert!(matches("Alice@Example.com", "alice@example.com"));
+        assert!(!matches("alice@example.com", "alice@example.org"));
+        assert!(!matches("alice@example.com", "malice@example.com"));
+    }
+
+    // Domain entries match any address of the domain, but not of its subdomains or of similar domains
+    #[test]
+    fn test_domain_pattern() {
+        assert!(matches("@example.com", "alice@example.com"));
+        assert!(matches("@example.com", "BOB@EXAMPLE.COM"));
+        assert!(!matches("@example.com", "alice@mail.example.com"));
+        assert!(!matches("@example.com", "alice@badexample.com"));
+    }
+
+    // Subdomain entries match any address of the subdomains, but not of the domain itself
+    #[test]
+    fn test_subdomains_pattern() {
+        assert!(matches("*.example.com", "alice@mail.example.com"));
+        assert!(matches("*.example.com", "alice@a.b.EXAMPLE.com"));
+        assert!(!matches("*.example.com", "alice@example.com"));
+        assert!(!matches("*.example.com", "alice@mail.badexample.com"));
+    }
+
+    // Glob entries match the whole address
+    #[test]
+    fn test_glob_pattern() {
+        assert!(matches("alice+*@example.com", "alice+books@example.com"));
+        assert!(matches("alice+*@example.com", "ALICE+books@example.com"));
+        assert!(matches("user?@example.com", "user1@example.com"));
+        assert!(!matches("user?@example.com", "user12@example.com"));
+        assert!(!matches("alice+*@example.com", "alice@example.com"));
+        // Regex metacharacters are literal in globs
+        assert!(!matches("alice.*@example.com", "alicex@example.com"));
+    }
+
+    // Regex entries are delimited by slashes and ignore case
+    #[test]
+    fn test_regex_pattern() {
+        assert!(matches(r"/^(alice|bob)@example\.com$/", "Bob@example.com"));
+        assert!(!matches(r"/^(alice|bob)@example\.com$/", "carol@example.com"));
+        assert!(SenderPattern::parse("/(unclosed/").is_err());
+    }
+
+    // Patterns are turned into substrings for IMAP search, when possible
+    #[test]
+    fn test_imap_search_terms() {
+        let terms = |entries: &[&str]| {
+            let entries = entries.iter().map(ToString::to_string).collect::<Vec<String>>();
+            SenderPatterns::parse(&entries)
+                .unwrap()
+                .imap_search_terms()
+                .map(|terms| terms.into_iter().map(ToString::to_string).collect::<Vec<String>>())
+        };
+
+        assert_eq!(
+            terms(&[
+                "Alice@Example.com",
+                "@example.org",
+                "*.example.net",
+                "books-*@example.io"
+            ]),
+            Some(vec![
+                "Alice@Example.com".to_string(),
+                "@example.org".to_string(),
+                ".example.net".to_string(),
+                "@example.io".to_string(),
+            ])
+        );
+        assert_eq!(terms(&["alice@example.com", r"/^bob@example\.com$/"]), None);
+        assert_eq!(terms(&["alice@example.com", "*"]), None);
+    }
+}
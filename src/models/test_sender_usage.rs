@@ -0,0 +1,44 @@
+mod sender_usage_tests {
+    use super::super::SenderUsage;
+
+    // Recorded emails count towards the daily limit of their sender, ignoring case, and checking an email records nothing
+    #[test]
+    fn test_fits_applies_daily_limit() {
+        let mut usage = SenderUsage::default();
+
+        assert!(usage.fits(Some(1), "reader@example.com", 60, 1, 100));
+        assert!(usage.fits(Some(1), "reader@example.com", 60, 1, 100));
+        usage.record(Some(1), "reader@example.com", 60, 1);
+        assert!(!usage.fits(Some(2), "Reader@Example.com", 50, 1, 100));
+        assert!(usage.fits(Some(2), "reader@example.com", 40, 1, 100));
+        usage.record(Some(2), "reader@example.com", 40, 1);
+        assert!(!usage.fits(Some(3), "reader@example.com", 1, 1, 100));
+        assert!(usage.fits(Some(3), "other@example.com", 100, 1, 100));
+        assert!(!usage.fits(Some(3), "other@example.com", 101, 1, 100));
+    }
+
+    // An email is only counted once per UID, and an email already recorded still fits
+    #[test]
+    fn test_record_counts_each_uid_once() {
+        let mut usage = SenderUsage::default();
+
+        usage.record(Some(1), "reader@example.com", 60, 1);
+        usage.record(Some(1), "reader@example.com", 60, 1);
+        assert!(usage.fits(Some(1), "reader@example.com", 60, 1, 100));
+        assert!(usage.fits(Some(2), "reader@example.com", 40, 1, 100));
+        usage.record(None, "reader@example.com", 30, 1);
+        usage.record(None, "reader@example.com", 10, 1);
+        assert!(!usage.fits(Some(2), "reader@example.com", 1, 1, 100));
+    }
+
+    // Usage starts over every day
+    #[test]
+    fn test_usage_resets_every_day() {
+        let mut usage = SenderUsage::default();
+
+        usage.record(Some(1), "reader@example.com", 100, 1);
+        assert!(!usage.fits(Some(2), "reader@example.com", 1, 1, 100));
+        assert!(usage.fits(Some(2), "reader@example.com", 100, 2, 100));
+        assert!(!usage.fits(Some(1), "reader@example.com", 101, 2, 100));
+    }
+}
@@ -0,0 +1,67 @@
+mod subject_commands_tests {
+    use std::collections::BTreeSet;
+
+    use super::super::SubjectCommands;
+    use crate::models::SubjectCommand;
+
+    fn all_commands() -> BTreeSet<SubjectCommand> {
+        BTreeSet::from_iter([
+            SubjectCommand::Shelf,
+            SubjectCommand::Tag,
+            SubjectCommand::Overwrite,
+            SubjectCommand::Dry,
+        ])
+    }
+
+    // Known tokens are parsed anywhere in the subject, ignoring case
+    #[test]
+    fn test_parse_subject_commands() {
+        let commands = SubjectCommands::parse("[Shelf: SciFi] My book [tag:work][OVERWRITE] [dry]", &all_commands());
+
+        assert_eq!(
+            commands,
+            SubjectCommands {
+                shelf: Some("scifi".to_string()),
+                tag: Some("work".to_string()),
+                overwrite: true,
+                dry: true,
+            }
+        );
+    }
+
+    // Unknown tokens, tokens with missing or unexpected values and unclosed brackets are ignored
+    #[test]
+    fn test_ignore_unknown_tokens() {
+        let commands = SubjectCommands::parse(
+            "[EXTERNAL] Re: [list] [shelf] [shelf:] [overwrite:yes] [dry:no] [tag:unclosed",
+            &all_commands(),
+        );
+
+        assert!(commands.is_empty());
+    }
+
+    // Only the enabled commands are parsed
+    #[test]
+    fn test_disabled_commands() {
+        let subject = "[shelf:scifi] [dry] My book";
+
+        assert!(SubjectCommands::parse(subject, &BTreeSet::new()).is_empty());
+        assert_eq!(
+            SubjectCommands::parse(subject, &BTreeSet::from_iter([SubjectCommand::Dry])),
+            SubjectCommands {
+                dry: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    // Shelves cannot escape the attachments directory
+    #[test]
+    fn test_shelf_is_a_single_directory() {
+        let shelf = |subject: &str| SubjectCommands::parse(subject, &all_commands()).shelf.unwrap();
+
+        assert_eq!(shelf("[shelf:../../etc]"), ".._.._etc");
+        assert_eq!(shelf("[shelf:..]"), "_");
+        assert_eq!(shelf("[shelf:/root]"), "_root");
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use secrecy::{ExposeSecret, SecretString};
+use tempfile::{NamedTempFile, TempDir};
+
+use crate::ImapAttachmentDaemonError;
+
+// OpenPGP messages, see <https://www.rfc-editor.org/rfc/rfc4880>, decrypted and verified by GnuPG in a home directory
+// of its own, holding the secret keys of the account and the public keys of the trusted signers. GnuPG reports what it
+// did with status lines, see <https://github.com/gpg/gnupg/blob/master/doc/DETAILS>.
+
+// Decrypted messages much larger than the encrypted message are compression bombs
+const MAX_COMPRESSION_RATIO: u64 = 100;
+const MIN_DECRYPTED_LIMIT: u64 = 1024 * 1024;
+const STATUS_PREFIX: &str = "[GNUPG:] ";
+
+// A GnuPG home directory, removed with its agent when dropped
+pub(crate) struct Keyring {
+    home: TempDir,
+    passphrase: Option<SecretString>,
+    // The email addresses of the trusted signers, by the fingerprint of their primary key
+    signers: BTreeMap<String, Vec<String>>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("home", &self.home.path())
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "[REDACTED]"))
+            .field("signers", &self.signers)
+            .finish()
+    }
+}
+
+// The content of a decrypted message, with the email addresses of the trusted signers whose signatures are valid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PgpContent {
+    pub content: Vec<u8>,
+    pub signers: Vec<String>,
+}
+
+// What GnuPG wrote to its output and its status lines
+struct GpgOutput {
+    output: Vec<u8>,
+    status: Vec<String>,
+}
+
+impl GpgOutput {
+    fn has(&self, keyword: &str) -> bool {
+        self.statuses(keyword).next().is_some()
+    }
+
+    // The arguments of the status lines with the keyword
+    fn statuses<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = Vec<&'a str>> + 'a {
+        self.status.iter().filter_map(move |line| {
+            let mut words = line.split(' ');
+            (words.next() == Some(keyword)).then(|| words.collect())
+        })
+    }
+}
+
+impl Keyring {
+    pub(crate) fn new(passphrase: Option<&SecretString>) -> Result<Self, ImapAttachmentDaemonError> {
+        Ok(Self {
+            home: tempfile::Builder::new().prefix("gnupg-").tempdir()?,
+            passphrase: passphrase.cloned(),
+            signers: BTreeMap::new(),
+        })
+    }
+
+    // Imports the secret keys of an ASCII-armored key block, checking that the passphrase unlocks each key that can
+    // decrypt messages. Keys that can only sign are kept but not checked.
+    pub(crate) fn import_secret_keys(&self, path: &Path) -> Result<(), ImapAttachmentDaemonError> {
+        let imported = self.gpg([OsStr::new("--import"), path.as_os_str()], 0)?;
+        let mut decryption_keys = 0;
+        for fingerprint in imported_fingerprints(&imported, true) {
+            let probe = self.temp_file(b"probe")?;
+            let Ok(encrypted) = self.gpg(
+                [
+                    OsStr::new("--encrypt"),
+                    OsStr::new("--recipient"),
+                    OsStr::new(fingerprint),
+                    probe.path().as_os_str(),
+                ],
+                MIN_DECRYPTED_LIMIT,
+            ) else {
+                continue;
+            };
+            let encrypted = self.temp_file(&encrypted.output)?;
+            let decrypted = self.gpg(
+                [OsStr::new("--decrypt"), encrypted.path().as_os_str()],
+                MIN_DECRYPTED_LIMIT,
+            );
+            if !decrypted.is_ok_and(|decrypted| decrypted.output == b"probe") {
+                return Err(invalid("wrong passphrase for the secret key"));
+            }
+            decryption_keys += 1;
+        }
+        if decryption_keys == 0 {
+            return Err(invalid("missing decryption key"));
+        }
+        Ok(())
+    }
+
+    // Imports the public keys of the trusted signers from an ASCII-armored key block, with the email addresses of
+    // their user IDs that are not revoked
+    pub(crate) fn import_signers(&mut self, path: &Path) -> Result<(), ImapAttachmentDaemonError> {
+        let imported = self.gpg([OsStr::new("--import"), path.as_os_str()], 0)?;
+        for fingerprint in imported_fingerprints(&imported, false) {
+            let listed = self.gpg(
+                [
+                    OsStr::new("--with-colons"),
+                    OsStr::new("--fixed-list-mode"),
+                    OsStr::new("--list-keys"),
+                    OsStr::new(fingerprint),
+                ],
+                MIN_DECRYPTED_LIMIT,
+            )?;
+            let emails = String::from_utf8_lossy(&listed.output)
+                .lines()
+                .map(|line| line.split(':').collect::<Vec<&str>>())
+                .filter(|fields| fields.first() == Some(&"uid") && fields.get(1) != Some(&"r"))
+                .filter_map(|fields| fields.get(9).and_then(|user_id| user_id_email(user_id)))
+                .collect();
+            let _ = self.signers.insert(fingerprint.to_string(), emails);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn has_signers(&self) -> bool {
+        !self.signers.is_empty()
+    }
+
+    // Decrypts a message encrypted to one of the secret keys, checking its integrity and its signatures against the
+    // trusted signers
+    pub(crate) fn decrypt_message(&self, message: &[u8]) -> Result<PgpContent, ImapAttachmentDaemonError> {
+        let encrypted = self.temp_file(message)?;
+        let limit = (u64::try_from(message.len()).unwrap_or(u64::MAX))
+            .saturating_mul(MAX_COMPRESSION_RATIO)
+            .max(MIN_DECRYPTED_LIMIT);
+        // GnuPG fails when a signature cannot be checked, so its exit status is not enough
+        let decrypted = match self.gpg([OsStr::new("--decrypt"), encrypted.path().as_os_str()], limit) {
+            Ok(decrypted) | Err(GpgError::Failed(decrypted)) => decrypted,
+            Err(GpgError::Io(err)) => return Err(err.into()),
+            Err(GpgError::TooLarge) => return Err(invalid("the decrypted message is too large")),
+        };
+        // Messages encrypted with a passphrase rather than a public key, or without integrity protection, are rejected
+        if !decrypted.has("DECRYPTION_KEY")
+            || !decrypted.has("DECRYPTION_OKAY")
+            || !decrypted.has("GOODMDC")
+            || decrypted.has("DECRYPTION_FAILED")
+        {
+            return Err(invalid("cannot decrypt the message"));
+        }
+        let signers = self.valid_signers(&decrypted);
+        Ok(PgpContent {
+            content: decrypted.output,
+            signers,
+        })
+    }
+
+    // Verifies a detached signature of the data, returning the email addresses of the trusted signers whose signature
+    // is valid
+    pub(crate) fn verify_detached_signature(
+        &self,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<Vec<String>, ImapAttachmentDaemonError> {
+        let data = self.temp_file(data)?;
+        let signature = self.temp_file(signature)?;
+        let verified = match self.gpg(
+            [
+                OsStr::new("--verify"),
+                signature.path().as_os_str(),
+                data.path().as_os_str(),
+            ],
+            0,
+        ) {
+            Ok(verified) | Err(GpgError::Failed(verified)) => verified,
+            Err(GpgError::Io(err)) => return Err(err.into()),
+            Err(GpgError::TooLarge) => return Err(invalid("invalid signature")),
+        };
+        Ok(self.valid_signers(&verified))
+    }
+
+    // The trusted signers with a good signature, made with a key that is neither expired nor revoked. Each signature
+    // starts with a `NEWSIG` status line.
+    fn valid_signers(&self, output: &GpgOutput) -> Vec<String> {
+        let mut signers = Vec::new();
+        let mut good = false;
+        for line in &output.status {
+            let fields = line.split(' ').collect::<Vec<&str>>();
+            match fields.first() {
+                Some(&"NEWSIG") => good = false,
+                Some(&"GOODSIG") => good = true,
+                // The fingerprint of the primary key is the last field
+                Some(&"VALIDSIG") if good => {
+                    if let Some(emails) = fields.get(10).and_then(|fingerprint| self.signers.get(*fingerprint)) {
+                        signers.extend(emails.iter().cloned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        signers
+    }
+
+    fn temp_file(&self, content: &[u8]) -> Result<NamedTempFile, ImapAttachmentDaemonError> {
+        let mut file = NamedTempFile::new_in(self.home.path())?;
+        file.write_all(content)?;
+        Ok(file)
+    }
+
+    // Runs GnuPG in the home directory, giving it the passphrase on its standard input. Its output is limited to the
+    // given number of bytes, or discarded when the limit is 0.
+    fn gpg<'a>(&self, args: impl IntoIterator<Item = &'a OsStr>, limit: u64) -> Result<GpgOutput, GpgError> {
+        let status_file = NamedTempFile::new_in(self.home.path())?;
+        let mut child = Command::new("gpg")
+            .arg("--homedir")
+            .arg(self.home.path())
+            .args([
+                "--batch",
+                "--no-tty",
+                "--yes",
+                "--pinentry-mode",
+                "loopback",
+                "--passphrase-fd",
+                "0",
+            ])
+            // Signers are trusted through the signers file only, and their keys are never downloaded
+            .args([
+                "--trust-model",
+                "always",
+                "--no-auto-key-retrieve",
+                "--weak-digest",
+                "SHA1",
+            ])
+            .arg("--status-file")
+            .arg(status_file.path())
+            .args(["--output", "-"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(if limit > 0 { Stdio::piped() } else { Stdio::null() })
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let passphrase = self
+                .passphrase
+                .as_ref()
+                .map_or("", |passphrase| passphrase.expose_secret());
+            // GnuPG may exit without reading its input, when no passphrase is needed
+            let _ = writeln!(stdin, "{passphrase}");
+        }
+        let mut output = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            let _ = stdout.take(limit.saturating_add(1)).read_to_end(&mut output)?;
+        }
+        if u64::try_from(output.len()).unwrap_or(u64::MAX) > limit && limit > 0 {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(GpgError::TooLarge);
+        }
+        let exit_status = child.wait()?;
+        let status = fs::read_to_string(status_file.path())?
+            .lines()
+            .filter_map(|line| line.strip_prefix(STATUS_PREFIX))
+            .map(str::to_string)
+            .collect();
+        let output = GpgOutput { output, status };
+        if exit_status.success() {
+            Ok(output)
+        } else {
+            Err(GpgError::Failed(output))
+        }
+    }
+}
+
+impl Drop for Keyring {
+    fn drop(&mut self) {
+        // The agent started by GnuPG would otherwise outlive its home directory
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(self.home.path())
+            .args(["--kill", "gpg-agent"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+enum GpgError {
+    Io(io::Error),
+    Failed(GpgOutput),
+    TooLarge,
+}
+
+impl From<io::Error> for GpgError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<GpgError> for ImapAttachmentDaemonError {
+    fn from(err: GpgError) -> Self {
+        match err {
+            GpgError::Io(err) => err.into(),
+            GpgError::Failed(_) => invalid("invalid key block"),
+            GpgError::TooLarge => invalid("the output of gpg is too large"),
+        }
+    }
+}
+
+// The fingerprints of the primary keys imported by GnuPG, only those with a secret key if required. The first field of
+// `IMPORT_OK` is a bit mask, where 16 flags a secret key.
+fn imported_fingerprints(output: &GpgOutput, secret: bool) -> Vec<&str> {
+    const SECRET_KEY: u32 = 16;
+    let mut fingerprints = output
+        .statuses("IMPORT_OK")
+        .filter(|fields| {
+            !secret
+                || fields
+                    .first()
+                    .and_then(|flags| flags.parse::<u32>().ok())
+                    .is_some_and(|flags| flags & SECRET_KEY != 0)
+        })
+        .filter_map(|fields| fields.get(1).copied())
+        .collect::<Vec<&str>>();
+    fingerprints.sort_unstable();
+    fingerprints.dedup();
+    fingerprints
+}
+
+// The email address of a user ID such as `Trusted Sender <trusted@example.com>`, where GnuPG escapes colons as `\x3a`
+fn user_id_email(user_id: &str) -> Option<String> {
+    let user_id = user_id.replace("\\x3a", ":");
+    let email = match (user_id.rfind('<'), user_id.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &user_id[start + 1..end],
+        _ => user_id.trim(),
+    };
+    email.contains('@').then(|| email.to_lowercase())
+}
+
+fn invalid(reason: &'static str) -> ImapAttachmentDaemonError {
+    ImapAttachmentDaemonError::OpenPgpError(reason)
+}
+
+#[cfg(test)]
+#[path = "test_openpgp.rs"]
+pub(crate) mod test_openpgp;
@@ -0,0 +1,233 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use mail_parser::{Message, MessagePart, MimeHeaders, PartType};
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+
+use crate::mail_parsing::parse_message;
+use crate::models::RequiredSignature;
+use crate::openpgp::{Keyring, PgpContent};
+use crate::smime::{self, SmimeContent};
+use crate::{AppConfig, ImapAttachmentDaemonError};
+
+// How many encryption and signature layers are unwrapped, such as a signed email that is then encrypted
+const MAX_LAYERS: usize = 3;
+
+// The private keys that decrypt emails and the trusted signers whose signatures are checked, loaded from the configured
+// files. OpenPGP keys live in a GnuPG keyring.
+#[derive(Clone, Default)]
+pub(crate) struct SecureMimeKeys {
+    smime_key: Option<PKey<Private>>,
+    smime_signers: Vec<X509>,
+    keyring: Option<Arc<Keyring>>,
+}
+
+impl fmt::Debug for SecureMimeKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecureMimeKeys")
+            .field("smime_key", &self.smime_key.as_ref().map(|_| "[REDACTED]"))
+            .field("smime_signers", &self.smime_signers.len())
+            .field("keyring", &self.keyring)
+            .finish()
+    }
+}
+
+impl SecureMimeKeys {
+    pub(crate) fn load(config: &AppConfig) -> Result<Self, ImapAttachmentDaemonError> {
+        let mut keys = Self::default();
+        let passphrase = config.key_passphrase.as_ref();
+        if let Some(path) = &config.smime_key_file {
+            keys.smime_key = Some(read_key_file(path, |text| smime::read_private_key(text, passphrase))?);
+        }
+        // The signers file holds both S/MIME certificates and OpenPGP public keys
+        let signers = match &config.signers_file {
+            Some(path) => Some((path, fs::read(path)?)),
+            None => None,
+        };
+        let has_label = |label: &str| {
+            signers
+                .as_ref()
+                .is_some_and(|(_, text)| String::from_utf8_lossy(text).contains(&format!("-----BEGIN {label}-----")))
+        };
+        if let Some((path, _)) = signers.as_ref().filter(|_| has_label("CERTIFICATE")) {
+            keys.smime_signers = read_key_file(path, smime::read_certificates)?;
+        }
+        let pgp_signers = signers.as_ref().filter(|_| has_label("PGP PUBLIC KEY BLOCK"));
+        if config.pgp_key_file.is_some() || pgp_signers.is_some() {
+            let mut keyring = Keyring::new(passphrase)?;
+            if let Some(path) = &config.pgp_key_file {
+                keyring
+                    .import_secret_keys(path)
+                    .map_err(|err| invalid_key_file(path, &err))?;
+            }
+            if let Some((path, _)) = pgp_signers {
+                keyring
+                    .import_signers(path)
+                    .map_err(|err| invalid_key_file(path, &err))?;
+            }
+            keys.keyring = Some(Arc::new(keyring));
+        }
+        Ok(keys)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.smime_key.is_none() && self.smime_signers.is_empty() && self.keyring.is_none()
+    }
+}
+
+fn read_key_file<T>(
+    path: &Path,
+    read: impl FnOnce(&[u8]) -> Result<T, ImapAttachmentDaemonError>,
+) -> Result<T, ImapAttachmentDaemonError> {
+    read(&fs::read(path)?).map_err(|err| invalid_key_file(path, &err))
+}
+
+fn invalid_key_file(path: &Path, err: &ImapAttachmentDaemonError) -> ImapAttachmentDaemonError {
+    ImapAttachmentDaemonError::InvalidKeyFile {
+        path: path.to_path_buf(),
+        reason: err.to_string(),
+    }
+}
+
+// A valid signature of an email by a trusted signer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Signer {
+    pub kind: RequiredSignature,
+    pub email: String,
+}
+
+// The content of an email once its encryption and signature layers are unwrapped, and the signers of these layers
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OpenedEmail {
+    pub content: Option<Vec<u8>>,
+    pub signers: Vec<Signer>,
+}
+
+impl OpenedEmail {
+    // Whether the email is signed as required by the given sender
+    pub(crate) fn is_signed_by(&self, sender: &str, required: RequiredSignature) -> bool {
+        required == RequiredSignature::None
+            || self.signers.iter().any(|signer| {
+                signer.email.eq_ignore_ascii_case(sender)
+                    && (required == RequiredSignature::Any || required == signer.kind)
+            })
+    }
+}
+
+// Unwraps the S/MIME and PGP/MIME encryption and signature layers of an email, decrypting them with the private keys
+// and checking their signatures against the trusted signers
+pub(crate) fn open_email(message: &Message, keys: &SecureMimeKeys) -> Result<OpenedEmail, ImapAttachmentDaemonError> {
+    let mut opened = OpenedEmail::default();
+    let mut content: Option<Vec<u8>> = None;
+    for _ in 0..MAX_LAYERS {
+        let unwrapped = match &content {
+            Some(content) => open_layer(&parse_message(content)?, keys, &mut opened.signers)?,
+            None => open_layer(message, keys, &mut opened.signers)?,
+        };
+        let Some(unwrapped) = unwrapped else {
+            break;
+        };
+        content = Some(unwrapped);
+    }
+    opened.content = content;
+    Ok(opened)
+}
+
+// Unwraps the outer layer of an email, if it is encrypted or signed
+fn open_layer(
+    message: &Message,
+    keys: &SecureMimeKeys,
+    signers: &mut Vec<Signer>,
+) -> Result<Option<Vec<u8>>, ImapAttachmentDaemonError> {
+    let Some(root) = message.parts.first() else {
+        return Ok(None);
+    };
+    match mime_type(root).as_str() {
+        "application/pkcs7-mime" | "application/x-pkcs7-mime" => {
+            match smime::open_message(root.contents(), keys.smime_key.as_ref(), &keys.smime_signers)? {
+                SmimeContent::Decrypted(content) => Ok(Some(content)),
+                SmimeContent::Signed {
+                    content,
+                    signers: emails,
+                } => {
+                    signers.extend(emails.into_iter().map(|email| Signer {
+                        kind: RequiredSignature::Smime,
+                        email,
+                    }));
+                    Ok(Some(content))
+                }
+            }
+        }
+        "multipart/encrypted" => {
+            let encrypted = subparts(message, root)
+                .find(|part| mime_type(part) == "application/octet-stream")
+                .ok_or(ImapAttachmentDaemonError::OpenPgpError("missing encrypted message"))?;
+            let keyring = keys.keyring.as_ref().ok_or(ImapAttachmentDaemonError::OpenPgpError(
+                "no secret key to decrypt the message",
+            ))?;
+            let PgpContent {
+                content,
+                signers: emails,
+            } = keyring.decrypt_message(encrypted.contents())?;
+            signers.extend(emails.into_iter().map(|email| Signer {
+                kind: RequiredSignature::Pgp,
+                email,
+            }));
+            Ok(Some(content))
+        }
+        "multipart/signed" => {
+            let mut parts = subparts(message, root);
+            let (Some(signed), Some(signature)) = (parts.next(), parts.next()) else {
+                return Ok(None);
+            };
+            // The signature is made over the signed part as sent, headers included and up to the line break of the
+            // boundary
+            let Some(data) = message.raw_message.get(signed.offset_header..signed.offset_end) else {
+                return Ok(None);
+            };
+            let (kind, emails) = match mime_type(signature).as_str() {
+                "application/pkcs7-signature" | "application/x-pkcs7-signature" => (
+                    RequiredSignature::Smime,
+                    smime::verify_detached_signature(data, signature.contents(), &keys.smime_signers)?,
+                ),
+                "application/pgp-signature" => (
+                    RequiredSignature::Pgp,
+                    match keys.keyring.as_ref().filter(|keyring| keyring.has_signers()) {
+                        Some(keyring) => keyring.verify_detached_signature(data, signature.contents())?,
+                        None => Vec::new(),
+                    },
+                ),
+                _ => return Ok(None),
+            };
+            signers.extend(emails.into_iter().map(|email| Signer { kind, email }));
+            Ok(Some(data.to_vec()))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn subparts<'a>(message: &'a Message, part: &'a MessagePart) -> impl Iterator<Item = &'a MessagePart<'a>> {
+    let ids = match &part.body {
+        PartType::Multipart(ids) => ids.as_slice(),
+        _ => &[],
+    };
+    ids.iter().filter_map(|&id| message.parts.get(id))
+}
+
+fn mime_type(part: &MessagePart) -> String {
+    part.content_type().map_or_else(String::new, |content_type| {
+        format!(
+            "{}/{}",
+            content_type.ctype(),
+            content_type.subtype().unwrap_or_default()
+        )
+        .to_lowercase()
+    })
+}
+
+#[cfg(test)]
+#[path = "test_secure_mime.rs"]
+mod test_secure_mime;
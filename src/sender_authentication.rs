@@ -0,0 +1,169 @@
+use crate::models::{MessageMetadata, RequiredAuthentication};
+use crate::AppConfig;
+
+// A single method result of an `Authentication-Results` header, such as `dkim=pass header.d=example.com`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AuthenticationResult {
+    pub method: String,
+    pub result: String,
+    pub properties: Vec<(String, String)>,
+}
+
+impl AuthenticationResult {
+    fn property(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(property, _)| property == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn passed(&self, method: &str) -> bool {
+        self.method == method && self.result == "pass"
+    }
+}
+
+// Parses the value of an `Authentication-Results` header into its authserv-id and its method results, see
+// <https://www.rfc-editor.org/rfc/rfc8601#section-2.2>
+pub(crate) fn parse_authentication_results(value: &str) -> Option<(String, Vec<AuthenticationResult>)> {
+    let mut statements = tokenize(value).into_iter();
+    // The authserv-id may be followed by a version number
+    let authserv_id = statements.next()?.into_iter().next()?;
+    let results = statements
+        .filter_map(|words| {
+            let mut words = words.into_iter();
+            let (method, result) = words.next()?.split_once('=').map(|(method, result)| {
+                // Methods may have a version, such as `dkim/1`
+                let method = method.split_once('/').map_or(method, |(method, _)| method);
+                (method.to_lowercase(), result.to_lowercase())
+            })?;
+            let properties = words
+                .filter_map(|word| {
+                    word.split_once('=')
+                        .map(|(property, value)| (property.to_lowercase(), value.to_string()))
+                })
+                .collect();
+            Some(AuthenticationResult {
+                method,
+                result,
+                properties,
+            })
+        })
+        .collect();
+    Some((authserv_id, results))
+}
+
+// Splits a header value into statements separated by semicolons, each made of words separated by whitespace. Comments
+// are dropped, quoted strings are unquoted, and whitespace around `=` is removed so that `dkim = pass` is one word.
+fn tokenize(value: &str) -> Vec<Vec<String>> {
+    let mut statements = vec![Vec::new()];
+    let mut word = String::new();
+    let mut comment_depth = 0_usize;
+    let mut quoted = false;
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' if quoted || comment_depth > 0 => {
+                if let Some(escaped) = chars.next().filter(|_| quoted) {
+                    word.push(escaped);
+                }
+            }
+            '"' if comment_depth == 0 => quoted = !quoted,
+            _ if quoted => word.push(char),
+            '(' => comment_depth += 1,
+            ')' => comment_depth = comment_depth.saturating_sub(1),
+            _ if comment_depth > 0 => {}
+            ';' => {
+                push_word(&mut statements, &mut word);
+                statements.push(Vec::new());
+            }
+            _ if char.is_whitespace() => push_word(&mut statements, &mut word),
+            _ => word.push(char),
+        }
+    }
+    push_word(&mut statements, &mut word);
+    statements
+        .into_iter()
+        .map(|words| {
+            words.into_iter().fold(Vec::new(), |mut merged: Vec<String>, word| {
+                match merged.last_mut() {
+                    Some(last) if last.ends_with('=') || word.starts_with('=') => last.push_str(&word),
+                    _ => merged.push(word),
+                }
+                merged
+            })
+        })
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+fn push_word(statements: &mut [Vec<String>], word: &mut String) {
+    if !word.is_empty() {
+        if let Some(words) = statements.last_mut() {
+            words.push(std::mem::take(word));
+        }
+    }
+}
+
+// Whether the sender passed the authentication required by the configuration, according to the
+// `Authentication-Results` headers added by the trusted receiving server, which are those above the first `Received:`
+// header. Headers with another authserv-id may have been added by anyone, so they are ignored.
+pub(crate) fn is_sender_authenticated(message_descriptors: &MessageMetadata, config: &AppConfig) -> bool {
+    if config.require_authentication == RequiredAuthentication::None {
+        return true;
+    }
+    let Some(trusted_authserv_id) = &config.authserv_id else {
+        return false;
+    };
+    let Some((_, from_domain)) = message_descriptors.from().rsplit_once('@') else {
+        return false;
+    };
+    let from_domain = from_domain.to_lowercase();
+    let results = message_descriptors
+        .authentication_results()
+        .iter()
+        .filter_map(|value| parse_authentication_results(value))
+        .filter(|(authserv_id, _)| authserv_id.eq_ignore_ascii_case(trusted_authserv_id))
+        .flat_map(|(_, results)| results)
+        .collect::<Vec<AuthenticationResult>>();
+    let dkim = || results.iter().any(|result| is_aligned_dkim_pass(result, &from_domain));
+    let dmarc = || results.iter().any(|result| is_aligned_dmarc_pass(result, &from_domain));
+    match config.require_authentication {
+        RequiredAuthentication::None => true,
+        RequiredAuthentication::Dkim => dkim(),
+        RequiredAuthentication::Dmarc => dmarc(),
+        RequiredAuthentication::Any => dkim() || dmarc(),
+    }
+}
+
+fn is_aligned_dkim_pass(result: &AuthenticationResult, from_domain: &str) -> bool {
+    let signing_domain = result.property("header.d").or_else(|| {
+        result
+            .property("header.i")
+            .and_then(|identity| identity.rsplit_once('@').map(|(_, domain)| domain))
+    });
+    result.passed("dkim") && signing_domain.is_some_and(|signing_domain| is_aligned_domain(signing_domain, from_domain))
+}
+
+// A DKIM signing domain is aligned when it shares the organizational domain of the From domain, approximated here by
+// one domain being the other or one of its subdomains
+pub(crate) fn is_aligned_domain(signing_domain: &str, from_domain: &str) -> bool {
+    let signing_domain = signing_domain.to_lowercase();
+    let from_domain = from_domain.to_lowercase();
+    // A top-level domain cannot be a signing domain
+    signing_domain.contains('.')
+        && (signing_domain == from_domain
+            || from_domain.ends_with(&format!(".{signing_domain}"))
+            || signing_domain.ends_with(&format!(".{from_domain}")))
+}
+
+// DMARC already checks the alignment with the From domain, so only the evaluated domain is checked, when present
+fn is_aligned_dmarc_pass(result: &AuthenticationResult, from_domain: &str) -> bool {
+    result.passed("dmarc")
+        && result
+            .property("header.from")
+            .is_none_or(|domain| domain.eq_ignore_ascii_case(from_domain))
+}
+
+#[cfg(test)]
+#[path = "test_sender_authentication.rs"]
+mod test_sender_authentication;
@@ -0,0 +1,148 @@
+use openssl::cms::CmsContentInfo;
+use openssl::nid::Nid;
+use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::{X509Ref, X509};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::ImapAttachmentDaemonError;
+
+// S/MIME messages, see <https://www.rfc-editor.org/rfc/rfc8551>, decrypted and verified by OpenSSL.
+//
+// Most S/MIME clients still encrypt the content key with RSA PKCS#1 v1.5, whose padding checks leak through timing
+// (the Marvin attack, RUSTSEC-2023-0071). OpenSSL makes a single attempt over every recipient, decrypts with a random
+// key when none matches and, since OpenSSL 3.2, replaces invalid paddings with a pseudorandom key, so that a failure
+// cannot be told from a wrong key. Senders should prefer RSAES-OAEP, which is supported as well.
+
+// The content of a CMS message, either decrypted or signed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SmimeContent {
+    Decrypted(Vec<u8>),
+    // The signed content, with the email addresses of the trusted signers whose signatures are valid
+    Signed { content: Vec<u8>, signers: Vec<String> },
+}
+
+// Reads a private key from a PEM block, either PKCS#8, encrypted PKCS#8 or PKCS#1. A missing passphrase is given as
+// an empty one, so that OpenSSL never prompts for it.
+pub(crate) fn read_private_key(
+    pem: &[u8],
+    passphrase: Option<&SecretString>,
+) -> Result<PKey<Private>, ImapAttachmentDaemonError> {
+    let passphrase = passphrase.map_or("", |passphrase| passphrase.expose_secret());
+    PKey::private_key_from_pem_passphrase(pem, passphrase.as_bytes())
+        .map_err(|_| invalid("invalid private key or wrong passphrase"))
+}
+
+// Reads the PEM certificates of trusted signers, skipping the other blocks of the file such as OpenPGP public keys
+pub(crate) fn read_certificates(pem: &[u8]) -> Result<Vec<X509>, ImapAttachmentDaemonError> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    let text = String::from_utf8_lossy(pem);
+    text.match_indices(BEGIN)
+        .map(|(start, _)| {
+            let end = text[start..].find(END).ok_or(invalid("invalid certificate"))?;
+            X509::from_pem(text[start..start + end + END.len()].as_bytes()).map_err(|_| invalid("invalid certificate"))
+        })
+        .collect()
+}
+
+// The email addresses a certificate was issued for, from its subject alternative names and subject
+fn certificate_emails(certificate: &X509Ref) -> Vec<String> {
+    let mut emails = certificate
+        .subject_alt_names()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.email().map(str::to_lowercase))
+        .collect::<Vec<String>>();
+    emails.extend(
+        certificate
+            .subject_name()
+            .entries_by_nid(Nid::PKCS9_EMAILADDRESS)
+            .filter_map(|entry| entry.data().as_utf8().ok())
+            .map(|email| email.to_lowercase()),
+    );
+    emails.dedup();
+    emails
+}
+
+// Opens a CMS message, decrypting enveloped data with the private key, or returning the content of signed data with
+// the signers found among the trusted certificates
+pub(crate) fn open_message(
+    message: &[u8],
+    key: Option<&PKey<Private>>,
+    certificates: &[X509],
+) -> Result<SmimeContent, ImapAttachmentDaemonError> {
+    // Only signed data can be read as PKCS #7, the other CMS content types being enveloped data for S/MIME emails
+    let signed = Pkcs7::from_der(message).ok().filter(|pkcs7| pkcs7.signed().is_some());
+    let Some(signed) = signed else {
+        let key = key.ok_or(invalid("no private key to decrypt the message"))?;
+        return CmsContentInfo::from_der(message)
+            .and_then(|enveloped| enveloped.decrypt_without_cert_check(key))
+            .map(SmimeContent::Decrypted)
+            .map_err(|_| invalid("cannot decrypt the message"));
+    };
+    // The content is read without checking the signature, which is then checked against the trusted certificates
+    let mut content = Vec::new();
+    let no_certificates = Stack::new().map_err(|_| invalid("invalid signed data"))?;
+    let no_store = X509StoreBuilder::new()
+        .map_err(|_| invalid("invalid signed data"))?
+        .build();
+    signed
+        .verify(
+            &no_certificates,
+            &no_store,
+            None,
+            Some(&mut content),
+            Pkcs7Flags::NOVERIFY | Pkcs7Flags::NOSIGS | Pkcs7Flags::BINARY,
+        )
+        .map_err(|_| invalid("missing signed content"))?;
+    let signers = trusted_signers(&signed, None, certificates);
+    Ok(SmimeContent::Signed { content, signers })
+}
+
+// Verifies a detached signature of the data, returning the email addresses of the trusted signers whose signature is
+// valid
+pub(crate) fn verify_detached_signature(
+    data: &[u8],
+    signature: &[u8],
+    certificates: &[X509],
+) -> Result<Vec<String>, ImapAttachmentDaemonError> {
+    let signature = Pkcs7::from_der(signature)
+        .ok()
+        .filter(|pkcs7| pkcs7.signed().is_some())
+        .ok_or(invalid("invalid signature"))?;
+    Ok(trusted_signers(&signature, Some(data), certificates))
+}
+
+// The signers of signed data that are trusted certificates, when every signature is valid. The certificates are only
+// looked up among the trusted ones, and are trusted themselves rather than through the authority that issued them.
+fn trusted_signers(signed: &Pkcs7, data: Option<&[u8]>, certificates: &[X509]) -> Vec<String> {
+    let trusted = || -> Result<Vec<String>, openssl::error::ErrorStack> {
+        let mut stack = Stack::new()?;
+        let mut store = X509StoreBuilder::new()?;
+        store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+        for certificate in certificates {
+            stack.push(certificate.clone())?;
+            store.add_cert(certificate.clone())?;
+        }
+        let flags = Pkcs7Flags::NOINTERN | Pkcs7Flags::BINARY;
+        signed.verify(&stack, &store.build(), data, None, flags)?;
+        Ok(signed
+            .signers(&stack, flags)?
+            .iter()
+            .flat_map(certificate_emails)
+            .collect())
+    };
+    trusted().unwrap_or_default()
+}
+
+fn invalid(reason: &'static str) -> ImapAttachmentDaemonError {
+    ImapAttachmentDaemonError::SmimeError(reason)
+}
+
+#[cfg(test)]
+#[path = "test_smime.rs"]
+pub(crate) mod test_smime;
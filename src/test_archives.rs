@@ -0,0 +1,182 @@
+mod archives_tests {
+    use super::super::{archive_format, extract_archive, ArchiveLimits, ExtractedFile};
+    use crate::models::ArchiveFormat;
+    use crate::ImapAttachmentDaemonError;
+    use flate2::write::GzEncoder;
+    use std::collections::BTreeSet;
+    use std::io::{Cursor, Write};
+    use std::path::Path;
+    use zip::write::SimpleFileOptions;
+
+    const LIMITS: ArchiveLimits = ArchiveLimits {
+        entries: 10,
+        total_size: 16 * 1024,
+        compression_ratio: 100,
+        depth: 2,
+    };
+
+    fn all_formats() -> BTreeSet<ArchiveFormat> {
+        BTreeSet::from([ArchiveFormat::Zip, ArchiveFormat::Tar, ArchiveFormat::TarGz])
+    }
+
+    fn is_book(name: &str) -> bool {
+        Path::new(name)
+            .extension()
+            .is_some_and(|extension| extension == "epub" || extension == "pdf")
+    }
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn paths(files: &[ExtractedFile]) -> Vec<&str> {
+        files.iter().map(|file| file.path.as_str()).collect()
+    }
+
+    // Archives are recognised from their filename, only when their format is unpacked
+    #[test]
+    fn test_archive_format() {
+        let formats = BTreeSet::from([ArchiveFormat::Zip, ArchiveFormat::TarGz]);
+
+        assert_eq!(archive_format("Books.ZIP", &formats), Some(ArchiveFormat::Zip));
+        assert_eq!(archive_format("books.tar.gz", &formats), Some(ArchiveFormat::TarGz));
+        assert_eq!(archive_format("books.tgz", &formats), Some(ArchiveFormat::TarGz));
+        assert_eq!(archive_format("books.tar", &formats), None);
+        assert_eq!(archive_format("comic.cbz", &formats), None);
+        assert_eq!(archive_format("book.epub", &formats), None);
+    }
+
+    // Only the accepted files are extracted, from every format
+    #[test]
+    fn test_extract_accepted_files() {
+        let entries: [(&str, &[u8]); 3] = [
+            ("books/dune.epub", b"dune"),
+            ("readme.txt", b"read me"),
+            ("notes.pdf", b"notes"),
+        ];
+        let expected = vec![
+            ExtractedFile {
+                path: "books/dune.epub".to_string(),
+                content: b"dune".to_vec(),
+            },
+            ExtractedFile {
+                path: "notes.pdf".to_string(),
+                content: b"notes".to_vec(),
+            },
+        ];
+
+        for (content, format) in [
+            (zip(&entries), ArchiveFormat::Zip),
+            (tar(&entries), ArchiveFormat::Tar),
+            (gzip(&tar(&entries)), ArchiveFormat::TarGz),
+        ] {
+            let files = extract_archive(&content, format, &all_formats(), &is_book, LIMITS).unwrap();
+            assert_eq!(files, expected, "{format:?}");
+        }
+    }
+
+    // Nested archives are unpacked up to the maximum depth
+    #[test]
+    fn test_extract_nested_archives() {
+        let inner = zip(&[("inner.epub", b"inner")]);
+        let middle = tar(&[("inner.zip", &inner), ("middle.epub", b"middle")]);
+        let outer = zip(&[("middle.tar", &middle), ("outer.pdf", b"outer")]);
+
+        let files = extract_archive(&outer, ArchiveFormat::Zip, &all_formats(), &is_book, LIMITS).unwrap();
+        assert_eq!(paths(&files), ["middle.tar/middle.epub", "outer.pdf"]);
+
+        let limits = ArchiveLimits { depth: 3, ..LIMITS };
+        let files = extract_archive(&outer, ArchiveFormat::Zip, &all_formats(), &is_book, limits).unwrap();
+        assert_eq!(
+            paths(&files),
+            ["middle.tar/inner.zip/inner.epub", "middle.tar/middle.epub", "outer.pdf"]
+        );
+
+        // Archives in a format that is not unpacked are ordinary entries
+        let formats = BTreeSet::from([ArchiveFormat::Zip]);
+        let files = extract_archive(&outer, ArchiveFormat::Zip, &formats, &is_book, limits).unwrap();
+        assert_eq!(paths(&files), ["outer.pdf"]);
+    }
+
+    // Archives exceeding the entry count, total size or compression ratio are rejected
+    #[test]
+    fn test_extract_limits() {
+        let limit = |result: Result<Vec<ExtractedFile>, ImapAttachmentDaemonError>| match result {
+            Err(ImapAttachmentDaemonError::ArchiveLimitExceeded(limit)) => limit,
+            other => panic!("expected a limit to be exceeded, got {other:?}"),
+        };
+        let many = (0..11).map(|index| format!("{index}.txt")).collect::<Vec<_>>();
+        let many = many.iter().map(|name| (name.as_str(), &b""[..])).collect::<Vec<_>>();
+        let large = vec![b'a'; 32 * 1024];
+        let bomb = vec![0; 64 * 1024];
+
+        assert_eq!(
+            limit(extract_archive(
+                &zip(&many),
+                ArchiveFormat::Zip,
+                &all_formats(),
+                &is_book,
+                LIMITS
+            )),
+            "entry count"
+        );
+        assert_eq!(
+            limit(extract_archive(
+                &tar(&[("large.epub", &large)]),
+                ArchiveFormat::Tar,
+                &all_formats(),
+                &is_book,
+                LIMITS
+            )),
+            "total size"
+        );
+        let limits = ArchiveLimits {
+            total_size: u64::MAX,
+            ..LIMITS
+        };
+        assert_eq!(
+            limit(extract_archive(
+                &zip(&[("bomb.epub", &bomb)]),
+                ArchiveFormat::Zip,
+                &all_formats(),
+                &is_book,
+                limits
+            )),
+            "compression ratio"
+        );
+        // Skipped entries of compressed tar archives are decompressed too
+        assert_eq!(
+            limit(extract_archive(
+                &gzip(&tar(&[("bomb.bin", &bomb)])),
+                ArchiveFormat::TarGz,
+                &all_formats(),
+                &is_book,
+                limits
+            )),
+            "compression ratio"
+        );
+    }
+}
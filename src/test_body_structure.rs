@@ -0,0 +1,207 @@
+mod body_structure_tests {
+    use std::collections::BTreeSet;
+
+    use mail_parser::MimeHeaders;
+
+    use super::super::{decode_filename, FetchedParts, FetchedStructure, StructurePart};
+    use crate::mail_parsing::parse_message;
+    use crate::models::{ArchiveFormat, EmbeddedEncoding};
+    use crate::AppConfig;
+
+    fn part(section: u32, mime_type: &str, filename: Option<&str>) -> StructurePart {
+        StructurePart {
+            section: vec![section],
+            mime_type: mime_type.to_string(),
+            filename: filename.map(str::to_string),
+            undecoded_filename: false,
+            is_attachment: filename.is_some(),
+            encoding: Some("base64".to_string()),
+            octets: 1000,
+        }
+    }
+
+    // Parts are selected from their filename, MIME type and size, and the body text only when files embedded or linked
+    // in it are saved
+    #[test]
+    fn test_candidate_sections() {
+        let structure = FetchedStructure {
+            uid: 1,
+            multipart: true,
+            parts: vec![
+                part(1, "text/plain", None),
+                part(2, "application/octet-stream", Some("book.epub")),
+                part(3, "application/octet-stream", Some("setup.exe")),
+                part(4, "application/pdf", None),
+                part(5, "application/octet-stream", Some("attachment")),
+                part(6, "message/rfc822", None),
+                part(7, "application/zip", Some("books.zip")),
+                StructurePart {
+                    octets: 100_000,
+                    ..part(8, "application/epub+zip", Some("large.epub"))
+                },
+            ],
+        };
+        let config = AppConfig {
+            accepted_file_types: BTreeSet::from(["epub".to_string(), "pdf".to_string()]),
+            max_attachment_size: Some(10_000),
+            ..Default::default()
+        };
+
+        assert_eq!(structure.candidate_sections(&config).unwrap(), [[2], [4], [5]]);
+
+        let config = AppConfig {
+            max_nesting_depth: 1,
+            extract_archives: BTreeSet::from([ArchiveFormat::Zip]),
+            max_attachment_size: None,
+            ..config
+        };
+        assert_eq!(
+            structure.candidate_sections(&config).unwrap(),
+            [[2], [4], [5], [6], [7], [8]]
+        );
+
+        let config = AppConfig {
+            embedded_encodings: BTreeSet::from([EmbeddedEncoding::Uuencode]),
+            ..config
+        };
+        assert_eq!(
+            structure.candidate_sections(&config).unwrap(),
+            [[1], [2], [4], [5], [6], [7], [8]]
+        );
+
+        let structure = FetchedStructure {
+            parts: vec![part(1, "text/plain", None), part(2, "text/html", None)],
+            ..structure
+        };
+        let config = AppConfig {
+            embedded_encodings: BTreeSet::new(),
+            link_hosts: BTreeSet::from(["cloud.example.com".to_string()]),
+            ..config
+        };
+        assert_eq!(structure.candidate_sections(&config).unwrap(), [[1], [2]]);
+    }
+
+    // PGP/MIME encrypted emails and emails holding S/MIME encrypted parts need to be downloaded in full
+    #[test]
+    fn test_is_encrypted() {
+        let structure = |parts| FetchedStructure {
+            uid: 1,
+            multipart: true,
+            parts,
+        };
+
+        assert!(structure(vec![
+            part(1, "application/pgp-encrypted", None),
+            part(2, "application/octet-stream", None),
+        ])
+        .is_encrypted());
+        assert!(structure(vec![part(1, "application/pkcs7-mime", Some("smime.p7m"))]).is_encrypted());
+        assert!(!structure(vec![
+            part(1, "text/plain", None),
+            part(2, "application/pgp-signature", Some("signature.asc")),
+        ])
+        .is_encrypted());
+    }
+
+    // Filenames are decoded from RFC 2047 encoded words and RFC 2231 extended parameters, preferring the
+    // `Content-Disposition` filename, and are returned as is when they cannot be decoded
+    #[test]
+    fn test_decode_filename() {
+        assert_eq!(
+            decode_filename(&[("filename", "=?UTF-8?Q?Caf=C3=A9.epub?=")], &[]),
+            Ok(Some("Café.epub".to_string()))
+        );
+        assert_eq!(
+            decode_filename(&[], &[("name", "=?UTF-8?B?Q2Fmw6kuZXB1Yg==?=")]),
+            Ok(Some("Café.epub".to_string()))
+        );
+        assert_eq!(
+            decode_filename(&[("filename*", "UTF-8''Caf%C3%A9.epub")], &[("name", "other.pdf")]),
+            Ok(Some("Café.epub".to_string()))
+        );
+        assert_eq!(
+            decode_filename(&[("filename*0*", "UTF-8''Caf%C3%A9"), ("filename*1", ".epub")], &[]),
+            Ok(Some("Café.epub".to_string()))
+        );
+        assert_eq!(
+            decode_filename(&[("filename", "book.epub")], &[("charset", "us-ascii")]),
+            Ok(Some("book.epub".to_string()))
+        );
+        assert_eq!(
+            decode_filename(&[("size", "100")], &[("charset", "us-ascii")]),
+            Ok(None)
+        );
+        assert_eq!(
+            decode_filename(&[("filename", "=?UTF-8?X?book.pdf?=")], &[]),
+            Err("=?UTF-8?X?book.pdf?=".to_string())
+        );
+    }
+
+    // Parts with a decoded filename are judged from its extension, and parts whose filename could not be decoded are
+    // always selected
+    #[test]
+    fn test_candidate_sections_with_encoded_filenames() {
+        let filename = decode_filename(&[("filename", "=?UTF-8?Q?Caf=C3=A9.epub?=")], &[]).unwrap();
+        let structure = FetchedStructure {
+            uid: 1,
+            multipart: true,
+            parts: vec![
+                StructurePart {
+                    filename,
+                    ..part(1, "application/octet-stream", None)
+                },
+                StructurePart {
+                    undecoded_filename: true,
+                    ..part(2, "application/octet-stream", Some("=?UTF-8?X?setup.exe?="))
+                },
+                part(3, "application/octet-stream", Some("setup.exe")),
+            ],
+        };
+        let config = AppConfig {
+            accepted_file_types: BTreeSet::from(["epub".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(structure.candidate_sections(&config).unwrap(), [[1], [2]]);
+    }
+
+    // Selected parts are assembled into an email with the original header, where they are attachments
+    #[test]
+    fn test_into_email() {
+        let parts = FetchedParts {
+            uid: 7,
+            header: concat!(
+                "From: reader@example.com\r\n",
+                "Subject: Books\r\n",
+                "Content-Type: multipart/mixed;\r\n",
+                "\tboundary=\"original\"\r\n",
+                "MIME-Version: 1.0\r\n",
+                "\r\n",
+            )
+            .as_bytes()
+            .to_vec(),
+            parts: vec![(
+                concat!(
+                    "Content-Type: application/epub+zip\r\n",
+                    "Content-Disposition: attachment; filename=\"book.epub\"\r\n",
+                    "Content-Transfer-Encoding: base64\r\n",
+                    "\r\n",
+                )
+                .as_bytes()
+                .to_vec(),
+                b"Ym9vaw==".to_vec(),
+            )],
+        };
+
+        let email = parts.into_email(4096);
+        let message = parse_message(&email.raw).unwrap();
+
+        assert_eq!((email.uid, email.size), (7, 4096));
+        assert_eq!(message.subject(), Some("Books"));
+        assert!(!String::from_utf8_lossy(&email.raw).contains("original"));
+        let attachments = message.attachments().collect::<Vec<_>>();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].attachment_name(), Some("book.epub"));
+        assert_eq!(attachments[0].contents(), b"book");
+    }
+}
@@ -0,0 +1,82 @@
+mod content_type_tests {
+    use super::super::{detect_file_type, infer_extension, verify_content_type};
+    use crate::ImapAttachmentDaemonError;
+
+    // A minimal EPUB, starting with its uncompressed `mimetype` entry
+    fn epub() -> Vec<u8> {
+        let mut content = b"PK\x03\x04".to_vec();
+        content.resize(30, 0);
+        content.extend_from_slice(b"mimetypeapplication/epub+zip");
+        content
+    }
+
+    // File types are detected from their magic bytes, at their offset
+    #[test]
+    fn test_detect_file_type() {
+        let name = |content: &[u8]| detect_file_type(content).map(|file_type| file_type.name);
+
+        assert_eq!(name(&epub()), Some("EPUB"));
+        assert_eq!(name(b"PK\x03\x04rest of a comic book"), Some("ZIP"));
+        assert_eq!(name(b"%PDF-1.7"), Some("PDF"));
+        assert_eq!(name(&[&[0; 60][..], b"BOOKMOBI"].concat()), Some("Mobipocket"));
+        assert_eq!(name(b"MZ\x90\x00"), Some("Windows executable"));
+        assert_eq!(name(b"<?xml version=\"1.0\"?><FictionBook>"), None);
+        assert_eq!(name(b""), None);
+    }
+
+    // Declared extensions and MIME types must match the detected file type
+    #[test]
+    fn test_verify_content_type() {
+        assert!(verify_content_type(&epub(), Some("epub"), Some("application/epub+zip")).is_ok());
+        assert!(verify_content_type(&epub(), None, Some("application/octet-stream")).is_ok());
+        assert!(verify_content_type(b"PK\x03\x04", Some("cbz"), Some("application/x-cbz")).is_ok());
+        assert!(verify_content_type(b"%PDF-1.7", Some("pdf"), None).is_ok());
+        // Unknown content is not verified
+        assert!(verify_content_type(b"<html></html>", Some("pdf"), Some("application/pdf")).is_ok());
+
+        let mismatch = verify_content_type(b"MZ\x90\x00", Some("pdf"), Some("application/pdf"));
+        assert!(matches!(
+            mismatch,
+            Err(ImapAttachmentDaemonError::ContentTypeMismatch { declared, detected })
+                if declared == ".pdf" && detected == "Windows executable"
+        ));
+        let mismatch = verify_content_type(b"%PDF-1.7", None, Some("application/epub+zip"));
+        assert!(matches!(
+            mismatch,
+            Err(ImapAttachmentDaemonError::ContentTypeMismatch { declared, .. }) if declared == "application/epub+zip"
+        ));
+    }
+
+    // Extensions are inferred from specific MIME types first, then from the content
+    #[test]
+    fn test_infer_extension() {
+        let mut comic = b"PK\x03\x04".to_vec();
+        comic.resize(26, 0);
+        comic.extend_from_slice(&[8, 0, 0, 0]);
+        comic.extend_from_slice(b"001.JPEG");
+
+        assert_eq!(
+            infer_extension(b"", Some("application/x-mobipocket-ebook")),
+            Some("mobi")
+        );
+        assert_eq!(
+            infer_extension(b"%PDF-1.7", Some("application/vnd.comicbook+zip")),
+            Some("cbz")
+        );
+        assert_eq!(infer_extension(&epub(), Some("application/octet-stream")), Some("epub"));
+        assert_eq!(infer_extension(&comic, None), Some("cbz"));
+        assert_eq!(infer_extension(b"PK\x03\x04", None), Some("zip"));
+        assert_eq!(infer_extension(b"%PDF-1.7", None), Some("pdf"));
+        assert_eq!(
+            infer_extension(&[&[0; 60][..], b"BOOKMOBI"].concat(), None),
+            Some("mobi")
+        );
+        assert_eq!(
+            infer_extension(&[&[0; 60][..], b"TEXtREAd"].concat(), None),
+            Some("pdb")
+        );
+        assert_eq!(infer_extension(b"AT&TFORM", None), Some("djvu"));
+        assert_eq!(infer_extension(b"Rar!\x1a\x07\x00", None), Some("cbr"));
+        assert_eq!(infer_extension(b"plain text", Some("application/octet-stream")), None);
+    }
+}
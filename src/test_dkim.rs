@@ -0,0 +1,249 @@
+mod dkim_tests {
+    use std::fs;
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use rsa::pkcs8::EncodePublicKey;
+    use rsa::rand_core::OsRng;
+    use rsa::RsaPrivateKey;
+
+    use super::super::{verify_dkim, DkimFailure, DkimKeyResolver, StaticKeyResolver};
+    use crate::local_sources::local_source_processing;
+    use crate::AppConfig;
+
+    // Signed with the RSA key below, with the relaxed canonicalization
+    const RSA_SIGNED_EMAIL: &str = concat!(
+        "DKIM-Signature: v=1; a=rsa-sha256; s=rsa; d=example.com; c=relaxed/relaxed;\r\n",
+        "\th=Content-Type:MIME-Version:Subject:To:From; t=1792366240; bh=Xbg8hT0X/wjTC\r\n",
+        "\t4zfXYEYPP/5geZAyMSJc94vrr90AWA=; b=f12xACjjAJRhLZfAiWMA38pVK+Fxo94z7YQVqbCO\r\n",
+        "\tVyH3kfwMvfZOsFc6L/2K0jbJPqtqHHlNd1q21QriNpPpv2f4qrCO8m+CWleYgJrg7Z4we5skjT9\r\n",
+        "\tq3z2nmPHoruZ5kZ3lxPKJdtCbhue5GlNNFxJiqzLnYqsz5GbkiLg2pgUTfoiIpRSCK81bM5vG6F\r\n",
+        "\tkfwI/0Q34tM9XMm5U/o5PkfzwE0rlpwW/uN2IlbyjtiirtolA1ubB4l0wvDPjugq7G+fzU9pgD7\r\n",
+        "\tP3vrTb+y9QRa+MjeK+gpxhznHI64xZVjZjEutdynvNr9svUXNelwe2Il0UDkxmURLIT5Hwc81we\r\n",
+        "\tYg==;\r\n",
+        "From: Trusted Sender <trusted@example.com>\r\n",
+        "To: target@example.com\r\n",
+        "Subject: A book\r\n",
+        "MIME-Version: 1.0\r\n",
+        "Content-Type: multipart/mixed; boundary=\"boundary\"\r\n",
+        "\r\n",
+        "--boundary\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "Here is a book.  \r\n",
+        "--boundary\r\n",
+        "Content-Type: application/epub+zip\r\n",
+        "Content-Disposition: attachment; filename=\"book.epub\"\r\n",
+        "Content-Transfer-Encoding: base64\r\n",
+        "\r\n",
+        "Ym9vaw==\r\n",
+        "--boundary--\r\n",
+        "\r\n",
+    );
+
+    // Signed with the Ed25519 key below for a subdomain of the sender domain, with the simple canonicalization
+    const ED25519_SIGNED_EMAIL: &str = concat!(
+        "DKIM-Signature: v=1; a=ed25519-sha256; s=ed; d=mail.example.com; c=simple/simple;\r\n",
+        "\th=Subject:To:From; t=1792366240; bh=yZQq1c8wjBl0fZ4Wc/oraMCAG1mZJv5v/hlvyFy\r\n",
+        "\t+t6A=; b=slpa8pAfRMVBx0e1g44HguUPGILJRnw+lSeRltr05Nl4bdW83BOf38cHLUNf1olaVc\r\n",
+        "\tlwSDMLrx73n/TYTHzRBw==;\r\n",
+        "From: trusted@example.com\r\n",
+        "To: target@example.com\r\n",
+        "Subject: A note\r\n",
+        "\r\n",
+        "Hello.\r\n",
+    );
+
+    const DKIM_KEYS: &str = concat!(
+        "# Test keys\n",
+        "rsa._domainkey.example.com v=DKIM1; k=rsa; p=MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAxu3F3lwr3ZziY+6rXzOHnNdPxlG",
+        "agmGaFteBOJzs14kZD953P87uNah0iDmSYT5T63X8924LJss175n7GDRDHm1f3Z9saql0uVOzTZE++N+SlJ6cf6eN5vhBv6Sygw8dyzOTjuuS/4gup",
+        "bh59AAi/q638c/EGvgc+fol3EaPW3k4l1MW7mayiKWGk9s65+rYG5aNhcL6T0CwP4ONcSmDpTiZe3KCS7pwlRmWG7THK7oaVdVM6vUDSmtRHBZUefx",
+        "0qOYfraJh/O6hsFxEhbObhZKFl/9yj4rYxGiWmq6o4939pChpgRqVJ7/ETyaLxzNyLG41ZtlyJtONd8IHlZYL9wIDAQAB\n",
+        "\n",
+        "ed._domainkey.mail.example.com. v=DKIM1; k=ed25519; p=JdewU2d/D/OVjtVgrORlk45ty4ofYvQLzyoJVV9MP9o=\n",
+    );
+
+    fn resolver() -> StaticKeyResolver {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = dir.path().join("dkim-keys.txt");
+        fs::write(&keys_file, DKIM_KEYS).unwrap();
+        StaticKeyResolver::from_file(&keys_file).unwrap()
+    }
+
+    // Load the keys of a DKIM keys file, ignoring comments and the trailing dot of domain names
+    #[test]
+    fn test_static_key_resolver() {
+        let resolver = resolver();
+
+        assert_eq!(resolver.txt_records("rsa._domainkey.example.com").unwrap().len(), 1);
+        assert_eq!(
+            resolver.txt_records("ED._domainkey.mail.example.com").unwrap(),
+            vec!["v=DKIM1; k=ed25519; p=JdewU2d/D/OVjtVgrORlk45ty4ofYvQLzyoJVV9MP9o=".to_string()]
+        );
+        assert!(resolver.txt_records("other._domainkey.example.com").unwrap().is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = dir.path().join("dkim-keys.txt");
+        fs::write(&keys_file, "# Comment\nrsa._domainkey.example.com\n").unwrap();
+        assert!(StaticKeyResolver::from_file(&keys_file).is_err());
+    }
+
+    // Verify RSA signatures with the relaxed canonicalization, which tolerates whitespace changes
+    #[test]
+    fn test_verify_rsa_relaxed_signature() {
+        let resolver = resolver();
+
+        assert_eq!(
+            verify_dkim(RSA_SIGNED_EMAIL.as_bytes(), "example.com", &resolver),
+            Ok(())
+        );
+        let rewrapped = RSA_SIGNED_EMAIL
+            .replace("Subject: A book\r\n", "subject:  A\r\n   book \r\n")
+            .replace("Here is a book.  \r\n", "Here  is a\tbook.\r\n")
+            + "\r\n\r\n";
+        assert_eq!(verify_dkim(rewrapped.as_bytes(), "EXAMPLE.com", &resolver), Ok(()));
+    }
+
+    // Verify Ed25519 signatures with the simple canonicalization, from a subdomain of the sender domain
+    #[test]
+    fn test_verify_ed25519_simple_signature() {
+        let resolver = resolver();
+
+        assert_eq!(
+            verify_dkim(ED25519_SIGNED_EMAIL.as_bytes(), "example.com", &resolver),
+            Ok(())
+        );
+        let rewrapped = ED25519_SIGNED_EMAIL.replace("Subject: A note", "Subject:  A note");
+        assert_eq!(
+            verify_dkim(rewrapped.as_bytes(), "example.com", &resolver),
+            Err(DkimFailure::Invalid("Signature verification failed".to_string()))
+        );
+    }
+
+    // Emails stored with bare line feeds are verified as if they had CRLF line endings
+    #[test]
+    fn test_verify_signature_with_bare_line_feeds() {
+        let email = ED25519_SIGNED_EMAIL.replace("\r\n", "\n");
+
+        assert_eq!(verify_dkim(email.as_bytes(), "example.com", &resolver()), Ok(()));
+    }
+
+    // Changes to the signed headers or to the body invalidate the signature
+    #[test]
+    fn test_verify_tampered_email() {
+        let resolver = resolver();
+
+        let forged_subject = RSA_SIGNED_EMAIL.replace("Subject: A book", "Subject: A virus");
+        assert_eq!(
+            verify_dkim(forged_subject.as_bytes(), "example.com", &resolver),
+            Err(DkimFailure::Invalid("Signature verification failed".to_string()))
+        );
+        let forged_attachment = RSA_SIGNED_EMAIL.replace("Ym9vaw==", "dmlydXM=");
+        assert_eq!(
+            verify_dkim(forged_attachment.as_bytes(), "example.com", &resolver),
+            Err(DkimFailure::Invalid(
+                "Calculated body hash does not match signature hash".to_string()
+            ))
+        );
+    }
+
+    // Emails without signature, with a signature from another domain or without public key are not verified
+    #[test]
+    fn test_verify_missing_or_unaligned_signature() {
+        let resolver = resolver();
+        let unsigned = ED25519_SIGNED_EMAIL
+            .split_once("From:")
+            .map(|(_, rest)| format!("From:{rest}"))
+            .unwrap();
+
+        assert_eq!(
+            verify_dkim(unsigned.as_bytes(), "example.com", &resolver),
+            Err(DkimFailure::Unsigned)
+        );
+        assert_eq!(
+            verify_dkim(RSA_SIGNED_EMAIL.as_bytes(), "attacker.org", &resolver),
+            Err(DkimFailure::Unaligned)
+        );
+        assert_eq!(
+            verify_dkim(
+                RSA_SIGNED_EMAIL.as_bytes(),
+                "example.com",
+                &StaticKeyResolver::default()
+            ),
+            Err(DkimFailure::Invalid(
+                "DNS resolution error: no public key found at rsa._domainkey.example.com".to_string()
+            ))
+        );
+    }
+
+    // SHA-1 signatures, partial body signatures, RSA keys under 1024 bits and emails with several From header fields
+    // are rejected
+    #[test]
+    fn test_verify_weak_or_ambiguous_signature() {
+        let sha1_signed = RSA_SIGNED_EMAIL.replace("a=rsa-sha256", "a=rsa-sha1");
+        assert_eq!(
+            verify_dkim(sha1_signed.as_bytes(), "example.com", &resolver()),
+            Err(DkimFailure::Invalid("rsa-sha1 signatures are not accepted".to_string()))
+        );
+
+        let partially_signed = RSA_SIGNED_EMAIL.replace("c=relaxed/relaxed;", "c=relaxed/relaxed; l=10;");
+        assert_eq!(
+            verify_dkim(partially_signed.as_bytes(), "example.com", &resolver()),
+            Err(DkimFailure::Invalid("Insecure 'l=' tag found in Signature".to_string()))
+        );
+
+        let weak_key = RsaPrivateKey::new(&mut OsRng, 512).unwrap();
+        let weak_key = STANDARD.encode(weak_key.to_public_key().to_public_key_der().unwrap());
+        let weak_resolver = StaticKeyResolver(BTreeMap::from([(
+            "rsa._domainkey.example.com".to_string(),
+            vec![format!("v=DKIM1; k=rsa; p={weak_key}")],
+        )]));
+        assert_eq!(
+            verify_dkim(RSA_SIGNED_EMAIL.as_bytes(), "example.com", &weak_resolver),
+            Err(DkimFailure::Invalid(
+                "Cryptography layer error: RSA key of 512 bits, under the minimum of 1024 bits".to_string()
+            ))
+        );
+
+        let second_from = RSA_SIGNED_EMAIL.replace("To: target", "From: attacker@example.org\r\nTo: target");
+        assert_eq!(
+            verify_dkim(second_from.as_bytes(), "example.com", &resolver()),
+            Err(DkimFailure::Invalid("several From header fields".to_string()))
+        );
+    }
+
+    // Emails failing the DKIM verification are skipped when it is enabled, like emails from other senders
+    #[test]
+    fn test_dkim_verification_of_processed_emails() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_file = dir.path().join("dkim-keys.txt");
+        let emails_dir = dir.path().join("emails");
+        let attachments_dir = dir.path().join("attachments");
+        fs::write(&keys_file, DKIM_KEYS).unwrap();
+        fs::create_dir_all(&emails_dir).unwrap();
+        fs::create_dir_all(&attachments_dir).unwrap();
+        fs::write(emails_dir.join("signed.eml"), RSA_SIGNED_EMAIL).unwrap();
+        fs::write(
+            emails_dir.join("forged.eml"),
+            RSA_SIGNED_EMAIL.replace("Ym9vaw==", "dmlydXM="),
+        )
+        .unwrap();
+        let config = AppConfig {
+            whitelist: BTreeSet::from_iter(["trusted@example.com".to_string()]),
+            target_address: Some("target@example.com".to_string()),
+            attachments_dir: attachments_dir.to_string_lossy().to_string(),
+            accepted_file_types: BTreeSet::from_iter(["epub".to_string()]),
+            verify_dkim: true,
+            dkim_keys_file: Some(keys_file),
+            ..Default::default()
+        };
+
+        let summary = local_source_processing(&emails_dir, &config).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(fs::read(attachments_dir.join("book.epub")).unwrap(), b"book");
+    }
+}
@@ -0,0 +1,109 @@
+mod embedded_files_tests {
+    use super::super::decode_embedded_files;
+    use crate::archives::ExtractedFile;
+    use crate::models::EmbeddedEncoding;
+    use std::collections::BTreeSet;
+
+    fn all_encodings() -> BTreeSet<EmbeddedEncoding> {
+        BTreeSet::from([EmbeddedEncoding::Uuencode, EmbeddedEncoding::Yenc])
+    }
+
+    // A uuencoded block with lines of 45 bytes, using `` ` `` for zero
+    fn uuencode(name: &str, content: &[u8]) -> String {
+        let encode = |sextet: u8| if sextet == 0 { '`' } else { char::from(sextet + b' ') };
+        let mut block = format!("begin 644 {name}\r\n");
+        for line in content.chunks(45) {
+            block.push(encode(u8::try_from(line.len()).unwrap()));
+            for group in line.chunks(3) {
+                let bytes = [group[0], *group.get(1).unwrap_or(&0), *group.get(2).unwrap_or(&0)];
+                block.extend([
+                    encode(bytes[0] >> 2),
+                    encode(((bytes[0] << 4) | (bytes[1] >> 4)) & 0x3F),
+                    encode(((bytes[1] << 2) | (bytes[2] >> 6)) & 0x3F),
+                    encode(bytes[2] & 0x3F),
+                ]);
+            }
+            block.push_str("\r\n");
+        }
+        block.push_str("`\r\nend\r\n");
+        block
+    }
+
+    // A single-part yEnc block, escaping the critical characters
+    fn yenc(name: &str, content: &[u8], crc32: Option<u32>) -> Vec<u8> {
+        let mut block = format!("=ybegin line=128 size={} name={name}\r\n", content.len()).into_bytes();
+        for &byte in content {
+            let encoded = byte.wrapping_add(42);
+            if matches!(encoded, 0 | b'\n' | b'\r' | b'=') {
+                block.extend_from_slice(&[b'=', encoded.wrapping_add(64)]);
+            } else {
+                block.push(encoded);
+            }
+        }
+        let mut crc = flate2::Crc::new();
+        crc.update(content);
+        block.extend_from_slice(
+            format!(
+                "\r\n=yend size={} crc32={:08x}\r\n",
+                content.len(),
+                crc32.unwrap_or(crc.sum())
+            )
+            .as_bytes(),
+        );
+        block
+    }
+
+    fn paths(files: &[ExtractedFile]) -> Vec<&str> {
+        files.iter().map(|file| file.path.as_str()).collect()
+    }
+
+    // Uuencoded blocks are decoded wherever they are in the text
+    #[test]
+    fn test_decode_uuencode() {
+        let content = (0..=255).collect::<Vec<u8>>();
+        let text = format!(
+            "Hello,\r\n\r\n{}\r\nand a short one:\r\n{}\r\nbegin here.\r\n",
+            uuencode("The Dark Forest.epub", &content),
+            uuencode("notes.txt", b"notes")
+        );
+
+        let files = decode_embedded_files(text.as_bytes(), &all_encodings());
+
+        assert_eq!(paths(&files), ["The Dark Forest.epub", "notes.txt"]);
+        assert_eq!(files[0].content, content);
+        assert_eq!(files[1].content, b"notes");
+    }
+
+    // yEnc blocks are decoded from their 8-bit bytes, and checked against their declared size and CRC32
+    #[test]
+    fn test_decode_yenc() {
+        let content = (0..=255).rev().collect::<Vec<u8>>();
+        let mut text = b"Hello,\r\n".to_vec();
+        text.extend_from_slice(&yenc("dune.epub", &content, None));
+        text.extend_from_slice(&yenc("corrupted.epub", b"book", Some(0x1234_5678)));
+
+        let files = decode_embedded_files(&text, &all_encodings());
+
+        assert_eq!(paths(&files), ["dune.epub"]);
+        assert_eq!(files[0].content, content);
+    }
+
+    // Blocks are only decoded when their encoding is enabled, and unfinished or multipart blocks are skipped
+    #[test]
+    fn test_decode_skips_disabled_and_incomplete_blocks() {
+        let mut text = uuencode("book.epub", b"book").into_bytes();
+        text.extend_from_slice(&yenc("dune.epub", b"dune", None));
+
+        let uuencode_only = BTreeSet::from([EmbeddedEncoding::Uuencode]);
+        assert_eq!(paths(&decode_embedded_files(&text, &uuencode_only)), ["book.epub"]);
+        let yenc_only = BTreeSet::from([EmbeddedEncoding::Yenc]);
+        assert_eq!(paths(&decode_embedded_files(&text, &yenc_only)), ["dune.epub"]);
+        assert!(decode_embedded_files(&text, &BTreeSet::new()).is_empty());
+
+        let unfinished = uuencode("book.epub", b"book").replace("end\r\n", "");
+        assert!(decode_embedded_files(unfinished.as_bytes(), &all_encodings()).is_empty());
+        let mut multipart = b"=ybegin part=1 total=2 ".to_vec();
+        multipart.extend_from_slice(&yenc("dune.epub", b"dune", None)["=ybegin ".len()..]);
+        assert!(decode_embedded_files(&multipart, &all_encodings()).is_empty());
+    }
+}
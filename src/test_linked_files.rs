@@ -0,0 +1,247 @@
+pub(crate) mod linked_files_tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use tiny_http::{Header, Request, Response, Server};
+    use url::Url;
+
+    use super::super::{body_links, download_linked_file, extract_links, LinkLimits};
+    use crate::local_sources::local_source_processing;
+    use crate::mail_parsing::parse_message;
+    use crate::models::LinkSchemes;
+    use crate::AppConfig;
+
+    const LIMITS: LinkLimits = LinkLimits {
+        size: 1000,
+        timeout: Duration::from_secs(1),
+        schemes: LinkSchemes::Any,
+    };
+
+    fn hosts(hosts: &[&str]) -> BTreeSet<String> {
+        hosts.iter().map(ToString::to_string).collect()
+    }
+
+    fn urls(links: &[Url]) -> Vec<&str> {
+        links.iter().map(Url::as_str).collect()
+    }
+
+    fn respond(request: Request, port: u16) {
+        let header = |name: &str, value: &str| Header::from_bytes(name, value).unwrap();
+        let response = match request.url() {
+            "/books/dune.epub" => {
+                Response::from_data("dune").with_header(header("Content-Type", "application/epub+zip"))
+            }
+            "/s/AbC123/download" => Response::from_data("hobbit")
+                .with_header(header("Content-Type", "application/octet-stream"))
+                .with_header(header(
+                    "Content-Disposition",
+                    "attachment; filename=\"hobbit.epub\"; filename*=UTF-8''The%20Hobbit.epub",
+                )),
+            "/s/AbC123" => Response::from_data("<html>Password</html>")
+                .with_header(header("Content-Type", "text/html; charset=utf-8")),
+            "/large.epub" => Response::from_data(vec![b'a'; 2000]),
+            "/redirect" => Response::from_data("")
+                .with_status_code(302)
+                .with_header(header("Location", "/books/dune.epub")),
+            "/away" => {
+                let location = format!("http://localhost:{port}/books/dune.epub");
+                Response::from_data("")
+                    .with_status_code(302)
+                    .with_header(header("Location", &location))
+            }
+            "/slow.epub" => {
+                thread::sleep(Duration::from_secs(3));
+                Response::from_data("slow")
+            }
+            "/slow-redirect" | "/slow-redirect-2" => {
+                thread::sleep(Duration::from_millis(600));
+                let location = if request.url() == "/slow-redirect" {
+                    "/slow-redirect-2"
+                } else {
+                    "/books/dune.epub"
+                };
+                Response::from_data("")
+                    .with_status_code(302)
+                    .with_header(header("Location", location))
+            }
+            _ => Response::from_data("").with_status_code(404),
+        };
+        let _ = request.respond(response);
+    }
+
+    // A local HTTP server answering each request in its own thread, returning its base URL
+    pub(crate) fn start_server() -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let _ = thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let _ = thread::spawn(move || respond(request, port));
+            }
+        });
+        format!("http://127.0.0.1:{port}")
+    }
+
+    // Links are found in plain text and HTML, without trailing punctuation, and only for the allowed hosts
+    #[test]
+    fn test_extract_links() {
+        let text = concat!(
+            "Here is the book: https://cloud.example.com/s/AbC123.\n",
+            "Also at <https://files.example.org/dune.epub>, or HTTP://cloud.example.com/s/AbC123 again.\n",
+            "<a href=\"https://cloud.example.com/index.php?share=x&amp;path=%2F\">the folder</a>\n",
+            "Not https://example.com.evil.net/book.epub, https://cloud.example.com@evil.net/book.epub ",
+            "nor ftp://cloud.example.com/book.epub\n",
+        );
+
+        assert_eq!(
+            urls(&extract_links(text, &hosts(&["cloud.example.com", "*.example.org"]))),
+            [
+                "https://cloud.example.com/s/AbC123",
+                "https://files.example.org/dune.epub",
+                "http://cloud.example.com/s/AbC123",
+                "https://cloud.example.com/index.php?share=x&path=%2F",
+            ]
+        );
+        assert_eq!(
+            urls(&extract_links(text, &hosts(&["Files.Example.org"]))),
+            ["https://files.example.org/dune.epub"]
+        );
+        assert!(extract_links(text, &hosts(&["example.com", "*.cloud.example.com"])).is_empty());
+        assert!(extract_links(text, &BTreeSet::new()).is_empty());
+    }
+
+    // Links are taken from the plain text and HTML bodies, but not from text attachments
+    #[test]
+    fn test_body_links() {
+        let email = concat!(
+            "From: reader@example.com\r\n",
+            "Content-Type: multipart/mixed; boundary=\"mixed\"\r\n",
+            "\r\n",
+            "--mixed\r\n",
+            "Content-Type: multipart/alternative; boundary=\"alternative\"\r\n",
+            "\r\n",
+            "--alternative\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "https://cloud.example.com/s/AbC123\r\n",
+            "--alternative\r\n",
+            "Content-Type: text/html\r\n",
+            "\r\n",
+            "<a href=\"https://cloud.example.com/s/AbC123\">Dune</a> <a href=\"https://cloud.example.com/s/XyZ\">Emma</a>\r\n",
+            "--alternative--\r\n",
+            "--mixed\r\n",
+            "Content-Type: text/plain; name=\"links.txt\"\r\n",
+            "Content-Disposition: attachment; filename=\"links.txt\"\r\n",
+            "\r\n",
+            "https://cloud.example.com/s/attached\r\n",
+            "--mixed--\r\n",
+        );
+        let message = parse_message(email.as_bytes()).unwrap();
+
+        assert_eq!(
+            urls(&body_links(&message, &hosts(&["cloud.example.com"]))),
+            ["https://cloud.example.com/s/AbC123", "https://cloud.example.com/s/XyZ"]
+        );
+    }
+
+    // Files are downloaded with their filename and MIME type, following redirects on the allowed hosts, while web
+    // pages, files over the size limit and slow downloads are rejected
+    #[test]
+    fn test_download_linked_file() {
+        let server = start_server();
+        let allowed = hosts(&["127.0.0.1"]);
+        let download =
+            |path: &str| download_linked_file(&Url::parse(&(server.clone() + path)).unwrap(), &allowed, LIMITS);
+
+        let dune = download("/books/dune.epub").unwrap();
+        assert_eq!(dune.content, b"dune");
+        assert_eq!(dune.filename.as_deref(), Some("dune.epub"));
+        assert_eq!(dune.mime_type.as_deref(), Some("application/epub+zip"));
+
+        let hobbit = download("/s/AbC123/download").unwrap();
+        assert_eq!(hobbit.content, b"hobbit");
+        assert_eq!(hobbit.filename.as_deref(), Some("The Hobbit.epub"));
+
+        assert_eq!(download("/redirect").unwrap().content, b"dune");
+
+        // Each redirect is within the timeout, but not the whole download
+        for path in [
+            "/s/AbC123",
+            "/large.epub",
+            "/away",
+            "/slow.epub",
+            "/slow-redirect",
+            "/missing.epub",
+        ] {
+            assert!(download(path).is_err(), "{path}");
+        }
+
+        let patient = LinkLimits {
+            timeout: Duration::from_secs(3),
+            ..LIMITS
+        };
+        let url = Url::parse(&(server.clone() + "/slow-redirect")).unwrap();
+        assert_eq!(download_linked_file(&url, &allowed, patient).unwrap().content, b"dune");
+
+        let https_only = LinkLimits {
+            schemes: LinkSchemes::Https,
+            ..LIMITS
+        };
+        let url = Url::parse(&(server.clone() + "/books/dune.epub")).unwrap();
+        assert!(download_linked_file(&url, &allowed, https_only).is_err());
+    }
+
+    // The linked files of emails are saved as attachments, and emails without any saved linked file are left unread
+    #[test]
+    fn test_processing_of_linked_files() {
+        let server = start_server();
+        let dir = tempfile::tempdir().unwrap();
+        let emails_dir = dir.path().join("emails");
+        let attachments_dir = dir.path().join("attachments");
+        fs::create_dir_all(&emails_dir).unwrap();
+        fs::create_dir_all(&attachments_dir).unwrap();
+        let email = |body: &str| {
+            format!(
+                concat!(
+                    "From: reader@example.com\r\n",
+                    "To: target@example.com\r\n",
+                    "Subject: Books\r\n",
+                    "Content-Type: text/html\r\n",
+                    "\r\n",
+                    "{}\r\n",
+                ),
+                body
+            )
+        };
+        fs::write(
+            emails_dir.join("linked.eml"),
+            email(&format!(
+                "<a href=\"{server}/books/dune.epub\">Dune</a> and <a href=\"{server}/s/AbC123/download\">The Hobbit</a>"
+            )),
+        )
+        .unwrap();
+        fs::write(
+            emails_dir.join("share.eml"),
+            email(&format!("<a href=\"{server}/s/AbC123\">The share</a>")),
+        )
+        .unwrap();
+        let config = AppConfig {
+            whitelist: BTreeSet::from(["reader@example.com".to_string()]),
+            target_address: Some("target@example.com".to_string()),
+            attachments_dir: attachments_dir.to_string_lossy().to_string(),
+            accepted_file_types: BTreeSet::from(["epub".to_string()]),
+            link_hosts: hosts(&["127.0.0.1"]),
+            link_max_size: LIMITS.size,
+            link_timeout: LIMITS.timeout.as_secs(),
+            link_schemes: LinkSchemes::Any,
+            ..Default::default()
+        };
+
+        let summary = local_source_processing(&emails_dir, &config).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(fs::read(attachments_dir.join("dune.epub")).unwrap(), b"dune");
+        assert_eq!(fs::read(attachments_dir.join("the hobbit.epub")).unwrap(), b"hobbit");
+    }
+}
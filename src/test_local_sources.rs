@@ -0,0 +1,193 @@
+mod local_sources_tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+
+    use super::super::{local_source_processing, processed_maildir_filename, LocalSource};
+    use crate::AppConfig;
+
+    const EMAIL_WITH_ATTACHMENT: &str = concat!(
+        "From: trusted@example.com\r\n",
+        "To: target@example.com\r\n",
+        "Subject: A book\r\n",
+        "MIME-Version: 1.0\r\n",
+        "Content-Type: multipart/mixed; boundary=\"boundary\"\r\n",
+        "\r\n",
+        "--boundary\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "Here is a book.\r\n",
+        "--boundary\r\n",
+        "Content-Type: application/epub+zip\r\n",
+        "Content-Disposition: attachment; filename=\"book.epub\"\r\n",
+        "Content-Transfer-Encoding: base64\r\n",
+        "\r\n",
+        "Ym9vaw==\r\n",
+        "--boundary--\r\n",
+    );
+
+    const EMAIL_FROM_STRANGER: &str = concat!(
+        "From: stranger@example.com\r\n",
+        "To: target@example.com\r\n",
+        "Subject: Not a book\r\n",
+        "\r\n",
+        "Hello.\r\n",
+    );
+
+    fn config(attachments_dir: &std::path::Path) -> AppConfig {
+        AppConfig {
+            whitelist: BTreeSet::from_iter(["trusted@example.com".to_string()]),
+            target_address: Some("target@example.com".to_string()),
+            attachments_dir: attachments_dir.to_string_lossy().to_string(),
+            accepted_file_types: BTreeSet::from_iter(["epub".to_string()]),
+            ..Default::default()
+        }
+    }
+
+    // Detect the kind of local source from the filesystem layout
+    #[test]
+    fn test_detect_local_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let maildir = dir.path().join("maildir");
+        fs::create_dir_all(maildir.join("cur")).unwrap();
+        fs::create_dir_all(maildir.join("new")).unwrap();
+        let eml_file = dir.path().join("message.EML");
+        fs::write(&eml_file, EMAIL_FROM_STRANGER).unwrap();
+        let mbox_file = dir.path().join("inbox");
+        fs::write(&mbox_file, EMAIL_FROM_STRANGER).unwrap();
+
+        assert_eq!(
+            LocalSource::detect(&maildir).unwrap(),
+            LocalSource::Maildir(maildir.clone())
+        );
+        assert_eq!(
+            LocalSource::detect(dir.path()).unwrap(),
+            LocalSource::EmlDirectory(dir.path().to_path_buf())
+        );
+        assert_eq!(
+            LocalSource::detect(&eml_file).unwrap(),
+            LocalSource::EmlFile(eml_file.clone())
+        );
+        assert_eq!(
+            LocalSource::detect(&mbox_file).unwrap(),
+            LocalSource::Mbox(mbox_file.clone())
+        );
+        assert!(LocalSource::detect(&dir.path().join("missing")).is_err());
+    }
+
+    // Processed Maildir filenames keep their existing flags, sorted, with the seen and trashed flags added
+    #[test]
+    fn test_processed_maildir_filename() {
+        assert_eq!(
+            processed_maildir_filename("1700000000.M1P1.host"),
+            "1700000000.M1P1.host:2,ST"
+        );
+        assert_eq!(
+            processed_maildir_filename("1700000000.M1P1.host:2,F"),
+            "1700000000.M1P1.host:2,FST"
+        );
+        assert_eq!(
+            processed_maildir_filename("1700000000.M1P1.host:2,S"),
+            "1700000000.M1P1.host:2,ST"
+        );
+    }
+
+    // Whitelisted emails in a Maildir are processed and moved to `cur/`, other emails are left untouched
+    #[test]
+    fn test_maildir_processing() {
+        let dir = tempfile::tempdir().unwrap();
+        let maildir = dir.path().join("maildir");
+        let attachments_dir = dir.path().join("attachments");
+        for subdirectory in ["cur", "new", "tmp"] {
+            fs::create_dir_all(maildir.join(subdirectory)).unwrap();
+        }
+        fs::create_dir_all(&attachments_dir).unwrap();
+        fs::write(maildir.join("new").join("1.host"), EMAIL_WITH_ATTACHMENT).unwrap();
+        fs::write(maildir.join("new").join("2.host"), EMAIL_FROM_STRANGER).unwrap();
+        fs::write(maildir.join("cur").join("3.host:2,S"), EMAIL_WITH_ATTACHMENT).unwrap();
+
+        let summary = local_source_processing(&maildir, &config(&attachments_dir)).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(fs::read(attachments_dir.join("book.epub")).unwrap(), b"book");
+        assert!(maildir.join("cur").join("1.host:2,ST").is_file());
+        assert!(maildir.join("new").join("2.host").is_file());
+        assert!(maildir.join("cur").join("3.host:2,S").is_file());
+    }
+
+    // A dry run over a Maildir neither saves attachments nor moves emails
+    #[test]
+    fn test_maildir_processing_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let maildir = dir.path().join("maildir");
+        let attachments_dir = dir.path().join("attachments");
+        for subdirectory in ["cur", "new", "tmp"] {
+            fs::create_dir_all(maildir.join(subdirectory)).unwrap();
+        }
+        fs::write(maildir.join("new").join("1.host"), EMAIL_WITH_ATTACHMENT).unwrap();
+
+        let config = AppConfig {
+            dry_run: true,
+            ..config(&attachments_dir)
+        };
+        let summary = local_source_processing(&maildir, &config).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert!(!attachments_dir.exists());
+        assert!(maildir.join("new").join("1.host").is_file());
+    }
+
+    // All emails of an mbox file are processed, and the file is left untouched
+    #[test]
+    fn test_mbox_processing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mbox_file = dir.path().join("inbox");
+        let attachments_dir = dir.path().join("attachments");
+        fs::create_dir_all(&attachments_dir).unwrap();
+        let mbox = format!(
+            "From trusted@example.com Mon Jan  1 00:00:00 2024\n{EMAIL_WITH_ATTACHMENT}\nFrom stranger@example.com Mon Jan  1 00:00:00 2024\n{EMAIL_FROM_STRANGER}\n"
+        );
+        fs::write(&mbox_file, &mbox).unwrap();
+
+        let summary = local_source_processing(&mbox_file, &config(&attachments_dir)).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(fs::read(attachments_dir.join("book.epub")).unwrap(), b"book");
+        assert_eq!(fs::read_to_string(&mbox_file).unwrap(), mbox);
+    }
+
+    // Only `.eml` files of a directory are processed
+    #[test]
+    fn test_eml_directory_processing() {
+        let dir = tempfile::tempdir().unwrap();
+        let emails_dir = dir.path().join("emails");
+        let attachments_dir = dir.path().join("attachments");
+        fs::create_dir_all(&emails_dir).unwrap();
+        fs::create_dir_all(&attachments_dir).unwrap();
+        fs::write(emails_dir.join("book.eml"), EMAIL_WITH_ATTACHMENT).unwrap();
+        fs::write(emails_dir.join("notes.txt"), EMAIL_WITH_ATTACHMENT).unwrap();
+
+        let summary = local_source_processing(&emails_dir, &config(&attachments_dir)).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert_eq!(fs::read(attachments_dir.join("book.epub")).unwrap(), b"book");
+    }
+
+    // Emails from blocked senders are skipped, even when the sender is whitelisted
+    #[test]
+    fn test_blocked_emails_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let email_file = dir.path().join("book.eml");
+        let attachments_dir = dir.path().join("attachments");
+        fs::create_dir_all(&attachments_dir).unwrap();
+        fs::write(&email_file, EMAIL_WITH_ATTACHMENT).unwrap();
+
+        let config = AppConfig {
+            blocklist: BTreeSet::from_iter(["@example.com".to_string()]),
+            ..config(&attachments_dir)
+        };
+        let summary = local_source_processing(&email_file, &config).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (0, 1, 0));
+        assert!(!attachments_dir.join("book.epub").exists());
+    }
+}
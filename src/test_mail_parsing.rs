@@ -0,0 +1,94 @@
+mod target_destination_tests {
+    use super::super::{extract_descriptors, parse_message, target_destination, TargetDestination};
+    use crate::AppConfig;
+
+    fn config(target_address: &str) -> AppConfig {
+        AppConfig {
+            target_address: Some(target_address.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn destination(headers: &str, target_address: &str) -> Option<(String, Option<String>)> {
+        let raw = format!("From: trusted@example.com\r\n{headers}Subject: Test\r\n\r\nHello.\r\n");
+        let message = parse_message(raw.as_bytes()).unwrap();
+        let metadata = extract_descriptors(&message).unwrap();
+        target_destination(&metadata, &config(target_address))
+            .map(|TargetDestination { address, tag }| (address.to_string(), tag.map(ToString::to_string)))
+    }
+
+    // Collect the destinations of the To, Cc and delivery headers, without requiring a To header
+    #[test]
+    fn test_extract_destinations() {
+        let raw = concat!(
+            "Delivered-To: <list@example.com>\r\n",
+            "X-Original-To: alias@example.com\r\n",
+            "Envelope-To: first@example.com, Second <second@example.com>\r\n",
+            "From: trusted@example.com\r\n",
+            "To: undisclosed-recipients:;\r\n",
+            "Cc: Carol <carol@example.com>, dave@example.com\r\n",
+            "\r\n",
+            "Hello.\r\n",
+        );
+        let message = parse_message(raw.as_bytes()).unwrap();
+        let metadata = extract_descriptors(&message).unwrap();
+
+        assert_eq!(
+            metadata.destinations(),
+            [
+                "carol@example.com",
+                "dave@example.com",
+                "list@example.com",
+                "alias@example.com",
+                "first@example.com",
+                "second@example.com"
+            ]
+        );
+    }
+
+    // Match the target address in any destination header, ignoring case
+    #[test]
+    fn test_target_destination_headers() {
+        for headers in [
+            "To: Target <Target@Example.com>\r\n",
+            "To: other@example.com\r\nCc: target@example.com\r\n",
+            "Delivered-To: target@example.com\r\n",
+            "X-Original-To: target@example.com\r\nTo: list@example.com\r\n",
+            "Envelope-To: target@example.com\r\n",
+        ] {
+            assert!(destination(headers, "target@example.com").is_some(), "{headers}");
+        }
+        assert_eq!(destination("To: other@example.com\r\n", "target@example.com"), None);
+        assert_eq!(destination("", "target@example.com"), None);
+    }
+
+    // Match sub-addresses of the target address, exposing their tag
+    #[test]
+    fn test_target_destination_sub_address() {
+        assert_eq!(
+            destination("To: Target+Books@example.com\r\n", "target@example.com"),
+            Some(("Target+Books@example.com".to_string(), Some("Books".to_string())))
+        );
+        assert_eq!(
+            destination("To: target+@example.com\r\n", "target@example.com"),
+            Some(("target+@example.com".to_string(), Some(String::new())))
+        );
+        assert_eq!(
+            destination("To: target+books@example.org\r\n", "target@example.com"),
+            None
+        );
+        assert_eq!(
+            destination("To: other+target@example.com\r\n", "target@example.com"),
+            None
+        );
+        // A target address with a tag only matches exactly
+        assert_eq!(
+            destination("To: target+books@example.com\r\n", "target+books@example.com"),
+            Some(("target+books@example.com".to_string(), None))
+        );
+        assert_eq!(
+            destination("To: target+comics@example.com\r\n", "target+books@example.com"),
+            None
+        );
+    }
+}
@@ -2,8 +2,33 @@ mod search_criteria_tests {
     use std::collections::BTreeSet;
 
     use super::super::generate_search_criteria;
+    use crate::models::BlockedAction;
     use crate::AppConfig;
 
+    // Search keys matching a target address without tag in the destination and delivery headers
+    fn target_criteria(target: &str) -> String {
+        let (user, _) = target.rsplit_once('@').unwrap();
+        format!(
+            concat!(
+                r#"(OR OR OR OR OR OR OR OR OR TO "{0}" CC "{0}" HEADER Delivered-To "{0}" HEADER X-Original-To "{0}" "#,
+                r#"HEADER Envelope-To "{0}" TO "{1}+" CC "{1}+" HEADER Delivered-To "{1}+" HEADER X-Original-To "{1}+" "#,
+                r#"HEADER Envelope-To "{1}+")"#
+            ),
+            target, user
+        )
+    }
+
+    // Search keys matching a target address with a tag, which has no sub-addresses
+    fn tagged_target_criteria(target: &str) -> String {
+        format!(
+            concat!(
+                r#"(OR OR OR OR TO "{0}" CC "{0}" HEADER Delivered-To "{0}" HEADER X-Original-To "{0}" "#,
+                r#"HEADER Envelope-To "{0}")"#
+            ),
+            target
+        )
+    }
+
     // Generate search criteria with single whitelisted address and target address
     #[test]
     fn test_generate_search_criteria_single_whitelist_with_target() {
@@ -16,7 +41,13 @@ mod search_criteria_tests {
 
         let criteria = generate_search_criteria(&config);
 
-        assert_eq!(criteria, r#"UNSEEN TO "target@example.com" ( FROM "test@example.com")"#);
+        assert_eq!(
+            criteria,
+            format!(
+                r#"UNSEEN {} ( FROM "test@example.com")"#,
+                target_criteria("target@example.com")
+            )
+        );
     }
 
     // Generate search criteria with empty whitelist and no target address filter
@@ -31,7 +62,7 @@ mod search_criteria_tests {
 
         let criteria = generate_search_criteria(&config);
 
-        assert_eq!(criteria, r#"UNSEEN TO "user@example.com" ( )"#);
+        assert_eq!(criteria, format!("UNSEEN {} ( )", target_criteria("user@example.com")));
     }
 
     // Generate search criteria with multiple whitelisted addresses and target address
@@ -48,7 +79,10 @@ mod search_criteria_tests {
 
         assert_eq!(
             criteria,
-            r#"UNSEEN TO "target@example.com" (OR FROM "test1@example.com" FROM "test2@example.com")"#
+            format!(
+                r#"UNSEEN {} (OR FROM "test1@example.com" FROM "test2@example.com")"#,
+                target_criteria("target@example.com")
+            )
         );
     }
 
@@ -64,7 +98,13 @@ mod search_criteria_tests {
 
         let criteria = generate_search_criteria(&config);
 
-        assert_eq!(criteria, r#"UNSEEN TO "user@example.com" ( FROM "test@example.com")"#);
+        assert_eq!(
+            criteria,
+            format!(
+                r#"UNSEEN {} ( FROM "test@example.com")"#,
+                target_criteria("user@example.com")
+            )
+        );
     }
 
     // Generate search criteria with target address containing special characters
@@ -81,7 +121,10 @@ mod search_criteria_tests {
 
         assert_eq!(
             criteria,
-            r#"UNSEEN TO "target+special@example.com" ( FROM "test@example.com")"#
+            format!(
+                r#"UNSEEN {} ( FROM "test@example.com")"#,
+                tagged_target_criteria("target+special@example.com")
+            )
         );
     }
 
@@ -101,7 +144,10 @@ mod search_criteria_tests {
 
         assert_eq!(
             criteria,
-            format!(r#"UNSEEN TO "target@example.com" (OR FROM "{long_address_a}" FROM "{long_address_b}")"#,)
+            format!(
+                r#"UNSEEN {} (OR FROM "{long_address_a}" FROM "{long_address_b}")"#,
+                target_criteria("target@example.com")
+            )
         );
     }
 
@@ -120,7 +166,10 @@ mod search_criteria_tests {
 
         assert_eq!(
             criteria,
-            r#"UNSEEN TO "tárget@exámple.com" (OR FROM "tést@exámple.com" FROM "üser@domäin.com")"#
+            format!(
+                r#"UNSEEN {} (OR FROM "tést@exámple.com" FROM "üser@domäin.com")"#,
+                target_criteria("tárget@exámple.com")
+            )
         );
     }
 
@@ -145,9 +194,1140 @@ mod search_criteria_tests {
 
         assert_eq!(
             criteria,
+            format!(
+                r#"UNSEEN {} (OR OR OR OR OR OR FROM "test+filter@example.com" FROM "user-name@example.com" FROM "user.name@example.com" FROM "user@123.123.123.123" FROM "user@[IPv6:2001:db8::1]" FROM "user@sub.example.com" FROM "user_name@example.com")"#,
+                tagged_target_criteria("target+special@example.com")
+            )
+        );
+    }
+
+    // Generate search criteria with domain, subdomain and glob whitelist entries
+    #[test]
+    fn test_generate_search_criteria_with_patterns() {
+        let config = AppConfig {
+            whitelist: BTreeSet::from_iter([
+                "@Example.com".to_string(),
+                "*.example.org".to_string(),
+                "books-*@example.net".to_string(),
+            ]),
+            target_address: Some("target@example.com".to_string()),
+            username: "user@example.com".to_string(),
+            ..Default::default()
+        };
+
+        let criteria = generate_search_criteria(&config);
+
+        assert_eq!(
+            criteria,
+            format!(
+                r#"UNSEEN {} (OR OR FROM ".example.org" FROM "@Example.com" FROM "@example.net")"#,
+                target_criteria("target@example.com")
+            )
+        );
+    }
+
+    // Generate search criteria without sender filter when a whitelist entry is a regex
+    #[test]
+    fn test_generate_search_criteria_with_regex() {
+        let config = AppConfig {
+            whitelist: BTreeSet::from_iter([
+                "test@example.com".to_string(),
+                r"/^books-\d+@example\.com$/".to_string(),
+            ]),
+            target_address: Some("target@example.com".to_string()),
+            username: "user@example.com".to_string(),
+            ..Default::default()
+        };
+
+        let criteria = generate_search_criteria(&config);
+
+        assert_eq!(criteria, format!("UNSEEN {}", target_criteria("target@example.com")));
+    }
+
+    // Generate search criteria including the blocklist only when blocked emails are moved or deleted
+    #[test]
+    fn test_generate_search_criteria_with_blocklist() {
+        let config = AppConfig {
+            whitelist: BTreeSet::from_iter(["@example.com".to_string()]),
+            blocklist: BTreeSet::from_iter(["spam@example.org".to_string()]),
+            target_address: Some("target@example.com".to_string()),
+            username: "user@example.com".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            generate_search_criteria(&config),
+            format!(
+                r#"UNSEEN {} ( FROM "@example.com")"#,
+                target_criteria("target@example.com")
+            )
+        );
+
+        let config = AppConfig {
+            blocked_action: BlockedAction::Junk,
+            ..config
+        };
+
+        assert_eq!(
+            generate_search_criteria(&config),
+            format!(
+                r#"UNSEEN {} (OR FROM "@example.com" FROM "spam@example.org")"#,
+                target_criteria("target@example.com")
+            )
+        );
+    }
+}
+
+mod email_processing_tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    use super::super::{body_batches, idle_update_email_search, startup_email_search};
+    use crate::linked_files::test_linked_files::linked_files_tests::start_server;
+    use crate::mail_store::{EmailFlag, MailStore};
+    use crate::memory_store::MemoryStore;
+    use crate::models::{
+        ArchiveFormat, BlockedAction, BodyDownload, EmbeddedEncoding, ExistingFiles, InlineParts, LinkSchemes,
+        OversizedAction, RequiredAuthentication, Rules, SubjectCommand,
+    };
+    use crate::AppConfig;
+
+    fn email(from: &str, to: &str, attachment_name: Option<&str>) -> String {
+        let mut email = format!(
+            concat!(
+                "From: {}\r\n",
+                "To: {}\r\n",
+                "Subject: Test\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: multipart/mixed; boundary=\"boundary\"\r\n",
+                "\r\n",
+                "--boundary\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+                "Hello.\r\n",
+            ),
+            from, to
+        );
+        if let Some(attachment_name) = attachment_name {
+            email.push_str(&format!(
+                concat!(
+                    "--boundary\r\n",
+                    "Content-Type: application/octet-stream\r\n",
+                    "Content-Disposition: attachment; filename=\"{}\"\r\n",
+                    "Content-Transfer-Encoding: base64\r\n",
+                    "\r\n",
+                    "Ym9vaw==\r\n",
+                ),
+                attachment_name
+            ));
+        }
+        email.push_str("--boundary--\r\n");
+        email
+    }
+
+    fn config(attachments_dir: &Path) -> AppConfig {
+        AppConfig {
+            whitelist: BTreeSet::from_iter(["trusted@example.com".to_string()]),
+            target_address: Some("target@example.com".to_string()),
+            username: "user@example.com".to_string(),
+            attachments_dir: attachments_dir.to_string_lossy().to_string(),
+            accepted_file_types: BTreeSet::from_iter(["epub".to_string()]),
+            ..Default::default()
+        }
+    }
+
+    fn inbox_uids(store: &MemoryStore) -> Vec<u32> {
+        store.mailbox("INBOX").iter().map(|email| email.uid).collect()
+    }
+
+    // Whitelisted emails with accepted attachments are saved, marked as read and moved to the trash at startup
+    #[test]
+    fn test_startup_search_processes_whitelisted_emails() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let book = store.deliver_old(email("trusted@example.com", "target@example.com", Some("Book.epub")));
+        let stranger = store.deliver_old(email("stranger@example.com", "target@example.com", Some("other.epub")));
+        let unsupported = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.exe")));
+        let elsewhere = store.deliver_old(email("trusted@example.com", "other@example.com", Some("other.epub")));
+
+        let summary = startup_email_search(&config(attachments_dir.path()), &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("book.epub")).unwrap(), b"book");
+        assert!(!attachments_dir.path().join("other.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![stranger, unsupported, elsewhere]);
+        let trashed = store.mailbox("Trash");
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].uid, book);
+        assert!(trashed[0].flags.contains(&EmailFlag::Seen));
+        assert!(store.email(unsupported).unwrap().flags.is_empty());
+    }
+
+    // Whitelist patterns are applied to the IMAP search results, ignoring case
+    #[test]
+    fn test_startup_search_applies_whitelist_patterns() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = AppConfig {
+            whitelist: BTreeSet::from_iter(["@Trusted.example.com".to_string(), "*.friends.org".to_string()]),
+            ..config(attachments_dir.path())
+        };
+        let mut store = MemoryStore::default();
+        let _ = store.deliver_old(email(
+            "Alice@TRUSTED.example.com",
+            "target@example.com",
+            Some("alice.epub"),
+        ));
+        let _ = store.deliver_old(email("bob@mail.friends.org", "target@example.com", Some("bob.epub")));
+        let stranger = store.deliver_old(email(
+            "eve@untrusted.example.com",
+            "target@example.com",
+            Some("eve.epub"),
+        ));
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 2);
+        assert!(attachments_dir.path().join("alice.epub").is_file());
+        assert!(attachments_dir.path().join("bob.epub").is_file());
+        assert_eq!(inbox_uids(&store), vec![stranger]);
+    }
+
+    // Emails already read are not processed at startup
+    #[test]
+    fn test_startup_search_ignores_seen_emails() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let uid = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.epub")));
+        store.add_flag(uid, EmailFlag::Seen).unwrap();
+
+        let summary = startup_email_search(&config(attachments_dir.path()), &mut store).unwrap();
+
+        assert_eq!(summary.processed() + summary.skipped() + summary.failed(), 0);
+        assert_eq!(inbox_uids(&store), vec![uid]);
+    }
+
+    // New emails notified through IDLE are filtered by sender and destination, then processed
+    #[test]
+    fn test_idle_update_processes_new_emails() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = config(attachments_dir.path());
+        let mut store = MemoryStore::default();
+        let old = store.deliver_old(email("trusted@example.com", "target@example.com", Some("old.epub")));
+        assert!(!store.idle(Duration::from_secs(1)).unwrap());
+
+        let book = store.deliver(email("trusted@example.com", "target@example.com", Some("new.epub")));
+        let stranger = store.deliver(email("stranger@example.com", "target@example.com", Some("spam.epub")));
+        assert!(store.idle(Duration::from_secs(1)).unwrap());
+
+        let summary = idle_update_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert!(attachments_dir.path().join("new.epub").is_file());
+        assert!(!attachments_dir.path().join("spam.epub").exists());
+        assert!(!attachments_dir.path().join("old.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![old, stranger]);
+        assert_eq!(store.mailbox("Trash")[0].uid, book);
+        assert!(!store.idle(Duration::from_secs(1)).unwrap());
+    }
+
+    // A dry run neither saves attachments nor changes the mailbox
+    #[test]
+    fn test_dry_run_leaves_mailbox_untouched() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = AppConfig {
+            dry_run: true,
+            ..config(attachments_dir.path())
+        };
+        let mut store = MemoryStore::default();
+        let uid = store.deliver(email("trusted@example.com", "target@example.com", Some("book.epub")));
+
+        let summary = idle_update_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert!(!attachments_dir.path().join("book.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![uid]);
+        assert!(store.email(uid).unwrap().flags.is_empty());
+    }
+
+    // Emails that fail to be processed are counted and left in the inbox, without stopping the other emails
+    #[test]
+    fn test_failed_emails_are_counted() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        // The directory of the attachments of this email is a file, so they cannot be saved
+        fs::write(attachments_dir.path().join("unwritable"), b"").unwrap();
+        let unwritable = store.deliver_old(
+            email("trusted@example.com", "target@example.com", Some("other.epub"))
+                .replace("Subject: Test", "Subject: Unwritable"),
+        );
+        let book = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.epub")));
+        let rules = Rules::parse(
+            "[[rules]]\nname = \"unwritable\"\nsubject = \"unwritable\"\ndirectory = \"unwritable\"",
+            Path::new("rules.toml"),
+        )
+        .unwrap();
+        let config = AppConfig {
+            compiled_rules: rules.into(),
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 1));
+        assert_eq!(inbox_uids(&store), vec![unwritable]);
+        assert_eq!(store.mailbox("Trash")[0].uid, book);
+    }
+
+    fn config_with_blocklist(attachments_dir: &Path, blocked_action: BlockedAction) -> AppConfig {
+        AppConfig {
+            whitelist: BTreeSet::from_iter(["@example.com".to_string()]),
+            blocklist: BTreeSet::from_iter(["noreply@example.com".to_string(), "@spam.example.org".to_string()]),
+            blocked_action,
+            junk_mailbox: "Junk".to_string(),
+            ..config(attachments_dir)
+        }
+    }
+
+    // The blocklist takes precedence over the whitelist, and blocked emails are left unread by default
+    #[test]
+    fn test_blocked_emails_are_left_unread() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = config_with_blocklist(attachments_dir.path(), BlockedAction::Leave);
+        let mut store = MemoryStore::default();
+        let book = store.deliver(email("alice@example.com", "target@example.com", Some("book.epub")));
+        let blocked = store.deliver(email("NoReply@example.com", "target@example.com", Some("ad.epub")));
+
+        let summary = idle_update_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert!(!attachments_dir.path().join("ad.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![blocked]);
+        assert!(store.email(blocked).unwrap().flags.is_empty());
+        assert_eq!(store.mailbox("Trash")[0].uid, book);
+    }
+
+    // Blocked emails are moved to the junk mailbox at startup, even when their sender is not whitelisted
+    #[test]
+    fn test_blocked_emails_are_moved_to_junk() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = config_with_blocklist(attachments_dir.path(), BlockedAction::Junk);
+        let mut store = MemoryStore::default();
+        let noreply = store.deliver_old(email("noreply@example.com", "target@example.com", Some("ad.epub")));
+        let spam = store.deliver_old(email("eve@spam.example.org", "target@example.com", None));
+        let elsewhere = store.deliver_old(email("eve@spam.example.org", "other@example.com", None));
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (0, 2, 0));
+        assert!(!attachments_dir.path().join("ad.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![elsewhere]);
+        let junk = store
+            .mailbox("Junk")
+            .iter()
+            .map(|email| email.uid)
+            .collect::<Vec<u32>>();
+        assert_eq!(junk, vec![noreply, spam]);
+    }
+
+    // Blocked emails are deleted, unless it is a dry run
+    #[test]
+    fn test_blocked_emails_are_deleted() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = config_with_blocklist(attachments_dir.path(), BlockedAction::Delete);
+        let mut store = MemoryStore::default();
+        let blocked = store.deliver_old(email("noreply@example.com", "target@example.com", None));
+
+        let dry_run_config = AppConfig {
+            dry_run: true,
+            ..config_with_blocklist(attachments_dir.path(), BlockedAction::Delete)
+        };
+        let summary = startup_email_search(&dry_run_config, &mut store).unwrap();
+        assert_eq!(summary.skipped(), 1);
+        assert_eq!(inbox_uids(&store), vec![blocked]);
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+        assert_eq!(summary.skipped(), 1);
+        assert!(store.email(blocked).is_none());
+    }
+
+    // Whitelisted emails are only processed when the trusted receiving server authenticated their sender
+    #[test]
+    fn test_startup_search_requires_sender_authentication() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let config = AppConfig {
+            authserv_id: Some("mx.example.com".to_string()),
+            require_authentication: RequiredAuthentication::Dmarc,
+            ..config(attachments_dir.path())
+        };
+        let mut store = MemoryStore::default();
+        let authenticated = store.deliver_old(format!(
+            "Authentication-Results: mx.example.com; dmarc=pass header.from=example.com\r\n{}",
+            email("trusted@example.com", "target@example.com", Some("book.epub"))
+        ));
+        let forged = store.deliver_old(format!(
+            "Authentication-Results: mx.example.com; dmarc=fail header.from=example.com\r\n{}",
+            email("trusted@example.com", "target@example.com", Some("forged.epub"))
+        ));
+        let untrusted = store.deliver_old(format!(
+            "Authentication-Results: mx.attacker.org; dmarc=pass header.from=example.com\r\n{}",
+            email("trusted@example.com", "target@example.com", Some("untrusted.epub"))
+        ));
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert!(attachments_dir.path().join("book.epub").is_file());
+        assert!(!attachments_dir.path().join("forged.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![forged, untrusted]);
+        assert_eq!(store.mailbox("Trash")[0].uid, authenticated);
+    }
+
+    // Emails sent to the target address in Bcc or to one of its sub-addresses are processed
+    #[test]
+    fn test_startup_search_matches_delivery_headers_and_sub_addresses() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let bcc = store.deliver_old(
+            email("trusted@example.com", "list@example.com", Some("bcc.epub"))
+                .replace("To: list@example.com\r\n", "Delivered-To: target@example.com\r\n"),
+        );
+        let tagged = store.deliver_old(email(
+            "trusted@example.com",
+            "target+books@example.com",
+            Some("tag.epub"),
+        ));
+        let other = store.deliver_old(email(
+            "trusted@example.com",
+            "other+target@example.com",
+            Some("other.epub"),
+        ));
+
+        let summary = startup_email_search(&config(attachments_dir.path()), &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 2);
+        assert!(attachments_dir.path().join("bcc.epub").is_file());
+        assert!(attachments_dir.path().join("tag.epub").is_file());
+        assert_eq!(inbox_uids(&store), vec![other]);
+        let trashed = store
+            .mailbox("Trash")
+            .iter()
+            .map(|email| email.uid)
+            .collect::<Vec<u32>>();
+        assert_eq!(trashed, vec![bcc, tagged]);
+    }
+
+    // Rules are evaluated in order to route, reject or post-process attachments, and the accepted file types apply
+    // when no rule matches
+    #[test]
+    fn test_startup_search_applies_rules() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let tagged = store.deliver_old(email(
+            "trusted@example.com",
+            "target+books@example.com",
+            Some("tagged.epub"),
+        ));
+        let comic = store.deliver_old(email("trusted@example.com", "target@example.com", Some("comic.cbz")));
+        let rejected = store.deliver_old(email(
+            "trusted@example.com",
+            "target+drafts@example.com",
+            Some("draft.epub"),
+        ));
+        let default = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.epub")));
+        let rules = Rules::parse(
+            r#"
+            [[rules]]
+            name = "drafts"
+            tag = "drafts"
+            action = "reject"
+
+            [[rules]]
+            name = "tagged"
+            tag = "books"
+            directory = "{tag}"
+            post_process = "leave"
+            continue = true
+
+            [[rules]]
+            name = "copies"
+            tag = "books"
+            directory = "copies"
+
+            [[rules]]
+            name = "comics"
+            extensions = ["cbz"]
+            directory = "comics/{sender_domain}"
+            post_process = { move = "Comics" }
+            "#,
+            Path::new("rules.toml"),
+        )
+        .unwrap();
+        let config = AppConfig {
+            compiled_rules: rules.into(),
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (3, 1, 0));
+        assert!(attachments_dir.path().join("books/tagged.epub").is_file());
+        assert!(attachments_dir.path().join("copies/tagged.epub").is_file());
+        assert!(attachments_dir.path().join("comics/example.com/comic.cbz").is_file());
+        assert!(!attachments_dir.path().join("draft.epub").exists());
+        assert!(attachments_dir.path().join("book.epub").is_file());
+        // The first accepting rule decides the post-processing
+        assert_eq!(inbox_uids(&store), vec![tagged, rejected]);
+        assert!(store.email(tagged).unwrap().flags.is_empty());
+        assert!(store.email(rejected).unwrap().flags.is_empty());
+        assert_eq!(store.mailbox("Comics")[0].uid, comic);
+        let trashed = store
+            .mailbox("Trash")
+            .iter()
+            .map(|email| email.uid)
+            .collect::<Vec<u32>>();
+        assert_eq!(trashed, vec![default]);
+    }
+
+    // Existing files are replaced by default, and kept when configured so
+    #[test]
+    fn test_startup_search_replaces_or_keeps_existing_files() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        fs::write(attachments_dir.path().join("book.epub"), b"old").unwrap();
+        let mut store = MemoryStore::default();
+        let _ = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.epub")));
+
+        let summary = startup_email_search(&config(attachments_dir.path()), &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert_eq!(fs::read(attachments_dir.path().join("book.epub")).unwrap(), b"book");
+        assert!(!attachments_dir.path().join("book-1.epub").exists());
+
+        let _ = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.epub")));
+        let config = AppConfig {
+            existing_files: ExistingFiles::Rename,
+            ..config(attachments_dir.path())
+        };
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert_eq!(fs::read(attachments_dir.path().join("book-1.epub")).unwrap(), b"book");
+    }
+
+    // Subject commands choose a shelf, overwrite existing files that would otherwise be renamed, and save the attachments
+    // of test sends while skipping their IMAP post-processing
+    #[test]
+    fn test_startup_search_applies_subject_commands() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        fs::write(attachments_dir.path().join("book.epub"), b"old").unwrap();
+        let mut store = MemoryStore::default();
+        let with_subject = |subject: &str, attachment_name: &str| {
+            email("trusted@example.com", "target@example.com", Some(attachment_name))
+                .replace("Subject: Test\r\n", &format!("Subject: {subject}\r\n"))
+        };
+        let shelved = store.deliver_old(with_subject("[shelf:SciFi] Book", "book.epub"));
+        let renamed = store.deliver_old(with_subject("[unknown] Book", "book.epub"));
+        let overwriting = store.deliver_old(with_subject("[overwrite] Book", "book.epub"));
+        let test_send = store.deliver_old(with_subject("[dry] Book", "test.epub"));
+        let config = AppConfig {
+            subject_commands: BTreeSet::from_iter([
+                SubjectCommand::Shelf,
+                SubjectCommand::Overwrite,
+                SubjectCommand::Dry,
+            ]),
+            existing_files: ExistingFiles::Rename,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 4);
+        assert_eq!(
+            fs::read(attachments_dir.path().join("scifi/book.epub")).unwrap(),
+            b"book"
+        );
+        assert_eq!(fs::read(attachments_dir.path().join("book-1.epub")).unwrap(), b"book");
+        assert_eq!(fs::read(attachments_dir.path().join("book.epub")).unwrap(), b"book");
+        assert_eq!(fs::read(attachments_dir.path().join("test.epub")).unwrap(), b"book");
+        assert_eq!(inbox_uids(&store), vec![test_send]);
+        assert!(store.email(test_send).unwrap().flags.is_empty());
+        let trashed = store
+            .mailbox("Trash")
+            .iter()
+            .map(|email| email.uid)
+            .collect::<Vec<u32>>();
+        assert_eq!(trashed, vec![shelved, renamed, overwriting]);
+    }
+
+    // Accepted MIME types apply to attachments, and attachments whose content does not match their declared type are
+    // rejected
+    #[test]
+    fn test_startup_search_checks_mime_types_and_content() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let with_part = |content_type: &str, attachment_name: &str, content: &str| {
+            email("trusted@example.com", "target@example.com", Some(attachment_name))
+                .replace("application/octet-stream", content_type)
+                .replace("Ym9vaw==", content)
+        };
+        let extensionless = store.deliver_old(with_part("application/epub+zip", "book", "Ym9vaw=="));
+        let pdf = store.deliver_old(with_part("application/pdf", "paper.pdf", "JVBERi0xLjcgYm9vaw=="));
+        let executable = store.deliver_old(with_part("application/pdf", "report.pdf", "TVqQACBub3QgYSBib29r"));
+        let config = AppConfig {
+            accepted_file_types: BTreeSet::from_iter(["pdf".to_string(), "application/epub+zip".to_string()]),
+            verify_content_type: true,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (2, 1, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("book.epub")).unwrap(), b"book");
+        assert!(attachments_dir.path().join("paper.pdf").is_file());
+        assert!(!attachments_dir.path().join("report.pdf").exists());
+        assert_eq!(inbox_uids(&store), vec![executable]);
+        let trashed = store
+            .mailbox("Trash")
+            .iter()
+            .map(|email| email.uid)
+            .collect::<Vec<u32>>();
+        assert_eq!(trashed, vec![extensionless, pdf]);
+    }
+
+    // Extensions missing from attachment names are inferred before the acceptance check
+    #[test]
+    fn test_startup_search_infers_missing_extensions() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let untitled = store.deliver_old(
+            email("trusted@example.com", "target@example.com", Some("Untitled"))
+                .replace("Ym9vaw==", "JVBERi0xLjcgYm9vaw=="),
+        );
+        let unknown = store.deliver_old(email("trusted@example.com", "target@example.com", Some("attachment")));
+        let config = AppConfig {
+            accepted_file_types: BTreeSet::from_iter(["pdf".to_string()]),
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(
+            fs::read(attachments_dir.path().join("untitled.pdf")).unwrap(),
+            b"%PDF-1.7 book"
+        );
+        assert_eq!(inbox_uids(&store), vec![unknown]);
+        assert_eq!(store.mailbox("Trash")[0].uid, untitled);
+    }
+
+    // Attachments without name are named after their Content-Location, the subject, or their sender, date and index
+    #[test]
+    fn test_startup_search_names_nameless_attachments() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let nameless = |subject: &str, extra_headers: &str| {
+            email("trusted@example.com", "target@example.com", Some(""))
+                .replace("; filename=\"\"", "")
+                .replace(
+                    "Content-Type: application/octet-stream",
+                    "Content-Type: application/epub+zip",
+                )
+                .replace(
+                    "Content-Transfer-Encoding: base64\r\n",
+                    &format!("Content-Transfer-Encoding: base64\r\n{extra_headers}"),
+                )
+                .replace("Subject: Test\r\n", subject)
+        };
+        let _ = store.deliver_old(nameless(
+            "Subject: Test\r\n",
+            "Content-Location: https://example.com/books/Dune.epub?download=1\r\n",
+        ));
+        let _ = store.deliver_old(nameless("Subject: Children of Dune / Part 1\r\n", ""));
+        let _ = store.deliver_old(nameless("Date: Sat, 20 Nov 2021 14:22:01 +0000\r\n", ""));
+        let config = AppConfig {
+            accepted_file_types: BTreeSet::from_iter(["epub".to_string()]),
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 3);
+        for filename in [
+            "dune.epub",
+            "children of dune _ part 1.epub",
+            "trusted@example.com-20211120t142201-1.epub",
+        ] {
+            assert!(attachments_dir.path().join(filename).is_file(), "{filename}");
+        }
+    }
+
+    // Wraps an email as a `message/rfc822` attachment of an email forwarding it
+    fn forwarded(inner: &str, boundary: &str) -> String {
+        format!(
             concat!(
-                r#"UNSEEN TO "target+special@example.com" (OR FROM "test+filter@example.com" FROM "user-name@example.com" FROM "user.name@example.com" FROM "user@123.123.123.123" FROM "user@[IPv6:2001:db8::1]" FROM "user@sub.example.com" FROM "user_name@example.com")"#
+                "From: trusted@example.com\r\n",
+                "To: target@example.com\r\n",
+                "Subject: Fwd: Test\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: multipart/mixed; boundary=\"{0}\"\r\n",
+                "\r\n",
+                "--{0}\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+                "See attached.\r\n",
+                "--{0}\r\n",
+                "Content-Type: message/rfc822\r\n",
+                "Content-Disposition: attachment\r\n",
+                "\r\n",
+                "{1}",
+                "--{0}--\r\n",
+            ),
+            boundary, inner
+        )
+    }
+
+    // Attachments of emails forwarded as attachments are saved, up to the maximum nesting depth
+    #[test]
+    fn test_startup_search_extracts_forwarded_attachments() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let once = store.deliver_old(forwarded(
+            &email("author@example.org", "trusted@example.com", Some("forwarded.epub")),
+            "outer",
+        ));
+        let twice = store.deliver_old(forwarded(
+            &forwarded(
+                &email("author@example.org", "trusted@example.com", Some("nested.epub")),
+                "middle",
+            ),
+            "outer",
+        ));
+        let config = AppConfig {
+            max_nesting_depth: 1,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(
+            fs::read(attachments_dir.path().join("forwarded.epub")).unwrap(),
+            b"book"
+        );
+        assert!(!attachments_dir.path().join("nested.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![twice]);
+        assert_eq!(store.mailbox("Trash")[0].uid, once);
+
+        let config = AppConfig {
+            max_nesting_depth: 2,
+            ..config
+        };
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert_eq!(fs::read(attachments_dir.path().join("nested.epub")).unwrap(), b"book");
+    }
+
+    // Inline parts with a filename are only saved when enabled, and parts that are also attachments are saved once
+    #[test]
+    fn test_inline_parts_are_saved_when_enabled() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let raw = concat!(
+            "From: trusted@example.com\r\n",
+            "To: target@example.com\r\n",
+            "Subject: Test\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=\"boundary\"\r\n",
+            "\r\n",
+            "--boundary\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Hello.\r\n",
+            "--boundary\r\n",
+            "Content-Type: text/html\r\n",
+            "Content-Disposition: inline; filename=\"story.html\"\r\n",
+            "\r\n",
+            "<p>Once upon a time.</p>\r\n",
+            "--boundary\r\n",
+            "Content-Type: application/pdf\r\n",
+            "Content-Disposition: inline; filename=\"paper.pdf\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "JVBERi0xLjcgYm9vaw==\r\n",
+            "--boundary--\r\n",
+        );
+        let _ = store.deliver_old(raw.to_string());
+        let config = AppConfig {
+            accepted_file_types: BTreeSet::from_iter(["html".to_string(), "pdf".to_string()]),
+            existing_files: ExistingFiles::Rename,
+            ..config(attachments_dir.path())
+        };
+        let saved_files = || {
+            let mut files = fs::read_dir(attachments_dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect::<Vec<String>>();
+            files.sort();
+            files
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert_eq!(saved_files(), vec!["paper.pdf"]);
+
+        let _ = store.deliver_old(raw.to_string());
+        let config = AppConfig {
+            inline_parts: InlineParts::Save,
+            ..config
+        };
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 1);
+        assert_eq!(saved_files(), vec!["paper-1.pdf", "paper.pdf", "story.html"]);
+    }
+
+    // Accepted files are extracted from archive attachments when enabled, while comic book archives are saved as is
+    #[test]
+    fn test_startup_search_extracts_archives() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in [("books/Dune.epub", b"dune"), ("readme.txt", b"read")] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        let archive = STANDARD.encode(writer.finish().unwrap().into_inner());
+        let with_archive = |attachment_name: &str| {
+            email("trusted@example.com", "target@example.com", Some(attachment_name)).replace("Ym9vaw==", &archive)
+        };
+        let zip = store.deliver_old(with_archive("Books.zip"));
+        let comic = store.deliver_old(with_archive("comic.cbz"));
+        let mut config = AppConfig {
+            accepted_file_types: BTreeSet::from_iter(["epub".to_string(), "cbz".to_string()]),
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert!(attachments_dir.path().join("comic.cbz").is_file());
+        assert_eq!(inbox_uids(&store), vec![zip]);
+
+        config.extract_archives = BTreeSet::from([ArchiveFormat::Zip]);
+        config.archive_max_entries = 10;
+        config.archive_max_size = 1024;
+        config.archive_max_ratio = 100;
+        config.archive_max_depth = 1;
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("dune.epub")).unwrap(), b"dune");
+        assert!(!attachments_dir.path().join("readme.txt").exists());
+        assert!(!attachments_dir.path().join("books.zip").exists());
+        assert!(inbox_uids(&store).is_empty());
+        let trashed = store
+            .mailbox("Trash")
+            .iter()
+            .map(|email| email.uid)
+            .collect::<Vec<u32>>();
+        assert_eq!(trashed, vec![comic, zip]);
+    }
+
+    // The attachments embedded in `winmail.dat` attachments from Outlook are checked and saved as attachments
+    #[test]
+    fn test_startup_search_decodes_tnef_attachments() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let mut tnef = 0x223E_9F78_u32.to_le_bytes().to_vec();
+        tnef.extend_from_slice(&[0, 0]);
+        for (title, content) in [(&b"Dune.epub\0"[..], &b"dune"[..]), (b"setup.exe\0", b"exe")] {
+            for (id, data) in [
+                (0x0006_9002_u32, &[0; 14][..]),
+                (0x0001_8010, title),
+                (0x0006_800F, content),
+            ] {
+                tnef.push(2);
+                tnef.extend_from_slice(&id.to_le_bytes());
+                tnef.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+                tnef.extend_from_slice(data);
+                tnef.extend_from_slice(&[0, 0]);
+            }
+        }
+        let uid = store.deliver_old(
+            email("trusted@example.com", "target@example.com", Some("winmail.dat"))
+                .replace("Ym9vaw==", &STANDARD.encode(&tnef)),
+        );
+        let config = AppConfig {
+            body_download: BodyDownload::Parts,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("dune.epub")).unwrap(), b"dune");
+        assert!(!attachments_dir.path().join("setup.exe").exists());
+        assert!(!attachments_dir.path().join("winmail.dat").exists());
+        assert_eq!(store.part_fetches, vec![(uid, vec![vec![2]])]);
+        assert!(inbox_uids(&store).is_empty());
+    }
+
+    // Files uuencoded in plain text bodies are saved as attachments when enabled
+    #[test]
+    fn test_startup_search_decodes_embedded_files() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let uid = store.deliver_old(email("trusted@example.com", "target@example.com", None).replace(
+            "Hello.\r\n",
+            "Here is the book:\r\n\r\nbegin 644 Dune.epub\r\n$9'5N90``\r\n`\r\nend\r\n",
+        ));
+        let mut config = AppConfig {
+            body_download: BodyDownload::Parts,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (0, 1, 0));
+        assert!(store.part_fetches.is_empty());
+
+        config.embedded_encodings = BTreeSet::from([EmbeddedEncoding::Uuencode]);
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("dune.epub")).unwrap(), b"dune");
+        assert_eq!(store.part_fetches, vec![(uid, vec![vec![1]])]);
+        assert!(inbox_uids(&store).is_empty());
+    }
+
+    // Emails over the message size limit are not downloaded, and the oversized action is applied to them
+    #[test]
+    fn test_startup_search_applies_message_size_limit() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let large_email = || {
+            email("trusted@example.com", "target@example.com", Some("large.epub"))
+                .replace("Hello.", &"Hello. ".repeat(500))
+        };
+        let small = store.deliver_old(email("trusted@example.com", "target@example.com", Some("small.epub")));
+        let large = store.deliver_old(large_email());
+        let mut config = AppConfig {
+            max_message_size: Some(2048),
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert!(attachments_dir.path().join("small.epub").is_file());
+        assert!(!attachments_dir.path().join("large.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![large]);
+        assert!(store.email(large).unwrap().flags.is_empty());
+        assert_eq!(store.mailbox("Trash")[0].uid, small);
+
+        config.oversized_action = OversizedAction::Flag;
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (0, 1, 0));
+        assert_eq!(store.email(large).unwrap().flags, BTreeSet::from([EmailFlag::Flagged]));
+
+        config.oversized_action = OversizedAction::Move;
+        config.oversized_mailbox = "Too large".to_string();
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (0, 1, 0));
+        assert!(inbox_uids(&store).is_empty());
+        assert_eq!(store.mailbox("Too large")[0].uid, large);
+    }
+
+    // Attachments over the attachment size limit are rejected, and senders are limited to a total size per day, counting
+    // only the emails saved outside of dry runs
+    #[test]
+    fn test_startup_search_applies_attachment_and_daily_size_limits() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let large_attachment = store.deliver_old(
+            email("trusted@example.com", "target@example.com", Some("large.epub"))
+                .replace("Ym9vaw==", &STANDARD.encode("a larger book")),
+        );
+        let first = store.deliver_old(email("trusted@example.com", "target@example.com", Some("first.epub")));
+        let second = store.deliver_old(email("trusted@example.com", "target@example.com", Some("second.epub")));
+        let daily_size = store.email(first).unwrap().raw.len() + store.email(second).unwrap().raw.len() - 1;
+        let mut config = AppConfig {
+            max_attachment_size: Some(4),
+            max_daily_sender_size: Some(daily_size as u64),
+            dry_run: true,
+            ..config(attachments_dir.path())
+        };
+
+        for _ in 0..2 {
+            let summary = startup_email_search(&config, &mut store).unwrap();
+
+            assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (2, 1, 0));
+        }
+        assert_eq!(inbox_uids(&store), vec![large_attachment, first, second]);
+
+        config.dry_run = false;
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 2, 0));
+        assert!(attachments_dir.path().join("first.epub").is_file());
+        assert!(!attachments_dir.path().join("large.epub").exists());
+        assert!(!attachments_dir.path().join("second.epub").exists());
+        assert_eq!(inbox_uids(&store), vec![large_attachment, second]);
+    }
+
+    // Emails are split into batches by count and total size, emails over the byte budget being alone in their batch
+    #[test]
+    fn test_body_batches() {
+        let emails = [(1, 10), (2, 10), (3, 10), (4, 100), (5, 10)];
+
+        assert_eq!(body_batches(&emails, 2, 1000), vec![vec![1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(
+            body_batches(&emails, 10, 25),
+            vec![vec![1, 2], vec![3], vec![4], vec![5]]
+        );
+        assert_eq!(
+            body_batches(&emails, 0, 1000),
+            vec![vec![1], vec![2], vec![3], vec![4], vec![5]]
+        );
+        assert!(body_batches(&[], 10, 1000).is_empty());
+    }
+
+    // Bodies are fetched in batches, each processed before fetching the next
+    #[test]
+    fn test_startup_search_fetches_bodies_in_batches() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let uids = ["one.epub", "two.epub", "three.epub"]
+            .map(|name| store.deliver_old(email("trusted@example.com", "target@example.com", Some(name))));
+        let config = AppConfig {
+            body_download: BodyDownload::Full,
+            fetch_batch_count: 2,
+            fetch_batch_bytes: 64 * 1024,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!(summary.processed(), 3);
+        assert_eq!(store.body_fetches, vec![vec![uids[0], uids[1]], vec![uids[2]]]);
+        assert!(inbox_uids(&store).is_empty());
+    }
+
+    // Only the parts that may be accepted are downloaded, and emails without such parts are not downloaded at all
+    #[test]
+    fn test_startup_search_downloads_candidate_parts_only() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let book = store.deliver_old(email("trusted@example.com", "target@example.com", Some("book.epub")));
+        let unsupported = store.deliver_old(email("trusted@example.com", "target@example.com", Some("setup.exe")));
+        let config = AppConfig {
+            body_download: BodyDownload::Parts,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 1, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("book.epub")).unwrap(), b"book");
+        assert!(store.body_fetches.is_empty());
+        assert_eq!(store.part_fetches, vec![(book, vec![vec![2]])]);
+        assert_eq!(inbox_uids(&store), vec![unsupported]);
+        assert_eq!(store.mailbox("Trash")[0].uid, book);
+    }
+
+    // Inline parts that may be accepted are downloaded along with the attachments when only parts are downloaded
+    #[test]
+    fn test_startup_search_downloads_inline_parts() {
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let uid = store.deliver_old(
+            concat!(
+                "From: trusted@example.com\r\n",
+                "To: target@example.com\r\n",
+                "Subject: Test\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: multipart/related; boundary=\"boundary\"\r\n",
+                "\r\n",
+                "--boundary\r\n",
+                "Content-Type: text/html\r\n",
+                "\r\n",
+                "<p>The cover.</p>\r\n",
+                "--boundary\r\n",
+                "Content-Type: application/epub+zip\r\n",
+                "Content-Disposition: inline; filename=\"book.epub\"\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "Ym9vaw==\r\n",
+                "--boundary--\r\n",
             )
+            .to_string(),
         );
+        let config = AppConfig {
+            body_download: BodyDownload::Parts,
+            inline_parts: InlineParts::Save,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("book.epub")).unwrap(), b"book");
+        assert!(store.body_fetches.is_empty());
+        assert_eq!(store.mailbox("Trash")[0].uid, uid);
+    }
+
+    // The text bodies of `multipart/alternative` emails are downloaded to find their links when only parts are
+    // downloaded
+    #[test]
+    fn test_startup_search_downloads_alternative_bodies_for_links() {
+        let server = start_server();
+        let attachments_dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::default();
+        let uid = store.deliver_old(format!(
+            concat!(
+                "From: trusted@example.com\r\n",
+                "To: target@example.com\r\n",
+                "Subject: Test\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: multipart/alternative; boundary=\"boundary\"\r\n",
+                "\r\n",
+                "--boundary\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+                "Dune: {server}/books/dune.epub\r\n",
+                "--boundary\r\n",
+                "Content-Type: text/html\r\n",
+                "\r\n",
+                "<a href=\"{server}/books/dune.epub\">Dune</a>\r\n",
+                "--boundary--\r\n",
+            ),
+            server = server
+        ));
+        let config = AppConfig {
+            body_download: BodyDownload::Parts,
+            link_hosts: BTreeSet::from_iter(["127.0.0.1".to_string()]),
+            link_max_size: 1000,
+            link_timeout: 5,
+            link_schemes: LinkSchemes::Any,
+            ..config(attachments_dir.path())
+        };
+
+        let summary = startup_email_search(&config, &mut store).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (1, 0, 0));
+        assert_eq!(fs::read(attachments_dir.path().join("dune.epub")).unwrap(), b"dune");
+        assert!(store.body_fetches.is_empty());
+        assert_eq!(store.mailbox("Trash")[0].uid, uid);
     }
 }
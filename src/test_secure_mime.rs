@@ -0,0 +1,195 @@
+mod secure_mime_tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::path::Path;
+
+    use secrecy::SecretString;
+
+    use super::super::{open_email, OpenedEmail, SecureMimeKeys, Signer};
+    use crate::local_sources::local_source_processing;
+    use crate::mail_parsing::parse_message;
+    use crate::models::{ExistingFiles, RequiredSignature};
+    use crate::openpgp::test_openpgp::openpgp_tests::{
+        DETACHED_SIGNATURE, INNER_EMAIL, PUBLIC_KEYS, RSA_SECRET_KEY, RSA_SIGNED_MESSAGE, X25519_MESSAGE,
+        X25519_SECRET_KEY,
+    };
+    use crate::smime::test_smime::smime_tests::{
+        CERTIFICATE, ENCRYPTED_PRIVATE_KEY, ENVELOPED_EMAIL, OPAQUE_SIGNED_EMAIL, SIGNED_EMAIL, SIGNED_ENCRYPTED_EMAIL,
+    };
+    use crate::AppConfig;
+
+    const HEADERS: &str = "From: Trusted Sender <trusted@example.com>\r\nTo: target@example.com\r\nSubject: A book\r\n";
+
+    // A PGP/MIME encrypted email, see <https://www.rfc-editor.org/rfc/rfc3156#section-4>
+    fn pgp_encrypted_email(message: &str) -> String {
+        format!(
+            concat!(
+                "{}Content-Type: multipart/encrypted; protocol=\"application/pgp-encrypted\"; boundary=\"pgp\"\r\n",
+                "\r\n",
+                "--pgp\r\n",
+                "Content-Type: application/pgp-encrypted\r\n",
+                "\r\n",
+                "Version: 1\r\n",
+                "--pgp\r\n",
+                "Content-Type: application/octet-stream; name=\"encrypted.asc\"\r\n",
+                "\r\n",
+                "{}",
+                "--pgp--\r\n",
+            ),
+            HEADERS, message
+        )
+    }
+
+    // A PGP/MIME signed email, whose signed part ends before the line break of the boundary
+    fn pgp_signed_email(signed: &str, signature: &str) -> String {
+        format!(
+            concat!(
+                "{}Content-Type: multipart/signed; micalg=pgp-sha256; protocol=\"application/pgp-signature\";\r\n",
+                " boundary=\"signed\"\r\n",
+                "\r\n",
+                "--signed\r\n",
+                "{}\r\n",
+                "--signed\r\n",
+                "Content-Type: application/pgp-signature; name=\"signature.asc\"\r\n",
+                "\r\n",
+                "{}",
+                "--signed--\r\n",
+            ),
+            HEADERS, signed, signature
+        )
+    }
+
+    // A configuration with the private keys and trusted signers of the test senders, written to the directory
+    fn config(dir: &Path) -> AppConfig {
+        let smime_key_file = dir.join("smime-key.pem");
+        let pgp_key_file = dir.join("pgp-keys.asc");
+        let signers_file = dir.join("signers.pem");
+        fs::write(&smime_key_file, ENCRYPTED_PRIVATE_KEY).unwrap();
+        fs::write(&pgp_key_file, [RSA_SECRET_KEY, X25519_SECRET_KEY].concat()).unwrap();
+        fs::write(&signers_file, [CERTIFICATE, PUBLIC_KEYS].concat()).unwrap();
+        AppConfig {
+            smime_key_file: Some(smime_key_file),
+            pgp_key_file: Some(pgp_key_file),
+            key_passphrase: Some(SecretString::from("secret")),
+            signers_file: Some(signers_file),
+            ..Default::default()
+        }
+    }
+
+    fn open(email: &str, keys: &SecureMimeKeys) -> OpenedEmail {
+        open_email(&parse_message(email.as_bytes()).unwrap(), keys).unwrap()
+    }
+
+    fn signer(kind: RequiredSignature, email: &str) -> Signer {
+        Signer {
+            kind,
+            email: email.to_string(),
+        }
+    }
+
+    // S/MIME emails are decrypted and their signatures checked, whether they are signed, encrypted or both
+    #[test]
+    fn test_open_smime_emails() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = SecureMimeKeys::load(&config(dir.path())).unwrap();
+
+        let signed = open(SIGNED_EMAIL, &keys);
+        assert_eq!(signed.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert_eq!(
+            signed.signers,
+            [signer(RequiredSignature::Smime, "trusted@example.com")]
+        );
+
+        let opaque_signed = open(OPAQUE_SIGNED_EMAIL, &keys);
+        assert_eq!(opaque_signed.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert_eq!(
+            opaque_signed.signers,
+            [signer(RequiredSignature::Smime, "trusted@example.com")]
+        );
+
+        let signed_encrypted = open(SIGNED_ENCRYPTED_EMAIL, &keys);
+        assert_eq!(signed_encrypted.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert_eq!(
+            signed_encrypted.signers,
+            [signer(RequiredSignature::Smime, "trusted@example.com")]
+        );
+
+        let encrypted = open(ENVELOPED_EMAIL, &keys);
+        assert_eq!(encrypted.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert!(encrypted.signers.is_empty());
+
+        let plain = open(INNER_EMAIL, &keys);
+        assert!(plain.content.is_none());
+    }
+
+    // PGP/MIME emails are decrypted and their signatures checked, whether they are signed, encrypted or both
+    #[test]
+    fn test_open_pgp_emails() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = SecureMimeKeys::load(&config(dir.path())).unwrap();
+
+        let signed_encrypted = open(&pgp_encrypted_email(RSA_SIGNED_MESSAGE), &keys);
+        assert_eq!(signed_encrypted.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert_eq!(
+            signed_encrypted.signers,
+            [signer(RequiredSignature::Pgp, "trusted@example.com")]
+        );
+
+        let encrypted = open(&pgp_encrypted_email(X25519_MESSAGE), &keys);
+        assert_eq!(encrypted.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert!(encrypted.signers.is_empty());
+
+        let signed = open(&pgp_signed_email(INNER_EMAIL, DETACHED_SIGNATURE), &keys);
+        assert_eq!(signed.content.as_deref(), Some(INNER_EMAIL.as_bytes()));
+        assert_eq!(signed.signers, [signer(RequiredSignature::Pgp, "modern@example.com")]);
+
+        let forged = open(
+            &pgp_signed_email(&INNER_EMAIL.replace("ZHVuZQ==", "dmlydXM="), DETACHED_SIGNATURE),
+            &keys,
+        );
+        assert!(forged.signers.is_empty());
+    }
+
+    // A required signature must be made by the sender, with the required kind of signature
+    #[test]
+    fn test_is_signed_by() {
+        let opened = OpenedEmail {
+            content: None,
+            signers: vec![signer(RequiredSignature::Pgp, "trusted@example.com")],
+        };
+
+        assert!(opened.is_signed_by("Trusted@Example.com", RequiredSignature::Pgp));
+        assert!(opened.is_signed_by("trusted@example.com", RequiredSignature::Any));
+        assert!(!opened.is_signed_by("trusted@example.com", RequiredSignature::Smime));
+        assert!(!opened.is_signed_by("other@example.com", RequiredSignature::Any));
+        assert!(OpenedEmail::default().is_signed_by("other@example.com", RequiredSignature::None));
+    }
+
+    // The attachments of encrypted emails are saved, unless a signature by their sender is required and missing
+    #[test]
+    fn test_processing_of_encrypted_emails() {
+        let dir = tempfile::tempdir().unwrap();
+        let emails_dir = dir.path().join("emails");
+        let attachments_dir = dir.path().join("attachments");
+        fs::create_dir_all(&emails_dir).unwrap();
+        fs::create_dir_all(&attachments_dir).unwrap();
+        fs::write(emails_dir.join("smime.eml"), [HEADERS, SIGNED_ENCRYPTED_EMAIL].concat()).unwrap();
+        fs::write(emails_dir.join("pgp.eml"), pgp_encrypted_email(RSA_SIGNED_MESSAGE)).unwrap();
+        fs::write(emails_dir.join("unsigned.eml"), pgp_encrypted_email(X25519_MESSAGE)).unwrap();
+        let config = AppConfig {
+            whitelist: BTreeSet::from_iter(["trusted@example.com".to_string()]),
+            target_address: Some("target@example.com".to_string()),
+            attachments_dir: attachments_dir.to_string_lossy().to_string(),
+            accepted_file_types: BTreeSet::from_iter(["epub".to_string()]),
+            require_signature: RequiredSignature::Any,
+            existing_files: ExistingFiles::Rename,
+            ..config(dir.path())
+        };
+
+        let summary = local_source_processing(&emails_dir, &config).unwrap();
+
+        assert_eq!((summary.processed(), summary.skipped(), summary.failed()), (2, 1, 0));
+        assert_eq!(fs::read(attachments_dir.join("dune.epub")).unwrap(), b"dune");
+        assert_eq!(fs::read(attachments_dir.join("dune-1.epub")).unwrap(), b"dune");
+    }
+}
@@ -0,0 +1,214 @@
+mod sender_authentication_tests {
+    use super::super::{is_sender_authenticated, parse_authentication_results, AuthenticationResult};
+    use crate::mail_parsing::{extract_descriptors, parse_message};
+    use crate::models::{MessageMetadata, RequiredAuthentication};
+    use crate::AppConfig;
+
+    fn config(require_authentication: RequiredAuthentication) -> AppConfig {
+        AppConfig {
+            authserv_id: Some("mx.example.net".to_string()),
+            require_authentication,
+            ..Default::default()
+        }
+    }
+
+    fn metadata<'a>(from: &'a str, authentication_results: Vec<&'a str>) -> MessageMetadata<'a> {
+        MessageMetadata::new(from, vec!["target@example.net"], None, None, authentication_results)
+    }
+
+    // Parse the authserv-id, method results and properties, ignoring comments, quotes and folding whitespace
+    #[test]
+    fn test_parse_authentication_results() {
+        let (authserv_id, results) = parse_authentication_results(concat!(
+            " mx.example.net 1; (checked by mx)\r\n",
+            "\tspf=pass smtp.mailfrom=example.com;\r\n",
+            " dkim/1 = pass (good signature) header.d=example.com header.s=\"sel;1\";\r\n",
+            " dmarc=FAIL reason=\"policy (strict)\" header.from=Example.com"
+        ))
+        .unwrap();
+
+        assert_eq!(authserv_id, "mx.example.net");
+        assert_eq!(
+            results,
+            vec![
+                AuthenticationResult {
+                    method: "spf".to_string(),
+                    result: "pass".to_string(),
+                    properties: vec![("smtp.mailfrom".to_string(), "example.com".to_string())],
+                },
+                AuthenticationResult {
+                    method: "dkim".to_string(),
+                    result: "pass".to_string(),
+                    properties: vec![
+                        ("header.d".to_string(), "example.com".to_string()),
+                        ("header.s".to_string(), "sel;1".to_string()),
+                    ],
+                },
+                AuthenticationResult {
+                    method: "dmarc".to_string(),
+                    result: "fail".to_string(),
+                    properties: vec![
+                        ("reason".to_string(), "policy (strict)".to_string()),
+                        ("header.from".to_string(), "Example.com".to_string()),
+                    ],
+                },
+            ]
+        );
+    }
+
+    // A header without results only has an authserv-id
+    #[test]
+    fn test_parse_authentication_results_none() {
+        let (authserv_id, results) = parse_authentication_results("mx.example.net; none").unwrap();
+
+        assert_eq!(authserv_id, "mx.example.net");
+        assert!(results.is_empty());
+        assert!(parse_authentication_results(" (only a comment) ").is_none());
+    }
+
+    // No authentication is required by default
+    #[test]
+    fn test_authentication_not_required() {
+        assert!(is_sender_authenticated(
+            &metadata("alice@example.com", vec![]),
+            &AppConfig::default()
+        ));
+    }
+
+    // DKIM signatures must pass and be aligned with the From domain
+    #[test]
+    fn test_dkim_authentication() {
+        let config = config(RequiredAuthentication::Dkim);
+
+        for (from, header) in [
+            ("alice@example.com", "mx.example.net; dkim=pass header.d=example.com"),
+            (
+                "alice@mail.example.com",
+                "mx.example.net; dkim=pass header.d=example.com",
+            ),
+            (
+                "alice@example.com",
+                "mx.example.net; dkim=pass header.i=@mail.example.com",
+            ),
+            (
+                "alice@example.com",
+                "MX.example.net; dkim=fail header.d=example.com; dkim=pass header.d=EXAMPLE.com",
+            ),
+        ] {
+            assert!(
+                is_sender_authenticated(&metadata(from, vec![header]), &config),
+                "{header}"
+            );
+        }
+        for (from, header) in [
+            ("alice@example.com", "mx.example.net; dkim=fail header.d=example.com"),
+            ("alice@example.com", "mx.example.net; dkim=pass header.d=attacker.org"),
+            ("alice@example.com", "mx.example.net; dkim=pass header.d=com"),
+            ("alice@example.com", "mx.example.net; dkim=pass header.d=badexample.com"),
+            (
+                "alice@example.com",
+                "mx.example.net; dmarc=pass header.from=example.com",
+            ),
+            ("alice@example.com", "mx.attacker.org; dkim=pass header.d=example.com"),
+        ] {
+            assert!(
+                !is_sender_authenticated(&metadata(from, vec![header]), &config),
+                "{header}"
+            );
+        }
+    }
+
+    // DMARC evaluations must pass for the From domain
+    #[test]
+    fn test_dmarc_authentication() {
+        let config = config(RequiredAuthentication::Dmarc);
+
+        assert!(is_sender_authenticated(
+            &metadata(
+                "alice@example.com",
+                vec!["mx.example.net; dmarc=pass header.from=example.com"]
+            ),
+            &config
+        ));
+        assert!(!is_sender_authenticated(
+            &metadata(
+                "alice@example.com",
+                vec!["mx.example.net; dmarc=pass header.from=attacker.org"]
+            ),
+            &config
+        ));
+        assert!(!is_sender_authenticated(
+            &metadata(
+                "alice@example.com",
+                vec!["mx.example.net; dkim=pass header.d=example.com"]
+            ),
+            &config
+        ));
+        assert!(!is_sender_authenticated(
+            &metadata("alice@example.com", vec![]),
+            &config
+        ));
+    }
+
+    // Either method is enough when any authentication is required, across several headers
+    #[test]
+    fn test_any_authentication() {
+        let config = config(RequiredAuthentication::Any);
+
+        assert!(is_sender_authenticated(
+            &metadata(
+                "alice@example.com",
+                vec![
+                    "mx.example.net; spf=pass",
+                    "mx.example.net; dkim=pass header.d=example.com"
+                ]
+            ),
+            &config
+        ));
+        assert!(is_sender_authenticated(
+            &metadata("alice@example.com", vec!["mx.example.net; dmarc=pass"]),
+            &config
+        ));
+        assert!(!is_sender_authenticated(
+            &metadata(
+                "alice@example.com",
+                vec!["mx.example.net; spf=pass smtp.mailfrom=example.com"]
+            ),
+            &config
+        ));
+    }
+
+    // Only the headers above the first `Received:` header are added by the receiving server, those below it being
+    // forged by the sender with the authserv-id of the server
+    #[test]
+    fn test_forged_authentication_results() {
+        let config = config(RequiredAuthentication::Dkim);
+        let authenticated = |raw: &str| {
+            let message = parse_message(raw.as_bytes()).unwrap();
+            is_sender_authenticated(&extract_descriptors(&message).unwrap(), &config)
+        };
+
+        assert!(authenticated(concat!(
+            "Authentication-Results: mx.example.net; dkim=pass header.d=example.com\r\n",
+            "Received: from mail.example.com by mx.example.net\r\n",
+            "From: alice@example.com\r\n",
+            "\r\n",
+            "Hello.\r\n",
+        )));
+        assert!(!authenticated(concat!(
+            "Authentication-Results: mx.example.net; dkim=fail header.d=example.com\r\n",
+            "Received: from mail.attacker.org by mx.example.net\r\n",
+            "Authentication-Results: mx.example.net; dkim=pass header.d=example.com\r\n",
+            "From: alice@example.com\r\n",
+            "\r\n",
+            "Hello.\r\n",
+        )));
+        assert!(!authenticated(concat!(
+            "Received: from mail.attacker.org by mx.example.net\r\n",
+            "Authentication-Results: mx.example.net; dkim=pass header.d=example.com\r\n",
+            "From: alice@example.com\r\n",
+            "\r\n",
+            "Hello.\r\n",
+        )));
+    }
+}
@@ -0,0 +1,127 @@
+mod tnef_tests {
+    use super::super::{decode_tnef, is_tnef, TNEF_SIGNATURE};
+    use crate::archives::ExtractedFile;
+    use crate::ImapAttachmentDaemonError;
+
+    const MESSAGE_CLASS: (u8, u32) = (1, 0x0007_8008);
+    const ATTACH_REND_DATA: (u8, u32) = (2, 0x0006_9002);
+    const ATTACH_TITLE: (u8, u32) = (2, 0x0001_8010);
+    const ATTACH_DATA: (u8, u32) = (2, 0x0006_800F);
+    const ATTACHMENT: (u8, u32) = (2, 0x0006_9005);
+
+    // A TNEF stream with the given attributes, each with its level, ID and data
+    fn tnef(attributes: &[((u8, u32), Vec<u8>)]) -> Vec<u8> {
+        let mut stream = TNEF_SIGNATURE.to_le_bytes().to_vec();
+        stream.extend_from_slice(&0x1234_u16.to_le_bytes());
+        for ((level, id), data) in attributes {
+            stream.push(*level);
+            stream.extend_from_slice(&id.to_le_bytes());
+            stream.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+            stream.extend_from_slice(data);
+            let checksum = data.iter().fold(0_u16, |sum, &byte| sum.wrapping_add(u16::from(byte)));
+            stream.extend_from_slice(&checksum.to_le_bytes());
+        }
+        stream
+    }
+
+    fn padded(mut value: Vec<u8>) -> Vec<u8> {
+        value.resize(value.len().div_ceil(4) * 4, 0);
+        value
+    }
+
+    // MAPI properties of an attachment: a named integer, a long and a Unicode long filename
+    fn mapi_properties(long_filename: &str) -> Vec<u8> {
+        let mut properties = 3_u32.to_le_bytes().to_vec();
+        // Named integer property, identified by its GUID and number
+        properties.extend_from_slice(&0x0003_u16.to_le_bytes());
+        properties.extend_from_slice(&0x8001_u16.to_le_bytes());
+        properties.extend_from_slice(&[0xAB; 16]);
+        properties.extend_from_slice(&0_u32.to_le_bytes());
+        properties.extend_from_slice(&0x8520_u32.to_le_bytes());
+        properties.extend_from_slice(&1_u32.to_le_bytes());
+        // PR_ATTACH_METHOD
+        properties.extend_from_slice(&0x0003_u16.to_le_bytes());
+        properties.extend_from_slice(&0x3705_u16.to_le_bytes());
+        properties.extend_from_slice(&1_u32.to_le_bytes());
+        // PR_ATTACH_LONG_FILENAME
+        let name = long_filename
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<u8>>();
+        properties.extend_from_slice(&0x001F_u16.to_le_bytes());
+        properties.extend_from_slice(&0x3707_u16.to_le_bytes());
+        properties.extend_from_slice(&1_u32.to_le_bytes());
+        properties.extend_from_slice(&u32::try_from(name.len()).unwrap().to_le_bytes());
+        properties.extend_from_slice(&padded(name));
+        properties
+    }
+
+    fn paths(files: &[ExtractedFile]) -> Vec<&str> {
+        files.iter().map(|file| file.path.as_str()).collect()
+    }
+
+    // TNEF attachments are recognised from their MIME type or their `winmail.dat` filename
+    #[test]
+    fn test_is_tnef() {
+        assert!(is_tnef(Some("winmail.dat"), Some("application/octet-stream")));
+        assert!(is_tnef(Some("WINMAIL.DAT"), None));
+        assert!(is_tnef(None, Some("application/ms-tnef")));
+        assert!(is_tnef(Some("message.dat"), Some("application/vnd.ms-tnef")));
+        assert!(!is_tnef(Some("book.epub"), Some("application/epub+zip")));
+        assert!(!is_tnef(None, None));
+    }
+
+    // Attachments are named after their long filename, or else after their 8.3 title
+    #[test]
+    fn test_decode_attachments() {
+        let stream = tnef(&[
+            (MESSAGE_CLASS, b"IPM.Microsoft Mail.Note\0".to_vec()),
+            (ATTACH_REND_DATA, vec![0; 14]),
+            (ATTACH_TITLE, b"THEDAR~1.EPU\0".to_vec()),
+            (ATTACH_DATA, b"dune".to_vec()),
+            (ATTACHMENT, mapi_properties("The Dark Forest.epub")),
+            (ATTACH_REND_DATA, vec![0; 14]),
+            (ATTACH_TITLE, b"notes.pdf\0".to_vec()),
+            (ATTACH_DATA, b"notes".to_vec()),
+        ]);
+
+        let files = decode_tnef(&stream).unwrap();
+
+        assert_eq!(paths(&files), ["The Dark Forest.epub", "notes.pdf"]);
+        assert_eq!(files[0].content, b"dune");
+        assert_eq!(files[1].content, b"notes");
+    }
+
+    // Attachments without data, such as embedded messages, are skipped and unnamed ones get a name
+    #[test]
+    fn test_decode_unnamed_and_empty_attachments() {
+        let stream = tnef(&[
+            (ATTACH_REND_DATA, vec![0; 14]),
+            (ATTACH_TITLE, b"Forwarded message\0".to_vec()),
+            (ATTACH_REND_DATA, vec![0; 14]),
+            (ATTACH_DATA, b"book".to_vec()),
+        ]);
+
+        let files = decode_tnef(&stream).unwrap();
+
+        assert_eq!(paths(&files), ["attachment-2"]);
+        assert!(decode_tnef(&tnef(&[(MESSAGE_CLASS, b"IPM.Note\0".to_vec())]))
+            .unwrap()
+            .is_empty());
+    }
+
+    // Streams without signature or cut short are invalid
+    #[test]
+    fn test_decode_invalid_streams() {
+        assert!(matches!(
+            decode_tnef(b"not a TNEF stream"),
+            Err(ImapAttachmentDaemonError::InvalidTnef(_))
+        ));
+        let stream = tnef(&[(ATTACH_REND_DATA, vec![0; 14]), (ATTACH_DATA, b"book".to_vec())]);
+        assert!(matches!(
+            decode_tnef(&stream[..stream.len() - 4]),
+            Err(ImapAttachmentDaemonError::InvalidTnef(_))
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::archives::ExtractedFile;
+use crate::ImapAttachmentDaemonError;
+
+// TNEF streams, sent by Outlook as `winmail.dat`, see
+// <https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxtnef>
+const TNEF_SIGNATURE: u32 = 0x223E_9F78;
+const ATTACHMENT_LEVEL: u8 = 2;
+// Attribute IDs, without their type in the high word
+const ATT_ATTACH_REND_DATA: u16 = 0x9002;
+const ATT_ATTACH_TITLE: u16 = 0x8010;
+const ATT_ATTACH_DATA: u16 = 0x800F;
+const ATT_ATTACHMENT: u16 = 0x9005;
+// MAPI properties of attachments
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+const PT_STRING8: u16 = 0x001E;
+const PT_UNICODE: u16 = 0x001F;
+
+pub(crate) fn is_tnef(filename: Option<&str>, mime_type: Option<&str>) -> bool {
+    mime_type.is_some_and(|mime_type| {
+        mime_type.eq_ignore_ascii_case("application/ms-tnef")
+            || mime_type.eq_ignore_ascii_case("application/vnd.ms-tnef")
+    }) || filename.is_some_and(|filename| filename.eq_ignore_ascii_case("winmail.dat"))
+}
+
+// Decodes the attachments embedded in a TNEF stream, named after their long filename when present, or else after their
+// title. Attachments without data, such as embedded messages, are skipped.
+pub(crate) fn decode_tnef(content: &[u8]) -> Result<Vec<ExtractedFile>, ImapAttachmentDaemonError> {
+    let mut reader = Reader { content, position: 0 };
+    if reader.u32()? != TNEF_SIGNATURE {
+        return Err(ImapAttachmentDaemonError::InvalidTnef("missing signature"));
+    }
+    let _legacy_key = reader.u16()?;
+    let mut attachments = Vec::new();
+    let mut current: Option<TnefAttachment> = None;
+    while reader.position < content.len() {
+        let level = reader.u8()?;
+        let attribute = reader.u32()?;
+        let length = usize::try_from(reader.u32()?).unwrap_or(usize::MAX);
+        let data = reader.bytes(length)?;
+        let _checksum = reader.u16()?;
+        if level != ATTACHMENT_LEVEL {
+            continue;
+        }
+        // The low word of attributes is their ID
+        match attribute.to_le_bytes() {
+            [low, high, ..] if u16::from_le_bytes([low, high]) == ATT_ATTACH_REND_DATA => {
+                attachments.extend(current.take());
+                current = Some(TnefAttachment::default());
+            }
+            [low, high, ..] => {
+                let attachment = current.get_or_insert_with(TnefAttachment::default);
+                match u16::from_le_bytes([low, high]) {
+                    ATT_ATTACH_TITLE => attachment.title = Some(string8(data)),
+                    ATT_ATTACH_DATA => attachment.data = Some(data.to_vec()),
+                    ATT_ATTACHMENT => attachment.long_filename = long_filename(data),
+                    _ => {}
+                }
+            }
+        }
+    }
+    attachments.extend(current);
+    Ok(attachments
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, attachment)| {
+            let content = attachment.data?;
+            let path = attachment
+                .long_filename
+                .or(attachment.title)
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| format!("attachment-{}", index + 1));
+            Some(ExtractedFile { path, content })
+        })
+        .collect())
+}
+
+#[derive(Debug, Default)]
+struct TnefAttachment {
+    title: Option<String>,
+    long_filename: Option<String>,
+    data: Option<Vec<u8>>,
+}
+
+// The long filename among the MAPI properties of an attachment. Properties are parsed up to the long filename, and
+// unknown property types end the parsing.
+fn long_filename(data: &[u8]) -> Option<String> {
+    let mut reader = Reader {
+        content: data,
+        position: 0,
+    };
+    let count = reader.u32().ok()?;
+    for _ in 0..count {
+        let property_type = reader.u16().ok()?;
+        let property_id = reader.u16().ok()?;
+        // Named properties carry their GUID and their name or number
+        if property_id >= 0x8000 {
+            let _guid = reader.bytes(16).ok()?;
+            if reader.u32().ok()? == 0 {
+                let _number = reader.u32().ok()?;
+            } else {
+                let length = usize::try_from(reader.u32().ok()?).ok()?;
+                let _name = reader.padded_bytes(length).ok()?;
+            }
+        }
+        let multi_valued = property_type & 0x1000 != 0;
+        let value_type = property_type & 0x0FFF;
+        let value_count = if multi_valued || is_variable_length(value_type) {
+            reader.u32().ok()?
+        } else {
+            1
+        };
+        for _ in 0..value_count {
+            let value = if is_variable_length(value_type) {
+                let length = usize::try_from(reader.u32().ok()?).ok()?;
+                reader.padded_bytes(length).ok()?
+            } else {
+                reader.bytes(fixed_length(value_type)?).ok()?
+            };
+            if property_id == PR_ATTACH_LONG_FILENAME && !multi_valued {
+                return match value_type {
+                    PT_STRING8 => Some(string8(value)),
+                    PT_UNICODE => Some(unicode(value)),
+                    _ => None,
+                };
+            }
+        }
+    }
+    None
+}
+
+fn is_variable_length(value_type: u16) -> bool {
+    // Strings, binaries and objects
+    matches!(value_type, PT_STRING8 | PT_UNICODE | 0x0102 | 0x000D)
+}
+
+fn fixed_length(value_type: u16) -> Option<usize> {
+    match value_type {
+        // Integers, floats, booleans and errors are padded to 4 bytes
+        0x0002 | 0x0003 | 0x0004 | 0x000A | 0x000B => Some(4),
+        // Doubles, currencies, dates and 64-bit integers
+        0x0005 | 0x0006 | 0x0007 | 0x0014 | 0x0040 => Some(8),
+        // GUIDs
+        0x0048 => Some(16),
+        _ => None,
+    }
+}
+
+// A null-terminated 8-bit string, decoded as Latin-1 since its code page is rarely known
+fn string8(data: &[u8]) -> String {
+    data.iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| char::from(byte))
+        .collect()
+}
+
+// A null-terminated UTF-16LE string
+fn unicode(data: &[u8]) -> String {
+    let units = data
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units)
+}
+
+struct Reader<'a> {
+    content: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], ImapAttachmentDaemonError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|&end| end <= self.content.len())
+            .ok_or(ImapAttachmentDaemonError::InvalidTnef("truncated stream"))?;
+        let bytes = &self.content[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    // Bytes padded to a multiple of 4, without their padding
+    fn padded_bytes(&mut self, length: usize) -> Result<&'a [u8], ImapAttachmentDaemonError> {
+        let bytes = self.bytes(length)?;
+        let _padding = self.bytes((4 - length % 4) % 4)?;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImapAttachmentDaemonError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImapAttachmentDaemonError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ImapAttachmentDaemonError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+#[path = "test_tnef.rs"]
+mod test_tnef;